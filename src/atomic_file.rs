@@ -0,0 +1,126 @@
+//! Crash-safe file writes.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const TMP_FILE_EXTENSION: &str = "tmp";
+const BACKUP_FILE_EXTENSION: &str = "bak";
+
+/// What to do with a file that already exists at the destination.
+#[derive(Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Overwrite an existing file instead of failing.
+    pub force: bool,
+    /// Keep the overwritten file as `<path>.bak`.
+    pub keep_backup: bool,
+}
+
+impl WriteOptions {
+    /// Replace any existing file without keeping a backup.
+    pub fn overwrite() -> Self {
+        WriteOptions {
+            force: true,
+            keep_backup: false,
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut with_suffix: OsString = path.as_os_str().to_owned();
+    with_suffix.push(format!(".{suffix}"));
+    PathBuf::from(with_suffix)
+}
+
+/// Where the file overwritten at `path` is kept.
+pub fn get_backup_path(path: &Path) -> PathBuf {
+    with_suffix(path, BACKUP_FILE_EXTENSION)
+}
+
+/// Writes to a temporary file in the same directory, syncs it and renames it over
+/// `path`, so a crash or a full disk never leaves a truncated file behind.
+pub fn write_atomically(path: &Path, bytes: &[u8], options: WriteOptions) -> Result<(), String> {
+    let exists: bool = path.exists();
+    if exists && !options.force {
+        return Err(format!(
+            "{} already exists, use --force to overwrite it",
+            path.display()
+        ));
+    }
+
+    let tmp_path: PathBuf =
+        with_suffix(path, &format!("{TMP_FILE_EXTENSION}{}", std::process::id()));
+    let write = || -> std::io::Result<()> {
+        let mut file: File = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        if exists && options.keep_backup {
+            fs::copy(path, get_backup_path(path))?;
+        }
+        fs::rename(&tmp_path, path)?;
+        sync_parent_dir(path)
+    };
+
+    write().map_err(|err| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("{}: {err}", path.display())
+    })
+}
+
+/// Makes the rename itself durable, as far as the platform allows it.
+fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) if cfg!(unix) => File::open(dir)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_atomic_file_write() {
+        let dir: PathBuf =
+            env::temp_dir().join(format!("contacts-cli-atomic-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path: PathBuf = dir.join("contacts.json");
+
+        write_atomically(&path, b"first", WriteOptions::default()).unwrap();
+        assert_eq!(b"first".to_vec(), fs::read(&path).unwrap());
+
+        let err: String = write_atomically(&path, b"second", WriteOptions::default())
+            .err()
+            .unwrap();
+        assert!(err.contains("--force"));
+        assert_eq!(b"first".to_vec(), fs::read(&path).unwrap());
+
+        write_atomically(
+            &path,
+            b"second",
+            WriteOptions {
+                force: true,
+                keep_backup: true,
+            },
+        )
+        .unwrap();
+        assert_eq!(b"second".to_vec(), fs::read(&path).unwrap());
+        assert_eq!(b"first".to_vec(), fs::read(get_backup_path(&path)).unwrap());
+
+        write_atomically(&path, b"third", WriteOptions::overwrite()).unwrap();
+        assert_eq!(b"third".to_vec(), fs::read(&path).unwrap());
+        assert_eq!(b"first".to_vec(), fs::read(get_backup_path(&path)).unwrap());
+
+        let missing_dir: PathBuf = dir.join("missing").join("contacts.json");
+        assert!(write_atomically(&missing_dir, b"x", WriteOptions::default()).is_err());
+        assert_eq!(2, fs::read_dir(&dir).unwrap().count());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
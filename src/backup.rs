@@ -0,0 +1,493 @@
+//! Point-in-time snapshots of every address book, with checksums and
+//! retention.
+
+use crate::atomic_file::{write_atomically, WriteOptions};
+use crate::codec::{decode_contacts, encode_contacts};
+use crate::crypto::{self, DecryptionProvider, Encryption};
+use crate::models::contact::Contact;
+use crate::models::sort_by::SortBy;
+use crate::repositories::contacts::ContactsRepository;
+use chrono::{DateTime, Datelike, NaiveDateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+const SNAPSHOT_EXTENSION: &str = ".json.gz";
+const CHECKSUM_EXTENSION: &str = ".sha256";
+const ID_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+const BOOKS_FIELD: &str = "books";
+
+/// The contents of a snapshot: each address book as an export file would hold
+/// it, so that its contacts are versioned like exports.
+#[derive(Serialize, Deserialize)]
+struct SnapshotFile {
+    books: BTreeMap<String, Value>,
+}
+
+/// A compressed copy of every contact, live and trashed, of every address book
+/// at a point in time.
+pub struct Snapshot {
+    /// The creation time as `YYYYMMDDTHHMMSSZ`, which sorts chronologically.
+    pub id: String,
+    /// Seconds since the Unix epoch.
+    pub created_at: i64,
+    /// The compressed file.
+    pub path: PathBuf,
+    /// The compressed size in bytes.
+    pub size: u64,
+}
+
+/// How many snapshots to keep: the newest one of each of the last `daily` days
+/// and of each of the last `weekly` weeks that have any.
+#[derive(Clone, Copy, Default)]
+pub struct Retention {
+    /// Days to keep one snapshot of.
+    pub daily: usize,
+    /// Weeks to keep one snapshot of.
+    pub weekly: usize,
+}
+
+fn get_snapshot_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{id}{SNAPSHOT_EXTENSION}"))
+}
+
+fn get_checksum_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{id}{SNAPSHOT_EXTENSION}{CHECKSUM_EXTENSION}"))
+}
+
+fn get_checksum(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn parse_id(id: &str) -> Option<i64> {
+    let timestamp: &str = id.split('-').next()?;
+    NaiveDateTime::parse_from_str(timestamp, ID_FORMAT)
+        .ok()
+        .map(|date_time| date_time.and_utc().timestamp())
+}
+
+/// Runs `f` in each of `books` in turn, switching back to the active book
+/// afterwards even if it failed.
+fn for_each_book(
+    contacts_service: &mut dyn ContactsRepository,
+    books: &[String],
+    mut f: impl FnMut(&mut dyn ContactsRepository, &str) -> Result<(), String>,
+) -> Result<(), String> {
+    let active_book: String = contacts_service.current_book();
+    let mut result: Result<(), String> = Ok(());
+    for book in books {
+        result = contacts_service
+            .use_book(book)
+            .and_then(|_| f(contacts_service, book));
+        if result.is_err() {
+            break;
+        }
+    }
+    contacts_service.use_book(&active_book)?;
+    result
+}
+
+fn encode_snapshot(books: BTreeMap<String, Vec<Contact>>) -> Result<Vec<u8>, String> {
+    let mut file: SnapshotFile = SnapshotFile {
+        books: BTreeMap::new(),
+    };
+    for (book, contacts) in books {
+        let value: Value =
+            serde_json::from_slice(&encode_contacts(&contacts)?).map_err(|err| err.to_string())?;
+        file.books.insert(book, value);
+    }
+    serde_json::to_vec(&file).map_err(|err| err.to_string())
+}
+
+/// The contacts of a snapshot by address book. Snapshots taken before they
+/// covered every book hold the contacts of a single one, read as those of
+/// `legacy_book`.
+fn decode_snapshot(
+    json: &[u8],
+    legacy_book: &str,
+) -> Result<BTreeMap<String, Vec<Contact>>, String> {
+    let value: Value = serde_json::from_slice(json).map_err(|err| err.to_string())?;
+    if value.get(BOOKS_FIELD).is_none() {
+        return Ok(BTreeMap::from([(
+            legacy_book.to_string(),
+            decode_contacts(json)?,
+        )]));
+    }
+
+    let file: SnapshotFile = serde_json::from_value(value).map_err(|err| err.to_string())?;
+    let mut books: BTreeMap<String, Vec<Contact>> = BTreeMap::new();
+    for (book, value) in file.books {
+        let json: Vec<u8> = serde_json::to_vec(&value).map_err(|err| err.to_string())?;
+        let contacts: Vec<Contact> =
+            decode_contacts(&json).map_err(|err| format!("Book {book}: {err}"))?;
+        books.insert(book, contacts);
+    }
+    Ok(books)
+}
+
+/// Writes a new snapshot of every address book of the repository to `dir`,
+/// named after the current time and encrypted after compression when an
+/// encryption is given.
+pub fn create(
+    contacts_service: &mut dyn ContactsRepository,
+    dir: &Path,
+    now: i64,
+    encryption: Option<&Encryption>,
+) -> Result<Snapshot, String> {
+    let mut books: BTreeMap<String, Vec<Contact>> = BTreeMap::new();
+    let book_names: Vec<String> = contacts_service.list_books()?;
+    for_each_book(contacts_service, &book_names, |contacts_service, book| {
+        let mut contacts: Vec<Contact> =
+            contacts_service.list(0, usize::MAX, SortBy::Name, false)?;
+        contacts.extend(contacts_service.list_trash()?);
+        books.insert(book.to_string(), contacts);
+        Ok(())
+    })?;
+    let json: Vec<u8> = encode_snapshot(books)?;
+
+    let mut encoder: GzEncoder<Vec<u8>> = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&json).map_err(|err| err.to_string())?;
+    let mut bytes: Vec<u8> = encoder.finish().map_err(|err| err.to_string())?;
+    if let Some(encryption) = encryption {
+        bytes = crypto::encrypt(&bytes, encryption)?;
+    }
+
+    fs::create_dir_all(dir).map_err(|err| format!("{}: {err}", dir.display()))?;
+    let timestamp: String = DateTime::<Utc>::from_timestamp(now, 0)
+        .ok_or(format!("Invalid timestamp {now}"))?
+        .format(ID_FORMAT)
+        .to_string();
+    let mut id: String = timestamp.clone();
+    let mut suffix: usize = 1;
+    while get_snapshot_path(dir, &id).exists() {
+        id = format!("{timestamp}-{suffix}");
+        suffix += 1;
+    }
+
+    let path: PathBuf = get_snapshot_path(dir, &id);
+    write_atomically(&path, &bytes, WriteOptions::default())?;
+    let file_name: String = format!("{id}{SNAPSHOT_EXTENSION}");
+    write_atomically(
+        &get_checksum_path(dir, &id),
+        format!("{}  {file_name}\n", get_checksum(&bytes)).as_bytes(),
+        WriteOptions::default(),
+    )?;
+
+    Ok(Snapshot {
+        id,
+        created_at: now,
+        path,
+        size: bytes.len() as u64,
+    })
+}
+
+/// The complete snapshots in `dir`, newest first. Snapshots without a checksum
+/// file were interrupted while being written and are left out.
+pub fn list(dir: &Path) -> Result<Vec<Snapshot>, String> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut snapshots: Vec<Snapshot> = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|err| format!("{}: {err}", dir.display()))? {
+        let entry: fs::DirEntry = entry.map_err(|err| err.to_string())?;
+        let file_name: String = entry.file_name().to_string_lossy().to_string();
+        let id: &str = match file_name.strip_suffix(SNAPSHOT_EXTENSION) {
+            Some(x) => x,
+            None => continue,
+        };
+        let created_at: i64 = match parse_id(id) {
+            Some(x) => x,
+            None => continue,
+        };
+        if !get_checksum_path(dir, id).exists() {
+            continue;
+        }
+        snapshots.push(Snapshot {
+            id: id.to_string(),
+            created_at,
+            path: entry.path(),
+            size: entry.metadata().map_err(|err| err.to_string())?.len(),
+        });
+    }
+
+    snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+    Ok(snapshots)
+}
+
+/// Reads the contacts of a snapshot back by address book, after checking it
+/// against its checksum, only asking how to decrypt it when it is encrypted.
+/// See `decode_snapshot` for `legacy_book`.
+pub fn load(
+    dir: &Path,
+    id: &str,
+    legacy_book: &str,
+    decryption: &mut DecryptionProvider,
+) -> Result<BTreeMap<String, Vec<Contact>>, String> {
+    let path: PathBuf = get_snapshot_path(dir, id);
+    let bytes: Vec<u8> = fs::read(&path).map_err(|_| format!("No backup with id {id}"))?;
+    let checksum: String = fs::read_to_string(get_checksum_path(dir, id))
+        .map_err(|_| format!("Missing checksum for backup {id}"))?;
+    let expected: &str = checksum.split_whitespace().next().unwrap_or_default();
+    if get_checksum(&bytes) != expected {
+        return Err(format!(
+            "Checksum mismatch for backup {id}, the file is corrupt"
+        ));
+    }
+
+    let bytes: Vec<u8> = match crypto::is_encrypted(&bytes) {
+        true => crypto::decrypt(&bytes, &decryption()?)?,
+        false => bytes,
+    };
+
+    let mut json: Vec<u8> = Vec::new();
+    GzDecoder::new(bytes.as_slice())
+        .read_to_end(&mut json)
+        .map_err(|err| err.to_string())?;
+    decode_snapshot(&json, legacy_book)
+}
+
+/// Replaces every address book of a snapshot with its contents, creating the
+/// books deleted since. Books created since are left as they are. The snapshot
+/// is verified and decoded before anything is written, and all the books are
+/// swapped in one step, so a failed restore leaves every book as it was.
+/// Returns the number of contacts restored.
+pub fn restore(
+    contacts_service: &mut dyn ContactsRepository,
+    dir: &Path,
+    id: &str,
+    decryption: &mut DecryptionProvider,
+) -> Result<usize, String> {
+    let books: BTreeMap<String, Vec<Contact>> =
+        load(dir, id, &contacts_service.current_book(), decryption)?;
+    let no_of_contacts: usize = books.values().map(Vec::len).sum();
+    contacts_service.replace_books(books)?;
+    Ok(no_of_contacts)
+}
+
+/// Picks the snapshots the retention policy does not keep. The newest snapshot
+/// is always kept.
+pub fn get_expired(snapshots: &[Snapshot], retention: Retention) -> Vec<usize> {
+    let mut days: HashSet<(i32, u32)> = HashSet::new();
+    let mut weeks: HashSet<(i32, u32)> = HashSet::new();
+    let mut expired: Vec<usize> = Vec::new();
+
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let date_time: DateTime<Utc> =
+            DateTime::<Utc>::from_timestamp(snapshot.created_at, 0).unwrap_or_default();
+        let day: (i32, u32) = (date_time.year(), date_time.ordinal());
+        let week: (i32, u32) = (date_time.iso_week().year(), date_time.iso_week().week());
+
+        let mut keep: bool = index == 0;
+        if !days.contains(&day) && days.len() < retention.daily {
+            days.insert(day);
+            keep = true;
+        }
+        if !weeks.contains(&week) && weeks.len() < retention.weekly {
+            weeks.insert(week);
+            keep = true;
+        }
+        if !keep {
+            expired.push(index);
+        }
+    }
+
+    expired
+}
+
+/// Deletes the snapshots the retention policy does not keep and returns them.
+pub fn prune(dir: &Path, retention: Retention) -> Result<Vec<Snapshot>, String> {
+    let snapshots: Vec<Snapshot> = list(dir)?;
+    let expired: Vec<usize> = get_expired(&snapshots, retention);
+
+    let mut pruned: Vec<Snapshot> = Vec::new();
+    for (index, snapshot) in snapshots.into_iter().enumerate() {
+        if !expired.contains(&index) {
+            continue;
+        }
+        fs::remove_file(get_checksum_path(dir, &snapshot.id)).map_err(|err| err.to_string())?;
+        fs::remove_file(&snapshot.path).map_err(|err| err.to_string())?;
+        pruned.push(snapshot);
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Decryption;
+    use crate::repositories::contacts::DEFAULT_BOOK;
+    use crate::repositories::inmemory_contacts::InMemoryContactsRepository;
+    use std::env;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    #[test]
+    fn test_backup_create_restore_prune() {
+        let dir: PathBuf =
+            env::temp_dir().join(format!("contacts-cli-backups-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        contacts_service
+            .add(
+                "Mihai".to_string(),
+                "491234567890".to_string(),
+                "mihai@mail.com".to_string(),
+            )
+            .unwrap();
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491111111111".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+        contacts_service.delete("Bogdan").unwrap();
+
+        // 2024-01-01 is a Monday
+        let monday: i64 = 1704067200;
+        let first: Snapshot = create(&mut contacts_service, &dir, monday, None).unwrap();
+        assert_eq!("20240101T000000Z", first.id);
+        let (secret_key, recipient) = crypto::generate_identity();
+        let encryption: Encryption = Encryption::Recipients(vec![recipient]);
+        let second: Snapshot =
+            create(&mut contacts_service, &dir, monday, Some(&encryption)).unwrap();
+        assert_eq!("20240101T000000Z-1", second.id);
+        assert!(crypto::is_encrypted(&fs::read(&second.path).unwrap()));
+        let mut no_decryption = || -> Result<Decryption, String> { Err("No key".to_string()) };
+        assert!(load(&dir, &second.id, DEFAULT_BOOK, &mut no_decryption).is_err());
+        let mut decryption = || -> Result<Decryption, String> {
+            Ok(Decryption::Identities(vec![Box::new(
+                crypto::parse_identity(&secret_key)?,
+            )]))
+        };
+        assert_eq!(
+            2,
+            load(&dir, &second.id, DEFAULT_BOOK, &mut decryption).unwrap()[DEFAULT_BOOK].len()
+        );
+
+        contacts_service
+            .update_email("Mihai", "mihai@work.com".to_string())
+            .unwrap();
+        contacts_service.restore("Bogdan").unwrap();
+        assert_eq!(
+            2,
+            restore(&mut contacts_service, &dir, &first.id, &mut no_decryption).unwrap()
+        );
+        assert_eq!(
+            "mihai@mail.com",
+            contacts_service.get("Mihai").unwrap().unwrap().email
+        );
+        assert!(contacts_service.get("Bogdan").unwrap().is_none());
+        assert_eq!(1, contacts_service.list_trash().unwrap().len());
+
+        fs::write(&second.path, b"corrupt").unwrap();
+        assert!(load(&dir, &second.id, DEFAULT_BOOK, &mut decryption)
+            .err()
+            .unwrap()
+            .contains("Checksum"));
+        assert!(load(&dir, "20000101T000000Z", DEFAULT_BOOK, &mut decryption).is_err());
+
+        for day in 1..10 {
+            create(&mut contacts_service, &dir, monday + day * DAY, None).unwrap();
+        }
+        assert_eq!(11, list(&dir).unwrap().len());
+        let pruned: Vec<Snapshot> = prune(
+            &dir,
+            Retention {
+                daily: 3,
+                weekly: 2,
+            },
+        )
+        .unwrap();
+        let kept: Vec<String> = list(&dir).unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(
+            vec![
+                "20240110T000000Z",
+                "20240109T000000Z",
+                "20240108T000000Z",
+                "20240107T000000Z",
+            ],
+            kept
+        );
+        assert_eq!(7, pruned.len());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_backup_every_book() {
+        let dir: PathBuf =
+            env::temp_dir().join(format!("contacts-cli-backup-books-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        contacts_service
+            .add(
+                "Mihai".to_string(),
+                "491234567890".to_string(),
+                "mihai@mail.com".to_string(),
+            )
+            .unwrap();
+        contacts_service.create_book("work").unwrap();
+        contacts_service.use_book("work").unwrap();
+        contacts_service
+            .add(
+                "Ana".to_string(),
+                "491111111111".to_string(),
+                "ana@work.com".to_string(),
+            )
+            .unwrap();
+
+        let snapshot: Snapshot = create(&mut contacts_service, &dir, 1704067200, None).unwrap();
+        assert_eq!("work", contacts_service.current_book());
+        let mut no_decryption = || -> Result<Decryption, String> { Err("No key".to_string()) };
+        let books: BTreeMap<String, Vec<Contact>> =
+            load(&dir, &snapshot.id, "work", &mut no_decryption).unwrap();
+        assert_eq!(
+            vec![DEFAULT_BOOK, "work"],
+            books.keys().map(String::as_str).collect::<Vec<&str>>()
+        );
+
+        contacts_service.use_book(DEFAULT_BOOK).unwrap();
+        contacts_service.delete("Mihai").unwrap();
+        contacts_service.delete_book("work").unwrap();
+        assert_eq!(
+            2,
+            restore(
+                &mut contacts_service,
+                &dir,
+                &snapshot.id,
+                &mut no_decryption
+            )
+            .unwrap()
+        );
+        assert_eq!(DEFAULT_BOOK, contacts_service.current_book());
+        assert!(contacts_service.get("Mihai").unwrap().is_some());
+        assert_eq!(
+            vec![DEFAULT_BOOK, "work"],
+            contacts_service.list_books().unwrap()
+        );
+        contacts_service.use_book("work").unwrap();
+        assert_eq!(
+            "ana@work.com",
+            contacts_service.get("Ana").unwrap().unwrap().email
+        );
+
+        // Snapshots of a single book, as written before, restore into the
+        // active one.
+        let legacy: Vec<u8> = encode_contacts(&books[DEFAULT_BOOK]).unwrap();
+        let legacy_books: BTreeMap<String, Vec<Contact>> =
+            decode_snapshot(&legacy, "work").unwrap();
+        assert_eq!(vec!["work"], legacy_books.keys().collect::<Vec<&String>>());
+        assert_eq!("Mihai", legacy_books["work"][0].name);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
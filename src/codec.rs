@@ -0,0 +1,190 @@
+//! The file format of exports and imports: a JSON object with the schema
+//! version and the contacts, optionally encrypted with age. Files of version
+//! 1 hold a bare array of contacts and are upgraded when read.
+
+use crate::atomic_file::{write_atomically, WriteOptions};
+use crate::crypto::{self, DecryptionProvider, Encryption};
+use crate::models::contact::Contact;
+use crate::repositories::contacts::{get_valid_email, get_valid_name, get_valid_phone_no};
+use crate::repositories::migrations::{get_latest_version, migrate, Migration};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+const SCHEMA_VERSION_FIELD: &str = "schema_version";
+
+/// The layouts of export files, applied to the parsed JSON before reading the
+/// contacts out of it.
+pub(crate) const EXPORT_MIGRATIONS: &[Migration<Value>] = &[
+    Migration {
+        version: 1,
+        description: "Write the contacts as a bare JSON array",
+        apply: |_| Ok(()),
+    },
+    Migration {
+        version: 2,
+        description: "Wrap the contacts in an object with the schema version",
+        apply: |value| {
+            if value.is_array() {
+                *value = serde_json::json!({ "contacts": value.take() });
+            }
+            Ok(())
+        },
+    },
+];
+
+#[derive(Serialize, Deserialize)]
+struct ExportFile {
+    schema_version: u32,
+    contacts: Vec<Contact>,
+}
+
+/// The schema version of a parsed export file.
+fn get_export_version(value: &Value) -> Result<u32, String> {
+    match value {
+        Value::Array(_) => Ok(1),
+        Value::Object(object) => object
+            .get(SCHEMA_VERSION_FIELD)
+            .and_then(Value::as_u64)
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or(format!("No valid {SCHEMA_VERSION_FIELD} in the file")),
+        _ => Err("Not a contacts file".to_string()),
+    }
+}
+
+/// Serializes contacts in the latest export layout.
+pub fn encode_contacts(contacts: &[Contact]) -> Result<Vec<u8>, String> {
+    let file: ExportFile = ExportFile {
+        schema_version: get_latest_version(EXPORT_MIGRATIONS),
+        contacts: contacts.to_vec(),
+    };
+    serde_json::to_vec(&file).map_err(|err| err.to_string())
+}
+
+/// Deserializes contacts written by `encode_contacts` in this or any earlier
+/// release, failing on files of a later one.
+pub fn decode_contacts(json: &[u8]) -> Result<Vec<Contact>, String> {
+    let mut value: Value = serde_json::from_slice(json).map_err(|err| err.to_string())?;
+    let version: u32 = get_export_version(&value)?;
+    migrate(EXPORT_MIGRATIONS, &mut value, version, |value, version| {
+        value[SCHEMA_VERSION_FIELD] = Value::from(version);
+        Ok(())
+    })?;
+    let file: ExportFile = serde_json::from_value(value).map_err(|err| err.to_string())?;
+    Ok(file.contacts)
+}
+
+/// Writes contacts with `encode_contacts`, encrypted when an encryption is given.
+pub fn write_contacts_file(
+    path: &str,
+    contacts: &[Contact],
+    encryption: Option<&Encryption>,
+    options: WriteOptions,
+) -> Result<(), String> {
+    let json: Vec<u8> = encode_contacts(contacts)?;
+    let bytes: Vec<u8> = match encryption {
+        Some(encryption) => crypto::encrypt(&json, encryption)?,
+        None => json,
+    };
+    write_atomically(Path::new(path), &bytes, options)
+}
+
+/// Reads contacts written by `write_contacts_file`, only asking how to decrypt
+/// them when the file is encrypted.
+pub fn read_contacts_file(
+    path: &str,
+    decryption: &mut DecryptionProvider,
+) -> Result<Vec<Contact>, String> {
+    let mut bytes: Vec<u8> = fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+    if crypto::is_encrypted(&bytes) {
+        bytes = crypto::decrypt(&bytes, &decryption()?).map_err(|err| format!("{path}: {err}"))?;
+    }
+    decode_contacts(&bytes).map_err(|err| format!("{path}: {err}"))
+}
+
+/// Reads contacts with `read_contacts_file`, setting aside the ones the
+/// validators reject, such as those exported before a validator got stricter.
+/// Returns the valid contacts and why each of the others was rejected.
+pub fn read_valid_contacts_file(
+    path: &str,
+    decryption: &mut DecryptionProvider,
+) -> Result<(Vec<Contact>, Vec<String>), String> {
+    let contacts: Vec<Contact> = read_contacts_file(path, decryption)?;
+    let mut valid: Vec<Contact> = Vec::new();
+    let mut rejected: Vec<String> = Vec::new();
+    for contact in contacts {
+        match get_valid_name(&contact.name)
+            .and_then(|_| get_valid_email(&contact.email))
+            .and_then(|_| get_valid_phone_no(&contact.phone_no.to_string()))
+        {
+            Ok(_) => valid.push(contact),
+            Err(err) => rejected.push(format!("{}: {err}", contact.name)),
+        }
+    }
+    Ok((valid, rejected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Decryption;
+    use crate::repositories::contacts::ContactsRepository;
+    use crate::repositories::inmemory_contacts::InMemoryContactsRepository;
+    use std::env;
+
+    #[test]
+    fn test_decode_contacts() {
+        let contact: Contact = Contact {
+            name: "Bogdan".to_string(),
+            phone_no: 491234567890,
+            email: "bogdan@mail.com".to_string(),
+            created_at: 1,
+            updated_at: 2,
+            deleted_at: None,
+            links: Vec::new(),
+        };
+        let json: Vec<u8> = encode_contacts(std::slice::from_ref(&contact)).unwrap();
+        let value: Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(Some(2), value[SCHEMA_VERSION_FIELD].as_u64());
+        assert_eq!(vec![contact.name.clone()], names(decode_contacts(&json)));
+
+        let old: Vec<u8> = serde_json::to_vec(std::slice::from_ref(&contact)).unwrap();
+        assert_eq!(vec![contact.name], names(decode_contacts(&old)));
+
+        assert!(decode_contacts(br#"{"schema_version":3,"contacts":[]}"#).is_err());
+        assert!(decode_contacts(br#"{"contacts":[]}"#).is_err());
+        assert!(decode_contacts(b"42").is_err());
+    }
+
+    #[test]
+    fn test_import_old_export() {
+        let path: String = env::temp_dir()
+            .join(format!(
+                "contacts-cli-old-export-{}.json",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .to_string();
+        // The email validator used to accept anything after a valid address.
+        let old: &str = r#"[{"name":"Bogdan","phone_no":491234567890,"email":"bogdan@mail.com"},{"name":"Mihai","phone_no":491234567890,"email":"mihai@mail.com "}]"#;
+        fs::write(&path, old).unwrap();
+
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        let mut no_decryption = || -> Result<Decryption, String> { Err("No key".to_string()) };
+        let rejected: Vec<String> = contacts_service
+            .import_from_json(path.clone(), &mut no_decryption)
+            .unwrap();
+        assert_eq!(vec!["Mihai: Email is not valid"], rejected);
+        assert_eq!(vec!["Bogdan"], contacts_service.list_names().unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn names(contacts: Result<Vec<Contact>, String>) -> Vec<String> {
+        contacts
+            .unwrap()
+            .into_iter()
+            .map(|contact| contact.name)
+            .collect()
+    }
+}
@@ -0,0 +1,158 @@
+use clap::Command;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+
+/// Tab completion for the REPL: subcommand names taken from the clap command
+/// tree and contact names for the subcommands that take a `NAME`.
+pub struct ContactsHelper {
+    subcommands: Vec<(String, Vec<String>)>,
+    subcommands_with_name: Vec<String>,
+    contact_names: Vec<String>,
+}
+
+impl ContactsHelper {
+    pub fn new(cli: &Command) -> Self {
+        let mut subcommands: Vec<(String, Vec<String>)> = Vec::new();
+        let mut subcommands_with_name: Vec<String> = Vec::new();
+
+        for subcommand in cli.get_subcommands() {
+            let nested: Vec<String> = subcommand
+                .get_subcommands()
+                .map(|c| c.get_name().to_string())
+                .collect();
+            for name in std::iter::once(subcommand.get_name()).chain(subcommand.get_all_aliases()) {
+                subcommands.push((name.to_string(), nested.clone()));
+            }
+            if subcommand
+                .get_positionals()
+                .any(|arg| arg.get_id() == "NAME")
+            {
+                subcommands_with_name.push(subcommand.get_name().to_string());
+            }
+        }
+
+        ContactsHelper {
+            subcommands,
+            subcommands_with_name,
+            contact_names: Vec::new(),
+        }
+    }
+
+    pub fn set_contact_names(&mut self, contact_names: Vec<String>) {
+        self.contact_names = contact_names;
+    }
+
+    fn get_candidates(&self, previous_words: &[&str]) -> Vec<String> {
+        match previous_words {
+            [] => self
+                .subcommands
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect(),
+            [subcommand] if self.subcommands_with_name.iter().any(|x| x == subcommand) => {
+                self.contact_names.clone()
+            }
+            [subcommand] => self
+                .subcommands
+                .iter()
+                .find(|(name, _)| name == subcommand)
+                .map(|(_, nested)| nested.clone())
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// The subcommands whose first argument is the name of a contact.
+pub fn get_subcommands_with_name(cli: &Command) -> Vec<String> {
+    cli.get_subcommands()
+        .filter(|subcommand| {
+            subcommand
+                .get_positionals()
+                .next()
+                .is_some_and(|arg| arg.get_id() == "NAME")
+        })
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect()
+}
+
+impl Completer for ContactsHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line: &str = &line[..pos];
+        let start: usize = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let previous_words: Vec<&str> = line[..start].split_whitespace().collect();
+        let word: &str = line[start..].trim_start_matches(['"', '\'']);
+
+        let candidates: Vec<Pair> = self
+            .get_candidates(&previous_words)
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .map(|candidate| Pair {
+                replacement: shlex::try_quote(&candidate)
+                    .map(|quoted| quoted.into_owned())
+                    .unwrap_or_else(|_| candidate.clone()),
+                display: candidate,
+            })
+            .collect();
+
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ContactsHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ContactsHelper {}
+
+impl Validator for ContactsHelper {}
+
+impl Helper for ContactsHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::history::DefaultHistory;
+
+    fn complete(helper: &ContactsHelper, line: &str) -> (usize, Vec<String>) {
+        let history: DefaultHistory = DefaultHistory::new();
+        let (start, candidates) = helper
+            .complete(line, line.len(), &Context::new(&history))
+            .unwrap();
+        (
+            start,
+            candidates.into_iter().map(|c| c.replacement).collect(),
+        )
+    }
+
+    #[test]
+    fn test_completion_subcommands_and_names() {
+        let cli: Command = Command::new("test")
+            .subcommand(Command::new("view").arg(clap::arg!(<NAME> "The name")))
+            .subcommand(Command::new("vanish"))
+            .subcommand(Command::new("trash").subcommand(Command::new("list")));
+        let mut helper: ContactsHelper = ContactsHelper::new(&cli);
+        helper.set_contact_names(vec!["Bogdan".to_string(), "Bob Smith".to_string()]);
+
+        assert_eq!(
+            (0, vec!["view".to_string(), "vanish".to_string()]),
+            complete(&helper, "v")
+        );
+        assert_eq!(
+            (5, vec!["Bogdan".to_string(), "'Bob Smith'".to_string()]),
+            complete(&helper, "view Bo")
+        );
+        assert_eq!((6, vec!["list".to_string()]), complete(&helper, "trash l"));
+        assert!(complete(&helper, "vanish B").1.is_empty());
+    }
+}
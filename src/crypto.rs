@@ -0,0 +1,140 @@
+//! Encryption at rest with age, by passphrase or by x25519 keys.
+
+use age::secrecy::{ExposeSecret, SecretString};
+use age::x25519;
+use age::{Decryptor, Encryptor, Identity, IdentityFile, Recipient};
+use std::io::{Read, Write};
+
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1";
+
+/// Who can read an encrypted file: anyone knowing the passphrase, or the holders
+/// of the identities matching the age public keys.
+#[derive(Clone)]
+pub enum Encryption {
+    /// Encrypt with a key derived from the passphrase.
+    Passphrase(SecretString),
+    /// Encrypt to these public keys.
+    Recipients(Vec<x25519::Recipient>),
+}
+
+/// How to open an encrypted file.
+pub enum Decryption {
+    /// Decrypt with the passphrase the file was encrypted with.
+    Passphrase(SecretString),
+    /// Decrypt with any of these private keys.
+    Identities(Vec<Box<dyn Identity>>),
+}
+
+/// Asks how to decrypt a file, e.g. by prompting for the passphrase, once it
+/// turns out to be encrypted.
+pub type DecryptionProvider<'a> = dyn FnMut() -> Result<Decryption, String> + 'a;
+
+/// A `DecryptionProvider` that async code can hold across await points.
+pub type AsyncDecryptionProvider<'a> = dyn FnMut() -> Result<Decryption, String> + Send + 'a;
+
+/// Whether `bytes` start with the age header.
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(AGE_HEADER)
+}
+
+/// Encrypts `plaintext` in the binary age format.
+pub fn encrypt(plaintext: &[u8], encryption: &Encryption) -> Result<Vec<u8>, String> {
+    let encryptor: Encryptor = match encryption {
+        Encryption::Passphrase(passphrase) => Encryptor::with_user_passphrase(passphrase.clone()),
+        Encryption::Recipients(recipients) => {
+            Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn Recipient))
+                .map_err(|err| err.to_string())?
+        }
+    };
+
+    let mut ciphertext: Vec<u8> = Vec::new();
+    let mut writer = encryptor
+        .wrap_output(&mut ciphertext)
+        .map_err(|err| err.to_string())?;
+    writer.write_all(plaintext).map_err(|err| err.to_string())?;
+    writer.finish().map_err(|err| err.to_string())?;
+    Ok(ciphertext)
+}
+
+/// Decrypts an age file, failing on a wrong passphrase or key.
+pub fn decrypt(ciphertext: &[u8], decryption: &Decryption) -> Result<Vec<u8>, String> {
+    let decryptor: Decryptor<&[u8]> =
+        Decryptor::new_buffered(ciphertext).map_err(|err| err.to_string())?;
+    let mut reader = match decryption {
+        Decryption::Passphrase(passphrase) => {
+            let identity: age::scrypt::Identity = age::scrypt::Identity::new(passphrase.clone());
+            decryptor.decrypt(std::iter::once(&identity as &dyn Identity))
+        }
+        Decryption::Identities(identities) => {
+            decryptor.decrypt(identities.iter().map(|i| i.as_ref()))
+        }
+    }
+    .map_err(|err| err.to_string())?;
+
+    let mut plaintext: Vec<u8> = Vec::new();
+    reader
+        .read_to_end(&mut plaintext)
+        .map_err(|err| err.to_string())?;
+    Ok(plaintext)
+}
+
+/// Parses comma or whitespace separated age public keys, e.g. `age1...`.
+pub fn parse_recipients(text: &str) -> Result<Vec<x25519::Recipient>, String> {
+    text.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|key| !key.is_empty())
+        .map(|key| {
+            key.parse::<x25519::Recipient>()
+                .map_err(|err| format!("Invalid age recipient {key}: {err}"))
+        })
+        .collect()
+}
+
+/// Reads the secret keys of an age identity file, as written by `age-keygen`.
+pub fn read_identities(path: &str) -> Result<Vec<Box<dyn Identity>>, String> {
+    IdentityFile::from_file(path.to_string())
+        .map_err(|err| format!("{path}: {err}"))?
+        .into_identities()
+        .map_err(|err| format!("{path}: {err}"))
+}
+
+/// A new random identity as its secret key string and its public key.
+pub fn generate_identity() -> (SecretString, x25519::Recipient) {
+    let identity: x25519::Identity = x25519::Identity::generate();
+    (identity.to_string(), identity.to_public())
+}
+
+/// Parses an `AGE-SECRET-KEY-1...` private key.
+pub fn parse_identity(secret_key: &SecretString) -> Result<x25519::Identity, String> {
+    secret_key
+        .expose_secret()
+        .trim()
+        .parse::<x25519::Identity>()
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crypto_encrypt_decrypt() {
+        let (secret_key, recipient) = generate_identity();
+        let encryption: Encryption =
+            Encryption::Recipients(parse_recipients(&format!("{recipient}, {recipient}")).unwrap());
+
+        let ciphertext: Vec<u8> = encrypt(b"[]", &encryption).unwrap();
+        assert!(is_encrypted(&ciphertext));
+        assert!(!is_encrypted(b"[]"));
+
+        let identity: x25519::Identity = parse_identity(&secret_key).unwrap();
+        let decryption: Decryption = Decryption::Identities(vec![Box::new(identity)]);
+        assert_eq!(b"[]".to_vec(), decrypt(&ciphertext, &decryption).unwrap());
+
+        let (_, other) = generate_identity();
+        let other_identity: Decryption = Decryption::Identities(vec![Box::new(
+            parse_identity(&generate_identity().0).unwrap(),
+        )]);
+        assert!(decrypt(&ciphertext, &other_identity).is_err());
+        assert!(parse_recipients(&format!("{other} nope")).is_err());
+    }
+}
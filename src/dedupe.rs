@@ -0,0 +1,364 @@
+//! Finding contacts that likely describe the same person and merging them.
+
+use crate::models::contact::Contact;
+use crate::repositories::contacts::ContactsRepository;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// How similar two names have to be, from 0 to 1, to count as a match.
+pub const DEFAULT_NAME_THRESHOLD: f64 = 0.9;
+
+const PHONE_NO_WEIGHT: f64 = 0.4;
+const EMAIL_WEIGHT: f64 = 0.4;
+const NAME_WEIGHT: f64 = 0.2;
+
+/// Contacts that likely describe the same person, with a score between 0 and 1
+/// of how confident the match is.
+pub struct DuplicateGroup {
+    /// The matching contacts, at least two.
+    pub contacts: Vec<Contact>,
+    /// How confident the match is, from 0 to 1.
+    pub score: f64,
+    /// Why the contacts matched, e.g. `same phone_no 123456789`.
+    pub reasons: BTreeSet<String>,
+}
+
+/// How to pick the surviving values of a group when merging automatically.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MergePolicy {
+    /// Keep the values of the most recently updated contact.
+    Newest,
+    /// Keep the values of the least recently updated contact.
+    Oldest,
+}
+
+impl FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newest" => Ok(MergePolicy::Newest),
+            "oldest" => Ok(MergePolicy::Oldest),
+            _ => Err(format!(
+                "Unknown merge policy {s}, expected one of newest, oldest"
+            )),
+        }
+    }
+}
+
+/// Picks one of the conflicting values of a field by index, `None` skips the group.
+pub type Chooser<'a> = dyn FnMut(&str, &[String]) -> Result<Option<usize>, String> + 'a;
+
+/// The contact a group is merged into.
+pub struct Merge {
+    /// The contact that survives, the others in the group are deleted.
+    pub name: String,
+    /// The merged phone number.
+    pub phone_no: u64,
+    /// The merged email.
+    pub email: String,
+}
+
+fn normalize_name(name: &str) -> String {
+    name.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Scores how likely two contacts are duplicates, or `None` if nothing matches.
+fn score_pair(a: &Contact, b: &Contact, name_threshold: f64) -> Option<(f64, Vec<String>)> {
+    let mut score: f64 = 0.0;
+    let mut reasons: Vec<String> = Vec::new();
+
+    if a.phone_no == b.phone_no {
+        score += PHONE_NO_WEIGHT;
+        reasons.push(format!("same phone_no {}", a.phone_no));
+    }
+    if normalize_email(&a.email) == normalize_email(&b.email) {
+        score += EMAIL_WEIGHT;
+        reasons.push(format!("same email {}", normalize_email(&a.email)));
+    }
+    let name_similarity: f64 =
+        strsim::jaro_winkler(&normalize_name(&a.name), &normalize_name(&b.name));
+    score += NAME_WEIGHT * name_similarity;
+    if name_similarity >= name_threshold {
+        reasons.push(format!(
+            "similar names {} and {} ({:.0}%)",
+            a.name,
+            b.name,
+            name_similarity * 100.0
+        ));
+    }
+
+    match reasons.is_empty() {
+        true => None,
+        false => Some((score, reasons)),
+    }
+}
+
+fn find_root(parents: &mut [usize], index: usize) -> usize {
+    let mut root: usize = index;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    parents[index] = root;
+    root
+}
+
+/// Groups likely duplicates by phone_no, case-insensitive email and fuzzy name
+/// similarity, best scored groups first.
+pub fn find_duplicates(contacts: &[Contact], name_threshold: f64) -> Vec<DuplicateGroup> {
+    let mut parents: Vec<usize> = (0..contacts.len()).collect();
+    let mut scores: Vec<f64> = vec![0.0; contacts.len()];
+    let mut reasons: Vec<BTreeSet<String>> = vec![BTreeSet::new(); contacts.len()];
+
+    for i in 0..contacts.len() {
+        for j in i + 1..contacts.len() {
+            let (score, pair_reasons) = match score_pair(&contacts[i], &contacts[j], name_threshold)
+            {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let root_i: usize = find_root(&mut parents, i);
+            let root_j: usize = find_root(&mut parents, j);
+            parents[root_j] = root_i;
+            scores[root_i] = scores[root_i].max(scores[root_j]).max(score);
+            let moved: BTreeSet<String> = std::mem::take(&mut reasons[root_j]);
+            reasons[root_i].extend(moved);
+            reasons[root_i].extend(pair_reasons);
+        }
+    }
+
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    for root in 0..contacts.len() {
+        if find_root(&mut parents, root) != root {
+            continue;
+        }
+        let members: Vec<Contact> = (0..contacts.len())
+            .filter(|&i| find_root(&mut parents, i) == root)
+            .map(|i| contacts[i].clone())
+            .collect();
+        if members.len() > 1 {
+            groups.push(DuplicateGroup {
+                contacts: members,
+                score: scores[root],
+                reasons: std::mem::take(&mut reasons[root]),
+            });
+        }
+    }
+
+    groups.sort_by(|a, b| b.score.total_cmp(&a.score));
+    groups
+}
+
+/// Picks every field from the most or least recently updated contact of the group.
+pub fn merge_with_policy(group: &DuplicateGroup, policy: MergePolicy) -> Merge {
+    let by_updated = |a: &&Contact, b: &&Contact| {
+        a.updated_at
+            .cmp(&b.updated_at)
+            .then_with(|| b.name.cmp(&a.name))
+    };
+    let survivor: &Contact = match policy {
+        MergePolicy::Newest => group.contacts.iter().max_by(by_updated),
+        MergePolicy::Oldest => group.contacts.iter().min_by(by_updated),
+    }
+    .expect("groups have at least two contacts");
+
+    Merge {
+        name: survivor.name.clone(),
+        phone_no: survivor.phone_no,
+        email: survivor.email.clone(),
+    }
+}
+
+/// Builds the merge field by field, letting `choose` pick among the distinct
+/// values of each field. `choose` returns `None` to skip the group.
+pub fn merge_interactively(
+    group: &DuplicateGroup,
+    choose: &mut Chooser,
+) -> Result<Option<Merge>, String> {
+    let distinct = |values: Vec<String>| -> Vec<String> {
+        let mut seen: Vec<String> = Vec::new();
+        for value in values {
+            if !seen.contains(&value) {
+                seen.push(value);
+            }
+        }
+        seen
+    };
+
+    let mut pick = |field: &str, values: Vec<String>| -> Result<Option<String>, String> {
+        let values: Vec<String> = distinct(values);
+        if values.len() == 1 {
+            return Ok(values.into_iter().next());
+        }
+        Ok(choose(field, &values)?.and_then(|index| values.get(index).cloned()))
+    };
+
+    let name: String = match pick(
+        "name",
+        group.contacts.iter().map(|c| c.name.clone()).collect(),
+    )? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let phone_no: String = match pick(
+        "phone_no",
+        group
+            .contacts
+            .iter()
+            .map(|c| c.phone_no.to_string())
+            .collect(),
+    )? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let email: String = match pick(
+        "email",
+        group.contacts.iter().map(|c| c.email.clone()).collect(),
+    )? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(Some(Merge {
+        name,
+        phone_no: phone_no.parse::<u64>().map_err(|e| e.to_string())?,
+        email,
+    }))
+}
+
+/// Applies a merge through the repository: the surviving contact gets the merged
+/// fields and the other contacts of the group are moved to the trash.
+pub fn apply_merge(
+    contacts_service: &mut dyn ContactsRepository,
+    group: &DuplicateGroup,
+    merge: &Merge,
+) -> Result<(), String> {
+    let survivor: &Contact = group
+        .contacts
+        .iter()
+        .find(|c| c.name == merge.name)
+        .ok_or(format!("{} is not part of the group", merge.name))?;
+
+    if survivor.phone_no != merge.phone_no {
+        contacts_service.update_phone_no(&merge.name, merge.phone_no.to_string())?;
+    }
+    if survivor.email != merge.email {
+        contacts_service.update_email(&merge.name, merge.email.clone())?;
+    }
+    for contact in group.contacts.iter().filter(|c| c.name != merge.name) {
+        contacts_service.delete(&contact.name)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::sort_by::SortBy;
+    use crate::repositories::inmemory_contacts::InMemoryContactsRepository;
+
+    fn add(
+        contacts_service: &mut InMemoryContactsRepository,
+        name: &str,
+        phone_no: &str,
+        email: &str,
+    ) {
+        contacts_service
+            .add(name.to_string(), phone_no.to_string(), email.to_string())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_dedupe_find_and_merge() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        add(
+            &mut contacts_service,
+            "Jon Smith",
+            "491234567890",
+            "jon@mail.com",
+        );
+        add(
+            &mut contacts_service,
+            "Jonathan Smith",
+            "491234567890",
+            "smith@mail.com",
+        );
+        add(
+            &mut contacts_service,
+            "Maria",
+            "491111111111",
+            "maria@mail.com",
+        );
+        add(
+            &mut contacts_service,
+            "Maria Popescu",
+            "492222222222",
+            "maria@mail.com",
+        );
+        add(
+            &mut contacts_service,
+            "Bogdan",
+            "493333333333",
+            "bogdan@mail.com",
+        );
+
+        let contacts: Vec<Contact> = contacts_service
+            .list(0, usize::MAX, SortBy::Name, false)
+            .unwrap();
+        let groups: Vec<DuplicateGroup> = find_duplicates(&contacts, DEFAULT_NAME_THRESHOLD);
+        assert_eq!(2, groups.len());
+        let names = |group: &DuplicateGroup| -> Vec<String> {
+            group.contacts.iter().map(|c| c.name.clone()).collect()
+        };
+        assert!(groups
+            .iter()
+            .any(|g| names(g) == vec!["Jon Smith", "Jonathan Smith"]));
+        assert!(groups
+            .iter()
+            .any(|g| names(g) == vec!["Maria", "Maria Popescu"]));
+
+        let group: &DuplicateGroup = groups
+            .iter()
+            .find(|g| names(g) == vec!["Jon Smith", "Jonathan Smith"])
+            .unwrap();
+        let mut answers = vec![Some(1), Some(0)].into_iter();
+        let merge: Merge = merge_interactively(group, &mut |_, _| Ok(answers.next().unwrap()))
+            .unwrap()
+            .unwrap();
+        assert_eq!("Jonathan Smith", merge.name);
+        assert_eq!("jon@mail.com", merge.email);
+
+        apply_merge(&mut contacts_service, group, &merge).unwrap();
+        assert!(contacts_service.get("Jon Smith").unwrap().is_none());
+        assert_eq!(
+            "jon@mail.com",
+            contacts_service
+                .get("Jonathan Smith")
+                .unwrap()
+                .unwrap()
+                .email
+        );
+
+        let skipped: Option<Merge> = merge_interactively(group, &mut |_, _| Ok(None)).unwrap();
+        assert!(skipped.is_none());
+        let mut maria: DuplicateGroup = groups
+            .into_iter()
+            .find(|g| names(g) == vec!["Maria", "Maria Popescu"])
+            .unwrap();
+        maria.contacts[0].updated_at = 100;
+        maria.contacts[1].updated_at = 200;
+        assert_eq!(
+            "Maria Popescu",
+            merge_with_policy(&maria, MergePolicy::Newest).name
+        );
+        assert_eq!("Maria", merge_with_policy(&maria, MergePolicy::Oldest).name);
+    }
+}
@@ -0,0 +1,111 @@
+//! Comparing two sets of contacts by name.
+
+use crate::models::change::{diff_fields, FieldChange};
+use crate::models::contact::Contact;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A contact present on both sides with at least one differing field.
+#[derive(Serialize)]
+pub struct ChangedContact {
+    /// The name the contact has on both sides.
+    pub name: String,
+    /// The differing fields, from left to right.
+    pub fields: Vec<FieldChange>,
+}
+
+/// What it takes to go from the left set of contacts to the right one.
+#[derive(Serialize, Default)]
+pub struct ContactsDiff {
+    /// Contacts only on the right side.
+    pub added: Vec<Contact>,
+    /// Contacts only on the left side.
+    pub removed: Vec<Contact>,
+    /// Contacts on both sides that differ.
+    pub changed: Vec<ChangedContact>,
+}
+
+impl ContactsDiff {
+    /// Whether both sides hold the same contacts.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Matches contacts by name and reports added, removed and changed ones, sorted
+/// by name. Timestamps other than `deleted_at` are not compared.
+pub fn diff_contacts(left: Vec<Contact>, right: Vec<Contact>) -> ContactsDiff {
+    let mut left: BTreeMap<String, Contact> =
+        left.into_iter().map(|c| (c.name.clone(), c)).collect();
+    let mut diff: ContactsDiff = ContactsDiff::default();
+
+    let right: BTreeMap<String, Contact> = right.into_iter().map(|c| (c.name.clone(), c)).collect();
+    for (name, new) in right {
+        match left.remove(&name) {
+            Some(old) => {
+                let fields: Vec<FieldChange> = diff_fields(Some(&old), Some(&new));
+                if !fields.is_empty() {
+                    diff.changed.push(ChangedContact { name, fields });
+                }
+            }
+            None => diff.added.push(new),
+        }
+    }
+    diff.removed = left.into_values().collect();
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(name: &str, phone_no: u64, email: &str) -> Contact {
+        Contact {
+            name: name.to_string(),
+            phone_no,
+            email: email.to_string(),
+            created_at: 0,
+            updated_at: 0,
+            deleted_at: None,
+            links: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_diff_contacts() {
+        let left: Vec<Contact> = vec![
+            contact("Mihai", 491234567890, "mihai@mail.com"),
+            contact("Maria", 491111111111, "maria@mail.com"),
+            contact("Bogdan", 492222222222, "bogdan@mail.com"),
+        ];
+        let mut moved: Contact = contact("Bogdan", 492222222222, "bogdan@work.com");
+        moved.updated_at = 100;
+        let right: Vec<Contact> = vec![
+            contact("Mihai", 491234567890, "mihai@mail.com"),
+            moved,
+            contact("Eugen", 493333333333, "eugen@mail.com"),
+        ];
+
+        let diff: ContactsDiff = diff_contacts(left, right);
+        assert_eq!(1, diff.added.len());
+        assert_eq!("Eugen", diff.added[0].name);
+        assert_eq!(1, diff.removed.len());
+        assert_eq!("Maria", diff.removed[0].name);
+        assert_eq!(1, diff.changed.len());
+        assert_eq!("Bogdan", diff.changed[0].name);
+        assert_eq!(
+            vec![FieldChange {
+                field: "email".to_string(),
+                old_value: Some("bogdan@mail.com".to_string()),
+                new_value: Some("bogdan@work.com".to_string()),
+            }],
+            diff.changed[0].fields
+        );
+
+        let same: ContactsDiff = diff_contacts(
+            vec![contact("Mihai", 491234567890, "mihai@mail.com")],
+            vec![contact("Mihai", 491234567890, "mihai@mail.com")],
+        );
+        assert!(same.is_empty());
+    }
+}
@@ -0,0 +1,333 @@
+use contacts_app::models::contact::Contact;
+use contacts_app::repositories::contacts::{
+    get_valid_email, get_valid_name, get_valid_phone_no, ContactsRepository,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_EDITOR: &str = "vi";
+const ERROR_PREFIX: &str = "# Error: ";
+/// How many names to try when creating the document before giving up.
+const CREATE_ATTEMPTS: u32 = 16;
+
+/// The fields of a contact as the user edits them.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+struct EditableContact {
+    name: String,
+    phone_no: String,
+    email: String,
+}
+
+/// The file the document is edited in. It holds personal data, so it is
+/// created under a name other users cannot guess, readable by the user only,
+/// and removed however the edit ends.
+struct EditedFile {
+    path: PathBuf,
+}
+
+impl EditedFile {
+    fn create(dir: &Path, document: &str) -> Result<Self, String> {
+        for _ in 0..CREATE_ATTEMPTS {
+            let path: PathBuf = dir.join(format!("contacts-cli-{:016x}.toml", get_random_u64()));
+            let mut options: OpenOptions = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            options.mode(0o600);
+            let mut file: File = match options.open(&path) {
+                Ok(x) => x,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(format!("{}: {err}", path.display())),
+            };
+            let edited_file: EditedFile = EditedFile { path };
+            file.write_all(document.as_bytes())
+                .map_err(|err| err.to_string())?;
+            return Ok(edited_file);
+        }
+        Err(format!("Cannot create a file to edit in {}", dir.display()))
+    }
+}
+
+impl Drop for EditedFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// A random number, from the randomly keyed hasher of the standard library.
+fn get_random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    let nanos: u128 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_nanos())
+        .unwrap_or_default();
+    hasher.write_u128(nanos);
+    hasher.write_u32(std::process::id());
+    hasher.finish()
+}
+
+#[derive(PartialEq, Eq, Debug)]
+pub enum EditOutcome {
+    Created,
+    Updated,
+    Unchanged,
+    Cancelled,
+}
+
+/// Opens the contact as a TOML document in `$VISUAL`/`$EDITOR`, reopening it with
+/// the errors as comments until it validates, then applies the changed fields.
+/// Without a name, a new contact is created the same way.
+pub fn edit_contact(
+    contacts_service: &mut dyn ContactsRepository,
+    name: Option<&str>,
+) -> Result<EditOutcome, String> {
+    let original: Option<Contact> = match name {
+        Some(name) => Some(
+            contacts_service
+                .get(name)?
+                .ok_or(format!("No contact with name {name}"))?,
+        ),
+        None => None,
+    };
+
+    let document: String = to_document(original.as_ref(), &[])?;
+    let edited_file: EditedFile = EditedFile::create(&env::temp_dir(), &document)?;
+
+    loop {
+        open_editor(&edited_file.path)?;
+        let text: String = fs::read_to_string(&edited_file.path).map_err(|e| e.to_string())?;
+
+        if is_blank(&text) {
+            return Ok(EditOutcome::Cancelled);
+        }
+        match check_document(&text, original.as_ref(), contacts_service) {
+            Ok(edited) => return apply(contacts_service, original.as_ref(), edited),
+            Err(errors) => fs::write(&edited_file.path, with_errors(&text, &errors))
+                .map_err(|e| e.to_string())?,
+        }
+    }
+}
+
+fn open_editor(path: &Path) -> Result<(), String> {
+    let editor: String = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+    let mut args: Vec<String> =
+        shlex::split(&editor).ok_or(format!("Invalid editor command {editor}"))?;
+    if args.is_empty() {
+        return Err("The editor command is empty".to_string());
+    }
+    let program: String = args.remove(0);
+
+    let status = Command::new(&program)
+        .args(args)
+        .arg(path)
+        .status()
+        .map_err(|e| format!("Cannot run editor {program}: {e}"))?;
+    if !status.success() {
+        return Err(format!("Editor {program} exited with {status}"));
+    }
+    Ok(())
+}
+
+fn to_document(contact: Option<&Contact>, errors: &[String]) -> Result<String, String> {
+    let editable: EditableContact = match contact {
+        Some(contact) => EditableContact {
+            name: contact.name.clone(),
+            phone_no: contact.phone_no.to_string(),
+            email: contact.email.clone(),
+        },
+        None => EditableContact {
+            name: String::new(),
+            phone_no: String::new(),
+            email: String::new(),
+        },
+    };
+    let body: String = toml::to_string(&editable).map_err(|e| e.to_string())?;
+    let header: &str = match contact {
+        Some(_) => "# Edit the contact and save to apply the changed fields.\n# The name cannot be changed. Delete everything to cancel.\n",
+        None => "# Fill in the new contact and save to create it.\n# Delete everything to cancel.\n",
+    };
+
+    Ok(with_errors(&format!("{header}{body}"), errors))
+}
+
+/// Replaces the error comments of a previous round with the new ones.
+fn with_errors(text: &str, errors: &[String]) -> String {
+    let mut document: String = errors
+        .iter()
+        .map(|error| format!("{ERROR_PREFIX}{error}\n"))
+        .collect();
+    for line in text.lines().filter(|line| !line.starts_with(ERROR_PREFIX)) {
+        document.push_str(line);
+        document.push('\n');
+    }
+    document
+}
+
+fn is_blank(text: &str) -> bool {
+    text.lines()
+        .map(str::trim)
+        .all(|line| line.is_empty() || line.starts_with('#'))
+}
+
+fn check_document(
+    text: &str,
+    original: Option<&Contact>,
+    contacts_service: &dyn ContactsRepository,
+) -> Result<EditableContact, Vec<String>> {
+    let edited: EditableContact = toml::from_str(text)
+        .map_err(|e| vec![e.to_string().lines().collect::<Vec<&str>>().join(" ")])?;
+
+    let mut errors: Vec<String> = Vec::new();
+    let mut check = |field: &str, result: Result<(), String>| {
+        if let Err(err) = result {
+            errors.push(format!("{field}: {err}"));
+        }
+    };
+    check("name", get_valid_name(&edited.name).map(|_| ()));
+    check("phone_no", get_valid_phone_no(&edited.phone_no).map(|_| ()));
+    check("email", get_valid_email(&edited.email).map(|_| ()));
+
+    match original {
+        Some(original) if original.name != edited.name => {
+            errors.push(format!("name: cannot be changed from {}", original.name))
+        }
+        None => match contacts_service.get(&edited.name) {
+            Ok(Some(_)) => errors.push(format!(
+                "name: a contact with name {} already exists",
+                edited.name
+            )),
+            Ok(None) => {}
+            Err(err) => errors.push(err),
+        },
+        _ => {}
+    }
+
+    match errors.is_empty() {
+        true => Ok(edited),
+        false => Err(errors),
+    }
+}
+
+fn apply(
+    contacts_service: &mut dyn ContactsRepository,
+    original: Option<&Contact>,
+    edited: EditableContact,
+) -> Result<EditOutcome, String> {
+    let original: &Contact = match original {
+        Some(x) => x,
+        None => {
+            contacts_service.add(edited.name, edited.phone_no, edited.email)?;
+            return Ok(EditOutcome::Created);
+        }
+    };
+
+    let mut outcome: EditOutcome = EditOutcome::Unchanged;
+    if get_valid_phone_no(&edited.phone_no)? != original.phone_no {
+        contacts_service.update_phone_no(&original.name, edited.phone_no)?;
+        outcome = EditOutcome::Updated;
+    }
+    if edited.email != original.email {
+        contacts_service.update_email(&original.name, edited.email)?;
+        outcome = EditOutcome::Updated;
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use contacts_app::repositories::inmemory_contacts::InMemoryContactsRepository;
+
+    #[test]
+    fn test_editor_check_and_apply() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491234567890".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+        let original: Contact = contacts_service.get("Bogdan").unwrap().unwrap();
+
+        let document: String = to_document(Some(&original), &[]).unwrap();
+        let unchanged: EditableContact =
+            check_document(&document, Some(&original), &contacts_service).unwrap();
+        assert_eq!(
+            EditOutcome::Unchanged,
+            apply(&mut contacts_service, Some(&original), unchanged).unwrap()
+        );
+
+        let invalid: String = document
+            .replace("bogdan@mail.com", "invalid")
+            .replace("\"Bogdan\"", "\"Other\"");
+        let errors: Vec<String> =
+            check_document(&invalid, Some(&original), &contacts_service).unwrap_err();
+        assert_eq!(2, errors.len());
+
+        let reopened: String = with_errors(&invalid, &errors);
+        assert!(reopened.starts_with("# Error: email: Email is not valid\n"));
+        let reopened_again: String = with_errors(&reopened, &errors);
+        assert_eq!(reopened, reopened_again);
+
+        let changed: EditableContact = check_document(
+            &document.replace("bogdan@mail.com", "new@mail.com"),
+            Some(&original),
+            &contacts_service,
+        )
+        .unwrap();
+        assert_eq!(
+            EditOutcome::Updated,
+            apply(&mut contacts_service, Some(&original), changed).unwrap()
+        );
+        assert_eq!(
+            "new@mail.com",
+            contacts_service.get("Bogdan").unwrap().unwrap().email
+        );
+
+        let new_document: String = to_document(None, &[]).unwrap();
+        assert!(check_document(&new_document, None, &contacts_service).is_err());
+        let duplicate: String = new_document
+            .replace("name = \"\"", "name = \"Bogdan\"")
+            .replace("phone_no = \"\"", "phone_no = \"491234567890\"")
+            .replace("email = \"\"", "email = \"bogdan@mail.com\"");
+        assert!(check_document(&duplicate, None, &contacts_service).is_err());
+        assert!(is_blank("# only comments\n\n"));
+    }
+
+    #[test]
+    fn test_editor_edited_file() {
+        let dir: PathBuf = env::temp_dir();
+        let edited_file: EditedFile = EditedFile::create(&dir, "name = \"Bogdan\"\n").unwrap();
+        let other: EditedFile = EditedFile::create(&dir, "").unwrap();
+        assert_ne!(edited_file.path, other.path);
+        assert_eq!(
+            "name = \"Bogdan\"\n",
+            fs::read_to_string(&edited_file.path).unwrap()
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode: u32 = fs::metadata(&edited_file.path)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(0o600, mode & 0o777);
+        }
+
+        let path: PathBuf = edited_file.path.clone();
+        drop(edited_file);
+        assert!(!path.exists());
+        assert!(other.path.exists());
+    }
+}
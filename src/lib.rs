@@ -0,0 +1,52 @@
+#![warn(missing_docs)]
+//! Contacts with a name, a German phone number and an email, kept in address
+//! books with a trash, a change log and links between contacts.
+//!
+//! [`ContactsRepository`] is the storage interface every backend implements,
+//! and [`AsyncContactsRepository`] its non-blocking counterpart. The backends
+//! are [`InMemoryContactsRepository`], always available, the encrypted local
+//! file of the `file` feature and Redis with the `redis` feature. `codec`
+//! reads and writes exports with the `json` feature, `backup` keeps snapshots
+//! with the `backup` feature, and [`dedupe`] and [`diff`] compare contacts.
+//! Stores and exports carry a schema version, which
+//! `repositories::migrations` brings up to date.
+//!
+//! ```
+//! use contacts_app::{ContactsRepository, InMemoryContactsRepository};
+//!
+//! let mut contacts_service = InMemoryContactsRepository::new();
+//! contacts_service
+//!     .add(
+//!         "Bogdan".to_string(),
+//!         "491234567890".to_string(),
+//!         "bogdan@mail.com".to_string(),
+//!     )
+//!     .unwrap();
+//! assert_eq!(1, contacts_service.count().unwrap());
+//! assert!(contacts_service.add("Ana".to_string(), "123".to_string(), "ana@mail.com".to_string()).is_err());
+//! ```
+
+pub mod atomic_file;
+#[cfg(feature = "backup")]
+pub mod backup;
+#[cfg(feature = "json")]
+pub mod codec;
+#[cfg(any(feature = "file", feature = "json"))]
+pub mod crypto;
+pub mod dedupe;
+pub mod diff;
+pub mod models;
+pub mod repositories;
+
+#[cfg(feature = "async")]
+pub use repositories::async_contacts::AsyncContactsRepository;
+#[cfg(feature = "redis")]
+pub use repositories::async_db_contacts::AsyncDbContactsRepository;
+pub use repositories::contacts::ContactsRepository;
+#[cfg(feature = "redis")]
+pub use repositories::db_contacts::DbContactsRepository;
+#[cfg(feature = "file")]
+pub use repositories::file_contacts::FileContactsRepository;
+pub use repositories::inmemory_contacts::InMemoryContactsRepository;
+
+pub use models::contact::Contact;
@@ -1,27 +1,208 @@
-use clap::{arg, ArgMatches, Command};
-use models::contact::Contact;
-use repositories::contacts::ContactsRepository;
-use repositories::db_contacts::DbContactsRepository;
+#[cfg(any(feature = "file", feature = "json"))]
+use age::secrecy::SecretString;
+use chrono::{DateTime, Utc};
+use clap::{arg, ArgGroup, ArgMatches, Command};
+use clap_complete::Shell;
+use completion::ContactsHelper;
+#[cfg(feature = "json")]
+use contacts_app::atomic_file::WriteOptions;
+#[cfg(feature = "backup")]
+use contacts_app::backup::{self, Retention, Snapshot};
+#[cfg(feature = "json")]
+use contacts_app::codec::read_contacts_file;
+#[cfg(feature = "json")]
+use contacts_app::crypto::{self, Decryption, Encryption};
+use contacts_app::dedupe::{self, DuplicateGroup, Merge, MergePolicy};
+#[cfg(feature = "json")]
+use contacts_app::diff::{self, ContactsDiff};
+use contacts_app::models::change::Change;
+use contacts_app::models::contact::Contact;
+use contacts_app::models::link::LinkKind;
+use contacts_app::models::page::Page;
+use contacts_app::models::sort_by::SortBy;
+use contacts_app::repositories::contacts::{get_current_timestamp, ContactsRepository};
+#[cfg(feature = "redis")]
+use contacts_app::repositories::db_contacts::DbContactsRepository;
+#[cfg(feature = "file")]
+use contacts_app::repositories::file_contacts::FileContactsRepository;
+use contacts_app::repositories::inmemory_contacts::InMemoryContactsRepository;
+use contacts_app::repositories::migrations::{MigrationStatus, MigrationStep};
+use editor::EditOutcome;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use std::env;
 use std::io::Write;
+use std::path::PathBuf;
 
-mod models;
-mod repositories;
+mod completion;
+mod editor;
+mod shell_completions;
+mod tui;
+
+const HISTORY_FILE_KEY: &str = "CONTACTS_HISTORY_FILE";
+const DEFAULT_HISTORY_FILE_NAME: &str = ".contacts_cli_history";
+#[cfg(feature = "backup")]
+const BACKUP_DIR_KEY: &str = "CONTACTS_BACKUP_DIR";
+#[cfg(feature = "backup")]
+const DEFAULT_BACKUP_DIR_NAME: &str = ".contacts_cli_backups";
+#[cfg(feature = "backup")]
+const BACKUP_KEEP_DAILY_KEY: &str = "CONTACTS_BACKUP_KEEP_DAILY";
+#[cfg(feature = "backup")]
+const BACKUP_KEEP_WEEKLY_KEY: &str = "CONTACTS_BACKUP_KEEP_WEEKLY";
+const BACKEND_KEY: &str = "CONTACTS_BACKEND";
+/// Redis when built in, else the encrypted local file, else memory.
+#[cfg(feature = "redis")]
+const DEFAULT_BACKEND: &str = "redis";
+#[cfg(all(not(feature = "redis"), feature = "file"))]
+const DEFAULT_BACKEND: &str = "file";
+#[cfg(not(any(feature = "redis", feature = "file")))]
+const DEFAULT_BACKEND: &str = "memory";
+#[cfg(feature = "file")]
+const STORE_FILE_KEY: &str = "CONTACTS_FILE";
+#[cfg(feature = "file")]
+const DEFAULT_STORE_FILE_NAME: &str = ".contacts_cli_store.age";
+#[cfg(any(feature = "file", feature = "json"))]
+const PASSPHRASE_KEY: &str = "CONTACTS_PASSPHRASE";
+#[cfg(feature = "json")]
+const RECIPIENTS_KEY: &str = "CONTACTS_AGE_RECIPIENTS";
+#[cfg(feature = "json")]
+const IDENTITY_FILE_KEY: &str = "CONTACTS_AGE_IDENTITY_FILE";
+const REQUIRE_ENCRYPTION_KEY: &str = "CONTACTS_REQUIRE_ENCRYPTION";
+const BOOK_KEY: &str = "CONTACTS_BOOK";
+const PROFILE_KEY: &str = "CONTACTS_PROFILE";
+const DEFAULT_MIGRATE_BATCH_SIZE: &str = "100";
+
+/// Cursors of the last listed page, used by `next` and `prev`.
+#[derive(Default)]
+struct ReplState {
+    page_size: usize,
+    next_cursor: Option<String>,
+    prev_cursor: Option<String>,
+}
 
 fn main() -> Result<(), String> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        run_repl()
+    } else {
+        run_once(args)
+    }
+}
+
+/// Runs a single command given on the command line, e.g. `contacts-cli view Bogdan`.
+fn run_once(args: Vec<String>) -> Result<(), String> {
+    let matches: ArgMatches = shell_cli()
+        .try_get_matches_from(std::iter::once(cli().get_name().to_string()).chain(args))
+        .unwrap_or_else(|e| e.exit());
+
+    match matches.subcommand() {
+        Some(("completions", sub_matches)) => {
+            let shell: Shell = *sub_matches.get_one::<Shell>("SHELL").expect("required");
+            shell_completions::write_completions(
+                shell,
+                &mut shell_cli(),
+                shell_completions::COMPLETE_NAMES_COMMAND,
+                &mut std::io::stdout(),
+            )
+        }
+        Some(("tui", _)) => {
+            let mut contacts_service: Box<dyn ContactsRepository> = open_repository()?;
+            tui::run(contacts_service.as_mut())
+        }
+        Some(("migrate-prefix", sub_matches)) => {
+            load_env_files()?;
+            migrate_prefix(sub_matches)
+        }
+        Some(("manpage", _)) => clap_mangen::Man::new(shell_cli())
+            .render(&mut std::io::stdout())
+            .map_err(|e| e.to_string()),
+        Some((shell_completions::COMPLETE_NAMES_COMMAND, _)) => {
+            // Called by the shell on every tab, so it never asks for anything.
+            let contacts_service: Box<dyn ContactsRepository> = match open_repository_quietly()? {
+                Some(contacts_service) => contacts_service,
+                None => return Ok(()),
+            };
+            for name in contacts_service.list_names()? {
+                writeln!(std::io::stdout(), "{}", name).map_err(|e| e.to_string())?;
+            }
+            Ok(())
+        }
+        _ => {
+            let mut contacts_service: Box<dyn ContactsRepository> = open_repository()?;
+            let mut state: ReplState = ReplState::default();
+            if let Err(err) = execute(&matches, contacts_service.as_mut(), &mut state) {
+                stderr_write(&err)?;
+                stderr_write_newline()?;
+                std::process::exit(1);
+            }
+            stdout_write("\n")?;
+            stdout_flush()
+        }
+    }
+}
+
+fn run_repl() -> Result<(), String> {
     stdout_write(
         "contacts-cli\n\nUse `help` to discover more commands, or `quit` to exit the REPL\n",
     )?;
-    let mut contacts_service: DbContactsRepository = DbContactsRepository::new();
+    let mut contacts_service: Box<dyn ContactsRepository> = open_repository()?;
+    let mut state: ReplState = ReplState::default();
 
+    let mut editor: Editor<ContactsHelper, DefaultHistory> =
+        Editor::new().map_err(|e| e.to_string())?;
+    editor.set_helper(Some(ContactsHelper::new(&cli())));
+    let history_file: Option<PathBuf> = get_history_file();
+    if let Some(history_file) = &history_file {
+        // A missing history file simply means a fresh history.
+        let _ = editor.load_history(history_file);
+    }
+
+    // A lost connection is reported once and the REPL keeps running, as the
+    // next command reconnects.
+    let mut connected: bool = true;
     loop {
-        let no_of_contacts: usize = contacts_service.count()?;
-        let line: String = stdin_read_line(no_of_contacts)?;
+        let no_of_contacts: Option<usize> = match contacts_service.count() {
+            Ok(no_of_contacts) => {
+                if !connected {
+                    stdout_write("Reconnected to the data store.\n")?;
+                    connected = true;
+                }
+                Some(no_of_contacts)
+            }
+            Err(err) => {
+                if connected {
+                    stderr_write(&format!("Cannot reach the data store: {err}"))?;
+                    stderr_write_newline()?;
+                    connected = false;
+                }
+                None
+            }
+        };
+        if let (true, Some(helper)) = (connected, editor.helper_mut()) {
+            if let Ok(names) = contacts_service.list_names() {
+                helper.set_contact_names(names);
+            }
+        }
+
+        let book: String = contacts_service.current_book();
+        let line: String = match stdin_read_line(&mut editor, no_of_contacts, &book)? {
+            Some(line) => line,
+            None => break,
+        };
         let line: &str = line.trim();
         if line.is_empty() {
             continue;
         }
 
-        match respond(line, &mut contacts_service) {
+        editor.add_history_entry(line).map_err(|e| e.to_string())?;
+        if let Some(history_file) = &history_file {
+            editor
+                .save_history(history_file)
+                .map_err(|e| e.to_string())?;
+        }
+
+        match respond(line, contacts_service.as_mut(), &mut state) {
             Ok(quit) => {
                 if quit {
                     break;
@@ -37,11 +218,24 @@ fn main() -> Result<(), String> {
     Ok(())
 }
 
-fn respond(line: &str, contacts_service: &mut DbContactsRepository) -> Result<bool, String> {
+fn respond(
+    line: &str,
+    contacts_service: &mut dyn ContactsRepository,
+    state: &mut ReplState,
+) -> Result<bool, String> {
     let args: Vec<String> = shlex::split(line).ok_or("error: Invalid quoting")?;
     let matches: ArgMatches = cli()
         .try_get_matches_from(args)
         .map_err(|e| e.to_string())?;
+
+    execute(&matches, contacts_service, state)
+}
+
+fn execute(
+    matches: &ArgMatches,
+    contacts_service: &mut dyn ContactsRepository,
+    state: &mut ReplState,
+) -> Result<bool, String> {
     let mut quit: bool = false;
 
     match matches.subcommand() {
@@ -64,42 +258,382 @@ fn respond(line: &str, contacts_service: &mut DbContactsRepository) -> Result<bo
             contacts_service.update_email(name, new_email)?;
             stdout_write("Contact updated succesfully")?;
         }
+        Some(("edit", sub_matches)) => {
+            let name: Option<&str> = match sub_matches.get_flag("new") {
+                true => None,
+                false => Some(get_arg("NAME", sub_matches)),
+            };
+            match editor::edit_contact(contacts_service, name)? {
+                EditOutcome::Created => stdout_write("Contact added succesfully")?,
+                EditOutcome::Updated => stdout_write("Contact updated succesfully")?,
+                EditOutcome::Unchanged => stdout_write("No changes")?,
+                EditOutcome::Cancelled => stdout_write("Edit cancelled")?,
+            }
+        }
         Some(("view", sub_matches)) => {
             let name: &str = get_arg("NAME", sub_matches);
             match contacts_service.get(name).unwrap() {
-                Some(contact) => stdout_write_contact(contact)?,
+                Some(contact) => {
+                    stdout_write_contact(contact)?;
+                    stdout_write_related(contacts_service.related(name)?)?;
+                }
                 None => stdout_write_unknown_key(name)?,
             }
         }
         Some(("delete", sub_matches)) => {
             let name: &str = get_arg("NAME", sub_matches);
             contacts_service.delete(name)?;
-            stdout_write("Contact deleted succesfully")?;
+            stdout_write("Contact moved to the trash succesfully")?;
         }
+        Some(("restore", sub_matches)) => {
+            let name: &str = get_arg("NAME", sub_matches);
+            contacts_service.restore(name)?;
+            stdout_write("Contact restored succesfully")?;
+        }
+        Some(("link", sub_matches)) => {
+            let name: &str = get_arg("NAME", sub_matches);
+            let other: &str = get_arg("OTHER", sub_matches);
+            let kind: LinkKind = get_arg("KIND", sub_matches).parse::<LinkKind>()?;
+            contacts_service.link(name, other, kind, sub_matches.get_flag("both"))?;
+            stdout_write("Contacts linked succesfully")?;
+        }
+        Some(("unlink", sub_matches)) => {
+            let name: &str = get_arg("NAME", sub_matches);
+            let other: &str = get_arg("OTHER", sub_matches);
+            contacts_service.unlink(name, other)?;
+            stdout_write("Contacts unlinked succesfully")?;
+        }
+        Some(("related", sub_matches)) => {
+            let related: Vec<(LinkKind, Contact)> =
+                contacts_service.related(get_arg("NAME", sub_matches))?;
+            if related.is_empty() {
+                stdout_write("No related contacts")?;
+            }
+            for (kind, contact) in related {
+                writeln!(
+                    std::io::stdout(),
+                    "{} | {} | {} | {}",
+                    kind,
+                    contact.name,
+                    contact.phone_no,
+                    contact.email
+                )
+                .map_err(|e| e.to_string())?;
+            }
+        }
+        Some(("trash", sub_matches)) => match sub_matches.subcommand() {
+            Some(("list", _)) => {
+                let contacts: Vec<Contact> = contacts_service.list_trash()?;
+                stdout_write_contacts(contacts)?;
+            }
+            Some(("purge", purge_matches)) => {
+                let deleted_before: Option<i64> =
+                    match purge_matches.get_one::<String>("older-than") {
+                        Some(older_than) => Some(get_timestamp_ago(older_than)?),
+                        None => None,
+                    };
+                let no_of_contacts: usize = contacts_service.purge_trash(deleted_before)?;
+                write!(
+                    std::io::stdout(),
+                    "{} contact(s) purged from the trash",
+                    no_of_contacts
+                )
+                .map_err(|e| e.to_string())?;
+            }
+            _ => unreachable!("subcommand required"),
+        },
+        #[cfg(feature = "json")]
         Some(("export", sub_matches)) => {
             let path: &str = get_arg("PATH", sub_matches);
-            match contacts_service.export_to_json(path.to_string()) {
+            let include_trash: bool = sub_matches.get_flag("include-trash");
+            let encryption: Option<Encryption> = get_encryption(sub_matches)?;
+            let options: WriteOptions = WriteOptions {
+                force: sub_matches.get_flag("force"),
+                keep_backup: sub_matches.get_flag("backup"),
+            };
+            match contacts_service.export_to_json(
+                path.to_string(),
+                include_trash,
+                encryption.as_ref(),
+                options,
+            ) {
                 Ok(_) => stdout_write("Contacts exported successfully")?,
                 Err(err) => stderr_write(&err)?,
             }
         }
+        #[cfg(feature = "json")]
         Some(("import", sub_matches)) => {
             let path: &str = get_arg("PATH", sub_matches);
-            match contacts_service.import_from_json(path.to_string()) {
-                Ok(_) => stdout_write("Contacts imported successfully")?,
+            match contacts_service.import_from_json(path.to_string(), &mut get_decryption) {
+                Ok(rejected) if rejected.is_empty() => {
+                    stdout_write("Contacts imported successfully")?
+                }
+                Ok(rejected) => {
+                    stdout_write("Contacts imported, skipped the invalid ones:")?;
+                    for reason in rejected {
+                        stdout_write(&format!("\n  {path}: {reason}"))?;
+                    }
+                }
                 Err(err) => stderr_write(&err)?,
             }
         }
         Some(("list", sub_matches)) => {
-            let page_no_as_str: &str = get_arg("PAGE_NO", sub_matches);
-            let page_size_as_str: &str = get_arg("PAGE_SIZE", sub_matches);
+            let page_size: usize = *sub_matches.get_one::<usize>("PAGE_SIZE").expect("default");
+            let sort_by: SortBy = get_arg("sort", sub_matches).parse::<SortBy>()?;
+            let descending: bool = sub_matches.get_flag("desc");
+            let page: Page = contacts_service.list_page(None, page_size, sort_by, descending)?;
 
-            let page_no: usize = page_no_as_str.parse::<usize>().unwrap_or(0);
-            let page_size: usize = page_size_as_str.parse::<usize>().unwrap_or(10);
-            let contacts: Vec<Contact> = contacts_service.list(page_no, page_size)?;
+            state.page_size = page_size;
+            stdout_write_page(page, state)?;
+        }
+        Some(("next", _)) => match state.next_cursor.take() {
+            Some(cursor) => {
+                let page: Page = contacts_service.list_page(
+                    Some(&cursor),
+                    state.page_size,
+                    SortBy::default(),
+                    false,
+                )?;
+                stdout_write_page(page, state)?;
+            }
+            None => stdout_write("No next page, use `list` first")?,
+        },
+        Some(("prev", _)) => match state.prev_cursor.take() {
+            Some(cursor) => {
+                let page: Page = contacts_service.list_page(
+                    Some(&cursor),
+                    state.page_size,
+                    SortBy::default(),
+                    false,
+                )?;
+                stdout_write_page(page, state)?;
+            }
+            None => stdout_write("No previous page")?,
+        },
+        Some(("recent", sub_matches)) => {
+            let limit: usize = get_arg("LIMIT", sub_matches)
+                .parse::<usize>()
+                .map_err(|e| e.to_string())?;
+            let contacts: Vec<Contact> = contacts_service.recent(limit)?;
 
             stdout_write_contacts(contacts)?;
         }
+        Some(("history", sub_matches)) => {
+            let name: &str = get_arg("NAME", sub_matches);
+            let changes: Vec<Change> = contacts_service.history(name)?;
+            stdout_write_changes(changes)?;
+        }
+        Some(("audit", sub_matches)) => {
+            let since: Option<i64> = match sub_matches.get_one::<String>("since") {
+                Some(since) => Some(parse_since(since)?),
+                None => None,
+            };
+            let changes: Vec<Change> = contacts_service.audit(since)?;
+            stdout_write_changes(changes)?;
+        }
+        Some(("dedupe", sub_matches)) => {
+            let threshold: f64 = sub_matches
+                .get_one::<f64>("threshold")
+                .copied()
+                .unwrap_or(dedupe::DEFAULT_NAME_THRESHOLD);
+            let policy: Option<MergePolicy> = match sub_matches.get_one::<String>("auto") {
+                Some(policy) => Some(policy.parse::<MergePolicy>()?),
+                None => None,
+            };
+            let dry_run: bool = sub_matches.get_flag("dry-run");
+            let contacts: Vec<Contact> =
+                contacts_service.list(0, usize::MAX, SortBy::Name, false)?;
+            let groups: Vec<DuplicateGroup> = dedupe::find_duplicates(&contacts, threshold);
+            if groups.is_empty() {
+                stdout_write("No duplicates found")?;
+            }
+
+            let mut no_of_merges: usize = 0;
+            for group in groups.iter() {
+                stdout_write_duplicate_group(group)?;
+                if dry_run {
+                    continue;
+                }
+                let merge: Option<Merge> = match policy {
+                    Some(policy) => Some(dedupe::merge_with_policy(group, policy)),
+                    None => dedupe::merge_interactively(group, &mut stdin_choose)?,
+                };
+                match merge {
+                    Some(merge) => {
+                        dedupe::apply_merge(contacts_service, group, &merge)?;
+                        write!(std::io::stdout(), "\nMerged into {}\n", merge.name)
+                            .map_err(|e| e.to_string())?;
+                        no_of_merges += 1;
+                    }
+                    None => stdout_write("\nSkipped\n")?,
+                }
+            }
+            if !groups.is_empty() && !dry_run {
+                write!(std::io::stdout(), "{} group(s) merged", no_of_merges)
+                    .map_err(|e| e.to_string())?;
+            }
+        }
+        #[cfg(feature = "json")]
+        Some(("diff", sub_matches)) => {
+            let left: Vec<Contact> =
+                read_contacts_file(get_arg("LEFT", sub_matches), &mut get_decryption)?;
+            let right: Vec<Contact> = match sub_matches.get_one::<String>("RIGHT") {
+                Some(path) => read_contacts_file(path, &mut get_decryption)?,
+                None => {
+                    let mut contacts: Vec<Contact> =
+                        contacts_service.list(0, usize::MAX, SortBy::Name, false)?;
+                    if sub_matches.get_flag("include-trash") {
+                        contacts.extend(contacts_service.list_trash()?);
+                    }
+                    contacts
+                }
+            };
+            let contacts_diff: ContactsDiff = diff::diff_contacts(left, right);
+            match sub_matches.get_flag("json") {
+                true => stdout_write(
+                    &serde_json::to_string_pretty(&contacts_diff).map_err(|e| e.to_string())?,
+                )?,
+                false => stdout_write_diff(contacts_diff)?,
+            }
+        }
+        #[cfg(feature = "backup")]
+        Some(("backup", sub_matches)) => {
+            let dir: PathBuf = get_backup_dir()?;
+            match sub_matches.subcommand() {
+                Some(("create", create_matches)) => {
+                    let encryption: Option<Encryption> = get_encryption(create_matches)?;
+                    let snapshot: Snapshot = backup::create(
+                        contacts_service,
+                        &dir,
+                        get_current_timestamp(),
+                        encryption.as_ref(),
+                    )?;
+                    write!(
+                        std::io::stdout(),
+                        "Backup {} created at {}",
+                        snapshot.id,
+                        snapshot.path.display()
+                    )
+                    .map_err(|e| e.to_string())?;
+                    if let Some(retention) = get_retention(create_matches)? {
+                        stdout_write_pruned(backup::prune(&dir, retention)?)?;
+                    }
+                }
+                Some(("list", _)) => {
+                    let snapshots: Vec<Snapshot> = backup::list(&dir)?;
+                    if snapshots.is_empty() {
+                        stdout_write("No backups")?;
+                    }
+                    for snapshot in snapshots {
+                        writeln!(
+                            std::io::stdout(),
+                            "{} | {} | {} bytes",
+                            snapshot.id,
+                            format_timestamp(snapshot.created_at),
+                            snapshot.size
+                        )
+                        .map_err(|e| e.to_string())?;
+                    }
+                }
+                Some(("restore", restore_matches)) => {
+                    let id: &str = get_arg("ID", restore_matches);
+                    let no_of_contacts: usize = backup::restore(contacts_service, &dir, id, &mut get_decryption)?;
+                    write!(
+                        std::io::stdout(),
+                        "{} contact(s) restored from backup {}",
+                        no_of_contacts,
+                        id
+                    )
+                    .map_err(|e| e.to_string())?;
+                }
+                Some(("prune", prune_matches)) => match get_retention(prune_matches)? {
+                    Some(retention) => stdout_write_pruned(backup::prune(&dir, retention)?)?,
+                    None => stderr_write(&format!(
+                        "No retention configured, use --keep-daily/--keep-weekly or set {BACKUP_KEEP_DAILY_KEY}/{BACKUP_KEEP_WEEKLY_KEY}"
+                    ))?,
+                },
+                _ => unreachable!("subcommand required"),
+            }
+        }
+        #[cfg(not(feature = "json"))]
+        Some((command @ ("export" | "import" | "diff"), _)) => {
+            return Err(format!(
+                "{command} needs the json feature, rebuild contacts-cli with `--features json`"
+            ));
+        }
+        #[cfg(not(feature = "backup"))]
+        Some(("backup", _)) => {
+            return Err(
+                "backup needs the backup feature, rebuild contacts-cli with `--features backup`"
+                    .to_string(),
+            );
+        }
+        Some(("book", sub_matches)) => match sub_matches.subcommand() {
+            Some(("create", create_matches)) => {
+                contacts_service.create_book(get_arg("BOOK", create_matches))?;
+                stdout_write("Address book created succesfully")?;
+            }
+            Some(("list", _)) => {
+                let current_book: String = contacts_service.current_book();
+                for book in contacts_service.list_books()? {
+                    let marker: &str = if book == current_book { "*" } else { " " };
+                    writeln!(std::io::stdout(), "{marker} {book}").map_err(|e| e.to_string())?;
+                }
+            }
+            Some(("use", use_matches)) => {
+                let book: &str = get_arg("BOOK", use_matches);
+                contacts_service.use_book(book)?;
+                state.next_cursor = None;
+                state.prev_cursor = None;
+                write!(std::io::stdout(), "Using address book {book}")
+                    .map_err(|e| e.to_string())?;
+            }
+            Some(("delete", delete_matches)) => {
+                contacts_service.delete_book(get_arg("BOOK", delete_matches))?;
+                stdout_write("Address book deleted succesfully")?;
+            }
+            _ => unreachable!("subcommand required"),
+        },
+        Some(("copy", sub_matches)) => {
+            let name: &str = get_arg("NAME", sub_matches);
+            let book: &str = get_arg("BOOK", sub_matches);
+            contacts_service.copy_to_book(name, book)?;
+            write!(std::io::stdout(), "Contact copied to {book} succesfully")
+                .map_err(|e| e.to_string())?;
+        }
+        Some(("move", sub_matches)) => {
+            let name: &str = get_arg("NAME", sub_matches);
+            let book: &str = get_arg("BOOK", sub_matches);
+            contacts_service.move_to_book(name, book)?;
+            write!(std::io::stdout(), "Contact moved to {book} succesfully")
+                .map_err(|e| e.to_string())?;
+        }
+        Some(("migrate", sub_matches)) => match sub_matches.subcommand() {
+            Some(("status", _)) => {
+                let status: MigrationStatus = contacts_service.migration_status()?;
+                write!(
+                    std::io::stdout(),
+                    "Schema version {}, the latest is {}",
+                    status.version,
+                    status.latest
+                )
+                .map_err(|e| e.to_string())?;
+                stdout_write_steps(&status.pending)?;
+            }
+            Some(("up", _)) => match contacts_service.migrate_up()?.as_slice() {
+                [] => stdout_write("The schema is already up to date")?,
+                steps @ [.., last] => {
+                    write!(
+                        std::io::stdout(),
+                        "Migrated to schema version {}",
+                        last.version
+                    )
+                    .map_err(|e| e.to_string())?;
+                    stdout_write_steps(steps)?;
+                }
+            },
+            _ => unreachable!("subcommand required"),
+        },
         Some(("quit", _)) => {
             stdout_write("Exiting...")?;
             quit = true;
@@ -149,16 +683,48 @@ fn cli() -> Command {
                 .arg(arg!(<NEW_EMAIL> "The new email of the contact"))
                 .arg_required_else_help(true),
         )
+        .subcommand(
+            Command::new("edit")
+                .about("Edit a contact in $EDITOR")
+                .arg(arg!([NAME] "The name of the contact").required_unless_present("new"))
+                .arg(arg!(--new "Create a new contact instead").conflicts_with("NAME"))
+                .arg_required_else_help(true),
+        )
         .subcommand(
             Command::new("delete")
                 .about("Delete a new contact")
                 .arg(arg!(<NAME> "The name of the contact"))
                 .arg_required_else_help(true),
         )
+        .subcommand(
+            Command::new("restore")
+                .about("Restore a contact from the trash")
+                .arg(arg!(<NAME> "The name of the contact"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("trash")
+                .about("Manage deleted contacts")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(Command::new("list").about("List contacts in the trash"))
+                .subcommand(
+                    Command::new("purge")
+                        .about("Permanently remove contacts from the trash")
+                        .arg(arg!(--"older-than" <DURATION> "Only purge contacts deleted longer ago than this, e.g. 30d, 12h")),
+                ),
+        )
         .subcommand(
             Command::new("export")
                 .about("Export contacts to a json file")
                 .arg(arg!(<PATH> "The path of the json file"))
+                .arg(arg!(--"include-trash" "Also export the contacts in the trash"))
+                .arg(arg!(--force "Overwrite the file if it already exists"))
+                .arg(
+                    arg!(--backup "Keep the overwritten file as <PATH>.bak")
+                        .requires("force"),
+                )
+                .args(encryption_args())
                 .arg_required_else_help(true),
         )
         .subcommand(
@@ -170,21 +736,527 @@ fn cli() -> Command {
         .subcommand(
             Command::new("list")
                 .about("List contacts")
-                .arg(arg!(<PAGE_NO> "Page no."))
-                .arg(arg!(<PAGE_SIZE> "Page size"))
+                .arg(
+                    arg!([PAGE_SIZE] "Page size")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("10"),
+                )
+                .arg(
+                    arg!(--sort <SORT> "Sort key")
+                        .value_parser(["name", "created", "updated", "email"])
+                        .default_value("name"),
+                )
+                .arg(arg!(--desc "Sort in descending order"))
+        )
+        .subcommand(Command::new("next").about("Show the next page of the last listing"))
+        .subcommand(Command::new("prev").about("Show the previous page of the last listing"))
+        .subcommand(
+            Command::new("recent")
+                .about("List the last modified contacts")
+                .arg(arg!([LIMIT] "Number of contacts").default_value("10")),
+        )
+        .subcommand(
+            Command::new("link")
+                .about("Record that OTHER is the KIND of NAME, e.g. their manager")
+                .arg(arg!(<NAME> "The name of the contact"))
+                .arg(arg!(<OTHER> "The name of the related contact"))
+                .arg(arg!(<KIND> "One of assistant, executive, manager, report, spouse, colleague"))
+                .arg(arg!(--both "Also record the inverse link from OTHER to NAME"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("unlink")
+                .about("Remove the links between two contacts, in both directions")
+                .arg(arg!(<NAME> "The name of the contact"))
+                .arg(arg!(<OTHER> "The name of the related contact"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("related")
+                .about("List the contacts a contact is linked to")
+                .arg(arg!(<NAME> "The name of the contact"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("Show the change history of a contact")
+                .arg(arg!(<NAME> "The name of the contact"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("audit")
+                .about("Show the change log of all contacts")
+                .arg(arg!(--since <SINCE> "Only show changes since a RFC 3339 date or for a duration back, e.g. 7d")),
+        )
+        .subcommand(
+            Command::new("dedupe")
+                .about("Find likely duplicate contacts and merge them")
+                .arg(
+                    arg!(--auto <POLICY> "Merge without prompting, keeping the newest or oldest contact")
+                        .value_parser(["newest", "oldest"]),
+                )
+                .arg(
+                    arg!(--threshold <THRESHOLD> "Minimum name similarity between 0 and 1, defaults to 0.9")
+                        .value_parser(parse_threshold),
+                )
+                .arg(arg!(--"dry-run" "Only report the duplicates")),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Compare two JSON exports, or an export against the live store")
+                .arg(arg!(<LEFT> "The JSON export to compare from"))
+                .arg(arg!([RIGHT] "The JSON export to compare to"))
+                .arg(
+                    arg!(--live "Compare to the contacts in the data store")
+                        .conflicts_with("RIGHT"),
+                )
+                .arg(
+                    arg!(--"include-trash" "Also compare to the contacts in the trash")
+                        .requires("live"),
+                )
+                .arg(arg!(--json "Print the differences as JSON"))
+                .group(
+                    ArgGroup::new("target")
+                        .args(["RIGHT", "live"])
+                        .required(true),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("backup")
+                .about("Manage compressed snapshots of all the contacts")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("Write a new snapshot, then apply the retention policy if any")
+                        .args(retention_args())
+                        .args(encryption_args()),
+                )
+                .subcommand(Command::new("list").about("List snapshots, newest first"))
+                .subcommand(
+                    Command::new("restore")
+                        .about("Replace all the contacts with a snapshot")
+                        .arg(arg!(<ID> "The id of the snapshot"))
+                        .arg_required_else_help(true),
+                )
+                .subcommand(
+                    Command::new("prune")
+                        .about("Delete the snapshots the retention policy does not keep")
+                        .args(retention_args()),
+                ),
+        )
+        .subcommand(
+            Command::new("book")
+                .about("Manage address books")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("create")
+                        .about("Create an empty address book")
+                        .arg(arg!(<BOOK> "The name of the address book"))
+                        .arg_required_else_help(true),
+                )
+                .subcommand(
+                    Command::new("list").about("List address books, marking the active one"),
+                )
+                .subcommand(
+                    Command::new("use")
+                        .about("Switch to another address book")
+                        .arg(arg!(<BOOK> "The name of the address book"))
+                        .arg_required_else_help(true),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("Delete an address book with all its contacts")
+                        .arg(arg!(<BOOK> "The name of the address book"))
+                        .arg_required_else_help(true),
+                ),
+        )
+        .subcommand(
+            Command::new("copy")
+                .about("Copy a contact to another address book")
+                .arg(arg!(<NAME> "The name of the contact"))
+                .arg(arg!(<BOOK> "The name of the address book"))
                 .arg_required_else_help(true),
         )
+        .subcommand(
+            Command::new("move")
+                .about("Move a contact to another address book")
+                .arg(arg!(<NAME> "The name of the contact"))
+                .arg(arg!(<BOOK> "The name of the address book"))
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("migrate")
+                .about("Bring the stored data up to the current schema version")
+                .subcommand_required(true)
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("status").about("Show the schema version and the pending steps"),
+                )
+                .subcommand(Command::new("up").about("Run the pending steps in order")),
+        )
         .subcommand(Command::new("quit").alias("exit").about("Quit the REPL"))
 }
 
-fn stdin_read_line(no_of_contacts: usize) -> Result<String, String> {
-    stdout_write_prompt(no_of_contacts)?;
+fn encryption_args() -> [clap::Arg; 2] {
+    [
+        arg!(--encrypt "Encrypt with a passphrase"),
+        arg!(--recipient <AGE_KEY> "Encrypt to an age public key, can be repeated")
+            .action(clap::ArgAction::Append)
+            .conflicts_with("encrypt"),
+    ]
+}
+
+fn retention_args() -> [clap::Arg; 2] {
+    [
+        arg!(--"keep-daily" <N> "Keep the newest snapshot of each of the last N days")
+            .value_parser(clap::value_parser!(usize)),
+        arg!(--"keep-weekly" <N> "Keep the newest snapshot of each of the last N weeks")
+            .value_parser(clap::value_parser!(usize)),
+    ]
+}
+
+/// The command tree for one-shot invocation from a shell, which on top of the
+/// REPL commands can generate shell completions and a man page.
+fn shell_cli() -> Command {
+    cli()
+        .multicall(false)
+        .subcommand(
+            Command::new("completions")
+                .about("Generate a shell completion script")
+                .arg(
+                    arg!(<SHELL> "The shell to generate the script for")
+                        .value_parser(clap::value_parser!(Shell)),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new("tui").about("Browse and edit contacts in a full-screen terminal UI"),
+        )
+        .subcommand(Command::new("manpage").about("Generate a man page in roff format"))
+        .subcommand(
+            Command::new("migrate-prefix")
+                .about("Move the Redis keys of the store from one key prefix to another")
+                .arg(arg!(<OLD> "The key prefix the keys are under"))
+                .arg(arg!(<NEW> "The key prefix to move them to"))
+                .arg(
+                    arg!(--"batch-size" <N> "How many keys to move per round trip")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value(DEFAULT_MIGRATE_BATCH_SIZE),
+                )
+                .arg_required_else_help(true),
+        )
+        .subcommand(
+            Command::new(shell_completions::COMPLETE_NAMES_COMMAND)
+                .about("List contact names for shell completion")
+                .hide(true),
+        )
+}
+
+/// Reads one line through the line editor, or `None` once the user hits Ctrl-D.
+fn stdin_read_line(
+    editor: &mut Editor<ContactsHelper, DefaultHistory>,
+    no_of_contacts: Option<usize>,
+    book: &str,
+) -> Result<Option<String>, String> {
+    stdout_write_prompt(no_of_contacts, book)?;
     stdout_flush()?;
-    let mut buf: String = String::new();
-    std::io::stdin()
-        .read_line(&mut buf)
+    match editor.readline(&format!("{book} $ ")) {
+        Ok(line) => Ok(Some(line)),
+        Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+        Err(ReadlineError::Eof) => Ok(None),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Where the REPL history is kept, or `None` when the lines, contact details
+/// included, must not be written in plain text: with the encrypted file backend
+/// or when `CONTACTS_REQUIRE_ENCRYPTION` is set.
+fn get_history_file() -> Option<PathBuf> {
+    if get_backend() == "file" || is_encryption_required() {
+        return None;
+    }
+    match env::var(HISTORY_FILE_KEY) {
+        Ok(path) => Some(PathBuf::from(path)),
+        Err(_) => env::var("HOME")
+            .ok()
+            .map(|home| PathBuf::from(home).join(DEFAULT_HISTORY_FILE_NAME)),
+    }
+}
+
+/// Reads the settings of the profile selected by `CONTACTS_PROFILE` from
+/// `.env.<profile>`, then the shared ones from `.env`. Variables already set
+/// win over both files, and the profile over `.env`.
+#[cfg(feature = "dotenv")]
+fn load_env_files() -> Result<(), String> {
+    if let Ok(profile) = env::var(PROFILE_KEY) {
+        if profile.is_empty()
+            || !profile
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "Invalid profile {profile}, profile names have letters, digits, - and _"
+            ));
+        }
+        dotenvy::from_filename(format!(".env.{profile}")).map_err(|err| {
+            format!("Cannot load the {profile} profile from .env.{profile}: {err}")
+        })?;
+    }
+    dotenvy::dotenv().ok();
+    Ok(())
+}
+
+#[cfg(not(feature = "dotenv"))]
+fn load_env_files() -> Result<(), String> {
+    match env::var(PROFILE_KEY) {
+        Ok(_) => Err(format!(
+            "{PROFILE_KEY} needs the dotenv feature, rebuild contacts-cli with `--features dotenv`"
+        )),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Moves the keys of the Redis store configured in the environment from the
+/// `OLD` key prefix to the `NEW` one.
+#[cfg(feature = "redis")]
+fn migrate_prefix(sub_matches: &ArgMatches) -> Result<(), String> {
+    let old_prefix: &str = get_arg("OLD", sub_matches);
+    let new_prefix: &str = get_arg("NEW", sub_matches);
+    let batch_size: usize = *sub_matches.get_one::<usize>("batch-size").expect("default");
+    let moved: usize =
+        DbContactsRepository::from_env()?.migrate_prefix(old_prefix, new_prefix, batch_size)?;
+    writeln!(
+        std::io::stdout(),
+        "Moved {moved} key(s) from {old_prefix} to {new_prefix}, set REDIS_KEY_PREFIX={new_prefix} to use them"
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "redis"))]
+fn migrate_prefix(_: &ArgMatches) -> Result<(), String> {
+    Err(
+        "migrate-prefix needs the redis feature, rebuild contacts-cli with `--features redis`"
+            .to_string(),
+    )
+}
+
+/// Opens the backend selected by `CONTACTS_BACKEND`, Redis, the encrypted local
+/// file, prompting for its passphrase, or memory, on the address book selected
+/// by `CONTACTS_BOOK`.
+fn open_repository() -> Result<Box<dyn ContactsRepository>, String> {
+    load_env_files()?;
+    use_configured_book(open_backend()?)
+}
+
+/// Opens the repository without prompting and without creating a store: the
+/// file backend is only opened when its store exists and `CONTACTS_PASSPHRASE`
+/// is set, and is `None` otherwise.
+fn open_repository_quietly() -> Result<Option<Box<dyn ContactsRepository>>, String> {
+    load_env_files()?;
+    #[cfg(feature = "file")]
+    if get_backend() == "file" {
+        let path: PathBuf = match get_store_path() {
+            Ok(path) if FileContactsRepository::exists(&path) => path,
+            _ => return Ok(None),
+        };
+        let passphrase: SecretString = match env::var(PASSPHRASE_KEY) {
+            Ok(passphrase) => SecretString::from(passphrase),
+            Err(_) => return Ok(None),
+        };
+        let contacts_service: Box<dyn ContactsRepository> =
+            Box::new(FileContactsRepository::open(path, passphrase)?);
+        return use_configured_book(contacts_service).map(Some);
+    }
+    use_configured_book(open_backend()?).map(Some)
+}
+
+fn use_configured_book(
+    mut contacts_service: Box<dyn ContactsRepository>,
+) -> Result<Box<dyn ContactsRepository>, String> {
+    if let Ok(book) = env::var(BOOK_KEY) {
+        contacts_service.use_book(&book)?;
+    }
+    Ok(contacts_service)
+}
+
+#[cfg(feature = "file")]
+fn get_store_path() -> Result<PathBuf, String> {
+    match env::var(STORE_FILE_KEY) {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => env::var("HOME")
+            .map(|home| PathBuf::from(home).join(DEFAULT_STORE_FILE_NAME))
+            .map_err(|_| format!("Cannot find the store file, set {STORE_FILE_KEY}")),
+    }
+}
+
+fn get_backend() -> String {
+    env::var(BACKEND_KEY).unwrap_or_else(|_| DEFAULT_BACKEND.to_string())
+}
+
+fn open_backend() -> Result<Box<dyn ContactsRepository>, String> {
+    let backend: String = get_backend();
+    match backend.as_str() {
+        #[cfg(feature = "redis")]
+        "redis" => Ok(Box::new(DbContactsRepository::from_env()?)),
+        #[cfg(feature = "file")]
+        "file" => {
+            let path: PathBuf = get_store_path()?;
+            let passphrase: SecretString = match FileContactsRepository::exists(&path) {
+                true => read_passphrase()?,
+                false => read_new_passphrase()?,
+            };
+            Ok(Box::new(FileContactsRepository::open(path, passphrase)?))
+        }
+        "memory" => Ok(Box::new(InMemoryContactsRepository::new())),
+        #[cfg(not(feature = "redis"))]
+        "redis" => Err(format!(
+            "The {backend} backend needs the redis feature, rebuild contacts-cli with `--features redis`"
+        )),
+        #[cfg(not(feature = "file"))]
+        "file" => Err(
+            "The file backend needs the file feature, rebuild contacts-cli with `--features file`"
+                .to_string(),
+        ),
+        backend => Err(format!(
+            "Unknown backend {backend}, expected one of redis, file, memory"
+        )),
+    }
+}
+
+#[cfg(any(feature = "file", feature = "json"))]
+fn read_passphrase() -> Result<SecretString, String> {
+    match env::var(PASSPHRASE_KEY) {
+        Ok(passphrase) => Ok(SecretString::from(passphrase)),
+        Err(_) => rpassword::prompt_password("Passphrase: ")
+            .map(SecretString::from)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Asks for a passphrase twice, to guard against typos in a passphrase that
+/// cannot be recovered.
+#[cfg(any(feature = "file", feature = "json"))]
+fn read_new_passphrase() -> Result<SecretString, String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_KEY) {
+        return Ok(SecretString::from(passphrase));
+    }
+    let passphrase: String =
+        rpassword::prompt_password("New passphrase: ").map_err(|e| e.to_string())?;
+    if passphrase.is_empty() {
+        return Err("The passphrase cannot be empty".to_string());
+    }
+    let confirmation: String =
+        rpassword::prompt_password("Repeat passphrase: ").map_err(|e| e.to_string())?;
+    if passphrase != confirmation {
+        return Err("The passphrases do not match".to_string());
+    }
+    Ok(SecretString::from(passphrase))
+}
+
+/// The encryption asked for on the command line, falling back to the recipients
+/// in the environment. Fails instead of writing plain text when encryption is
+/// required by `CONTACTS_REQUIRE_ENCRYPTION`.
+#[cfg(feature = "json")]
+fn get_encryption(sub_matches: &ArgMatches) -> Result<Option<Encryption>, String> {
+    let recipients: Vec<String> = sub_matches
+        .get_many::<String>("recipient")
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default();
+    if !recipients.is_empty() {
+        return Ok(Some(Encryption::Recipients(crypto::parse_recipients(
+            &recipients.join(","),
+        )?)));
+    }
+    if sub_matches.get_flag("encrypt") {
+        return Ok(Some(Encryption::Passphrase(read_new_passphrase()?)));
+    }
+    if let Ok(recipients) = env::var(RECIPIENTS_KEY) {
+        return Ok(Some(Encryption::Recipients(crypto::parse_recipients(
+            &recipients,
+        )?)));
+    }
+    match is_encryption_required() {
+        true => Err(format!(
+            "Writing unencrypted contacts is disabled by {REQUIRE_ENCRYPTION_KEY}, use --encrypt or --recipient"
+        )),
+        false => Ok(None),
+    }
+}
+
+fn is_encryption_required() -> bool {
+    matches!(
+        env::var(REQUIRE_ENCRYPTION_KEY).as_deref(),
+        Ok("1") | Ok("true")
+    )
+}
+
+/// Decrypts with the age identity file from the environment, or else with a
+/// passphrase.
+#[cfg(feature = "json")]
+fn get_decryption() -> Result<Decryption, String> {
+    match env::var(IDENTITY_FILE_KEY) {
+        Ok(path) => Ok(Decryption::Identities(crypto::read_identities(&path)?)),
+        Err(_) => Ok(Decryption::Passphrase(read_passphrase()?)),
+    }
+}
+
+#[cfg(feature = "backup")]
+fn get_backup_dir() -> Result<PathBuf, String> {
+    match env::var(BACKUP_DIR_KEY) {
+        Ok(path) => Ok(PathBuf::from(path)),
+        Err(_) => env::var("HOME")
+            .map(|home| PathBuf::from(home).join(DEFAULT_BACKUP_DIR_NAME))
+            .map_err(|_| format!("Cannot find a backup directory, set {BACKUP_DIR_KEY}")),
+    }
+}
+
+/// The retention policy from the command line, falling back to the environment.
+#[cfg(feature = "backup")]
+fn get_retention(sub_matches: &ArgMatches) -> Result<Option<Retention>, String> {
+    let get_count = |id: &str, key: &str| -> Result<Option<usize>, String> {
+        match sub_matches.get_one::<usize>(id) {
+            Some(count) => Ok(Some(*count)),
+            None => match env::var(key) {
+                Ok(count) => count
+                    .parse::<usize>()
+                    .map(Some)
+                    .map_err(|_| format!("Invalid {key} {count}")),
+                Err(_) => Ok(None),
+            },
+        }
+    };
+    let daily: Option<usize> = get_count("keep-daily", BACKUP_KEEP_DAILY_KEY)?;
+    let weekly: Option<usize> = get_count("keep-weekly", BACKUP_KEEP_WEEKLY_KEY)?;
+    if daily.is_none() && weekly.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(Retention {
+        daily: daily.unwrap_or(0),
+        weekly: weekly.unwrap_or(0),
+    }))
+}
+
+#[cfg(feature = "backup")]
+fn stdout_write_pruned(pruned: Vec<Snapshot>) -> Result<(), String> {
+    for snapshot in pruned.iter() {
+        write!(std::io::stdout(), "\nPruned backup {}", snapshot.id).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn stdout_write_steps(steps: &[MigrationStep]) -> Result<(), String> {
+    for step in steps {
+        write!(
+            std::io::stdout(),
+            "\n- {}: {}",
+            step.version,
+            step.description
+        )
         .map_err(|e| e.to_string())?;
-    Ok(buf)
+    }
+    Ok(())
 }
 
 fn stdout_flush() -> Result<(), String> {
@@ -199,13 +1271,22 @@ fn stdout_write(text: &str) -> Result<(), String> {
     write!(std::io::stdout(), "{}", text).map_err(|e| e.to_string())
 }
 
-fn stdout_write_prompt(no_of_contacts: usize) -> Result<(), String> {
+fn stdout_write_prompt(no_of_contacts: Option<usize>, book: &str) -> Result<(), String> {
+    let Some(no_of_contacts) = no_of_contacts else {
+        return write!(
+            std::io::stdout(),
+            "\nThe {} address book is unavailable, commands will try to reconnect.\n\n",
+            book
+        )
+        .map_err(|e| e.to_string());
+    };
     let suffix = if no_of_contacts == 1 { "" } else { "s" };
     write!(
         std::io::stdout(),
-        "\n{} contact{} currently in the data store.\n\n$ ",
+        "\n{} contact{} currently in the {} address book.\n\n",
         no_of_contacts,
-        suffix
+        suffix,
+        book
     )
     .map_err(|e| e.to_string())
 }
@@ -213,12 +1294,41 @@ fn stdout_write_prompt(no_of_contacts: usize) -> Result<(), String> {
 fn stdout_write_contact(contact: Contact) -> Result<(), String> {
     write!(
         std::io::stdout(),
-        "Contact\n- name: {}\n- phone_no: {}\n- email: {}",
+        "Contact\n- name: {}\n- phone_no: {}\n- email: {}\n- created_at: {}\n- updated_at: {}",
         contact.name,
         contact.phone_no,
-        contact.email
+        contact.email,
+        format_timestamp(contact.created_at),
+        format_timestamp(contact.updated_at)
     )
-    .map_err(|e| e.to_string())
+    .map_err(|e| e.to_string())?;
+    if let Some(deleted_at) = contact.deleted_at {
+        write!(
+            std::io::stdout(),
+            "\n- deleted_at: {}",
+            format_timestamp(deleted_at)
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn stdout_write_related(related: Vec<(LinkKind, Contact)>) -> Result<(), String> {
+    if related.is_empty() {
+        return Ok(());
+    }
+    let links: Vec<String> = related
+        .into_iter()
+        .map(|(kind, contact)| format!("{kind} {}", contact.name))
+        .collect();
+    write!(std::io::stdout(), "\n- related: {}", links.join(", ")).map_err(|e| e.to_string())
+}
+
+fn format_timestamp(timestamp: i64) -> String {
+    match DateTime::<Utc>::from_timestamp(timestamp, 0) {
+        Some(date_time) => date_time.to_rfc3339(),
+        None => timestamp.to_string(),
+    }
 }
 
 fn stdout_write_contacts(contacts: Vec<Contact>) -> Result<(), String> {
@@ -230,6 +1340,141 @@ fn stdout_write_contacts(contacts: Vec<Contact>) -> Result<(), String> {
     Ok(())
 }
 
+fn stdout_write_changes(changes: Vec<Change>) -> Result<(), String> {
+    if changes.is_empty() {
+        return stdout_write("No changes recorded");
+    }
+    for change in changes {
+        write!(
+            std::io::stdout(),
+            "{} {} by {}: {}",
+            format_timestamp(change.timestamp),
+            change.operation,
+            change.operator,
+            change.name
+        )
+        .map_err(|e| e.to_string())?;
+        for field in change.fields {
+            write!(
+                std::io::stdout(),
+                "\n  - {}: {} -> {}",
+                field.field,
+                field.old_value.unwrap_or_else(|| "<none>".to_string()),
+                field.new_value.unwrap_or_else(|| "<none>".to_string())
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        stdout_write("\n")?;
+    }
+    Ok(())
+}
+
+fn stdout_write_page(page: Page, state: &mut ReplState) -> Result<(), String> {
+    state.next_cursor = page.next_cursor;
+    state.prev_cursor = page.prev_cursor;
+    stdout_write_contacts(page.contacts)?;
+
+    let hints: Vec<&str> = [
+        state.prev_cursor.as_ref().map(|_| "`prev`"),
+        state.next_cursor.as_ref().map(|_| "`next`"),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if !hints.is_empty() {
+        write!(std::io::stdout(), "\nMore with {}", hints.join(" or "))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+fn stdout_write_duplicate_group(group: &DuplicateGroup) -> Result<(), String> {
+    write!(
+        std::io::stdout(),
+        "\nPossible duplicates ({:.0}%): {}",
+        group.score * 100.0,
+        group
+            .reasons
+            .iter()
+            .cloned()
+            .collect::<Vec<String>>()
+            .join(", ")
+    )
+    .map_err(|e| e.to_string())?;
+    for contact in group.contacts.iter() {
+        write!(
+            std::io::stdout(),
+            "\n  - {} | {} | {} | updated {}",
+            contact.name,
+            contact.phone_no,
+            contact.email,
+            format_timestamp(contact.updated_at)
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Asks which of the conflicting values of a field to keep, `s` skips the group.
+fn stdin_choose(field: &str, values: &[String]) -> Result<Option<usize>, String> {
+    loop {
+        write!(std::io::stdout(), "\nKeep which {}?", field).map_err(|e| e.to_string())?;
+        for (index, value) in values.iter().enumerate() {
+            write!(std::io::stdout(), "\n  {}) {}", index + 1, value).map_err(|e| e.to_string())?;
+        }
+        stdout_write("\n  s) skip this group\n> ")?;
+        stdout_flush()?;
+
+        let mut answer: String = String::new();
+        if std::io::stdin()
+            .read_line(&mut answer)
+            .map_err(|e| e.to_string())?
+            == 0
+        {
+            return Ok(None);
+        }
+        match answer.trim() {
+            "s" => return Ok(None),
+            answer => match answer.parse::<usize>() {
+                Ok(choice) if (1..=values.len()).contains(&choice) => return Ok(Some(choice - 1)),
+                _ => stdout_write("Invalid choice")?,
+            },
+        }
+    }
+}
+
+#[cfg(feature = "json")]
+fn stdout_write_diff(contacts_diff: ContactsDiff) -> Result<(), String> {
+    if contacts_diff.is_empty() {
+        return stdout_write("No differences");
+    }
+    let mut lines: Vec<String> = Vec::new();
+    for contact in contacts_diff.added {
+        lines.push(format!(
+            "+ {} | {} | {}",
+            contact.name, contact.phone_no, contact.email
+        ));
+    }
+    for contact in contacts_diff.removed {
+        lines.push(format!(
+            "- {} | {} | {}",
+            contact.name, contact.phone_no, contact.email
+        ));
+    }
+    for changed in contacts_diff.changed {
+        lines.push(format!("~ {}", changed.name));
+        for field in changed.fields {
+            lines.push(format!(
+                "  - {}: {} -> {}",
+                field.field,
+                field.old_value.unwrap_or_else(|| "<none>".to_string()),
+                field.new_value.unwrap_or_else(|| "<none>".to_string())
+            ));
+        }
+    }
+    stdout_write(&lines.join("\n"))
+}
+
 fn stdout_write_unknown_key(key: &str) -> Result<(), String> {
     write!(std::io::stdout(), "No contact with name {}", key).map_err(|e| e.to_string())
 }
@@ -238,10 +1483,63 @@ fn stderr_write(err: &str) -> Result<(), String> {
     write!(std::io::stderr(), "Err: {}", err).map_err(|e| e.to_string())
 }
 
+fn stderr_write_newline() -> Result<(), String> {
+    writeln!(std::io::stderr()).map_err(|e| e.to_string())
+}
+
 fn stderr_write_unknown_command(command: &str) -> Result<(), String> {
     write!(std::io::stderr(), "Unknown command: {}", command).map_err(|e| e.to_string())
 }
 
+fn parse_duration(text: &str) -> Result<i64, String> {
+    let text: &str = text.trim();
+    let split_at: usize = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (value, unit) = text.split_at(split_at);
+    let value: i64 = value
+        .parse::<i64>()
+        .map_err(|_| format!("Invalid duration {text}"))?;
+    let multiplier: i64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" | "" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Invalid duration unit {unit}, expected one of s, m, h, d, w"
+            ))
+        }
+    };
+    value
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("Duration {text} is too long"))
+}
+
+fn get_timestamp_ago(text: &str) -> Result<i64, String> {
+    get_current_timestamp()
+        .checked_sub(parse_duration(text)?)
+        .ok_or_else(|| format!("Duration {text} is too long"))
+}
+
+fn parse_threshold(text: &str) -> Result<f64, String> {
+    let threshold: f64 = text
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid threshold {text}"))?;
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(format!("Threshold {text} is not between 0 and 1"));
+    }
+    Ok(threshold)
+}
+
+fn parse_since(text: &str) -> Result<i64, String> {
+    match DateTime::parse_from_rfc3339(text) {
+        Ok(date_time) => Ok(date_time.timestamp()),
+        Err(_) => get_timestamp_ago(text),
+    }
+}
+
 fn get_arg<'a>(id: &str, sub_matches: &'a ArgMatches) -> &'a str {
     sub_matches.get_one::<String>(id).expect("required")
 }
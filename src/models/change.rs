@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use super::contact::Contact;
+use super::link::Link;
+
+/// The repository call that made a change.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    /// Added a contact or overwrote one with the same name.
+    Add,
+    /// Changed the email of a contact.
+    UpdateEmail,
+    /// Changed the phone number of a contact.
+    UpdatePhoneNo,
+    /// Moved a contact to the trash.
+    Delete,
+    /// Moved a contact back from the trash.
+    Restore,
+    /// Removed a trashed contact for good.
+    Purge,
+    /// Imported a contact from a file.
+    Import,
+    /// Replaced all the contacts with those of a backup.
+    RestoreBackup,
+    /// Replaced the links of a contact.
+    UpdateLinks,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text: &str = match self {
+            Operation::Add => "add",
+            Operation::UpdateEmail => "update-email",
+            Operation::UpdatePhoneNo => "update-phone-no",
+            Operation::Delete => "delete",
+            Operation::Restore => "restore",
+            Operation::Purge => "purge",
+            Operation::Import => "import",
+            Operation::RestoreBackup => "restore-backup",
+            Operation::UpdateLinks => "update-links",
+        };
+        write!(f, "{text}")
+    }
+}
+
+impl std::str::FromStr for Operation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "add" => Ok(Operation::Add),
+            "update-email" => Ok(Operation::UpdateEmail),
+            "update-phone-no" => Ok(Operation::UpdatePhoneNo),
+            "delete" => Ok(Operation::Delete),
+            "restore" => Ok(Operation::Restore),
+            "purge" => Ok(Operation::Purge),
+            "import" => Ok(Operation::Import),
+            "restore-backup" => Ok(Operation::RestoreBackup),
+            "update-links" => Ok(Operation::UpdateLinks),
+            _ => Err(format!("Unknown operation {s}")),
+        }
+    }
+}
+
+/// One field of a contact before and after a change.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct FieldChange {
+    /// The name of the field, e.g. `email`.
+    pub field: String,
+    /// The value before, `None` if the field was not set.
+    pub old_value: Option<String>,
+    /// The value after, `None` if the field is no longer set.
+    pub new_value: Option<String>,
+}
+
+/// One entry of the append-only change log.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Change {
+    /// When the change was made, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// Who made the change, see `CONTACTS_OPERATOR`.
+    pub operator: String,
+    /// What made the change.
+    pub operation: Operation,
+    /// The name of the changed contact.
+    pub name: String,
+    /// The fields that differ, empty if none does.
+    pub fields: Vec<FieldChange>,
+}
+
+impl Change {
+    /// Builds a change entry holding the field-level diff between the old and
+    /// the new state of a contact, where `None` means the contact did not exist.
+    pub fn new(
+        timestamp: i64,
+        operator: String,
+        operation: Operation,
+        name: &str,
+        old: Option<&Contact>,
+        new: Option<&Contact>,
+    ) -> Self {
+        Change {
+            timestamp,
+            operator,
+            operation,
+            name: name.to_string(),
+            fields: diff_fields(old, new),
+        }
+    }
+}
+
+/// Field-level diff between two states of a contact, where `None` means the
+/// contact did not exist.
+pub fn diff_fields(old: Option<&Contact>, new: Option<&Contact>) -> Vec<FieldChange> {
+    let mut fields: Vec<FieldChange> = Vec::new();
+    for ((field, old_value), (_, new_value)) in
+        get_field_values(old).into_iter().zip(get_field_values(new))
+    {
+        if old_value != new_value {
+            fields.push(FieldChange {
+                field: field.to_string(),
+                old_value,
+                new_value,
+            });
+        }
+    }
+    fields
+}
+
+fn get_field_values(contact: Option<&Contact>) -> [(&'static str, Option<String>); 4] {
+    [
+        ("phone_no", contact.map(|c| c.phone_no.to_string())),
+        ("email", contact.map(|c| c.email.clone())),
+        (
+            "deleted_at",
+            contact.and_then(|c| c.deleted_at.map(|x| x.to_string())),
+        ),
+        (
+            "links",
+            contact.filter(|c| !c.links.is_empty()).map(|c| {
+                c.links
+                    .iter()
+                    .map(Link::to_string)
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            }),
+        ),
+    ]
+}
@@ -1,8 +1,26 @@
 use serde::{Deserialize, Serialize};
 
+use super::link::Link;
+
+/// A person in an address book. Times are in seconds since the Unix epoch.
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Contact {
+    /// Unique within an address book.
     pub name: String,
+    /// A German number with its country code, e.g. `491234567890`.
     pub phone_no: u64,
+    /// A lowercase address.
     pub email: String,
+    /// When the contact was first added, 0 if unknown.
+    #[serde(default)]
+    pub created_at: i64,
+    /// When the contact last changed, 0 if unknown.
+    #[serde(default)]
+    pub updated_at: i64,
+    /// When the contact was moved to the trash, `None` while it is live.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    /// The contacts of the same book this one is related to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<Link>,
 }
@@ -0,0 +1,87 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// What the linked contact is to the contact holding the link.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkKind {
+    /// Assists the contact.
+    Assistant,
+    /// Is assisted by the contact.
+    Executive,
+    /// Manages the contact.
+    Manager,
+    /// Reports to the contact.
+    Report,
+    /// Is married to the contact.
+    Spouse,
+    /// Works with the contact.
+    Colleague,
+}
+
+impl LinkKind {
+    /// The name of the kind, as parsed by `from_str`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LinkKind::Assistant => "assistant",
+            LinkKind::Executive => "executive",
+            LinkKind::Manager => "manager",
+            LinkKind::Report => "report",
+            LinkKind::Spouse => "spouse",
+            LinkKind::Colleague => "colleague",
+        }
+    }
+
+    /// The kind of the link going the other way, e.g. the manager of a
+    /// contact has that contact as a report.
+    pub fn inverse(&self) -> Self {
+        match self {
+            LinkKind::Assistant => LinkKind::Executive,
+            LinkKind::Executive => LinkKind::Assistant,
+            LinkKind::Manager => LinkKind::Report,
+            LinkKind::Report => LinkKind::Manager,
+            LinkKind::Spouse => LinkKind::Spouse,
+            LinkKind::Colleague => LinkKind::Colleague,
+        }
+    }
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for LinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "assistant" => Ok(LinkKind::Assistant),
+            "executive" => Ok(LinkKind::Executive),
+            "manager" => Ok(LinkKind::Manager),
+            "report" => Ok(LinkKind::Report),
+            "spouse" => Ok(LinkKind::Spouse),
+            "colleague" => Ok(LinkKind::Colleague),
+            _ => Err(format!(
+                "Unknown relationship {s}, expected one of assistant, executive, manager, report, spouse, colleague"
+            )),
+        }
+    }
+}
+
+/// A relationship to another contact, stored on the contact it starts from.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Link {
+    /// The name of the linked contact.
+    pub name: String,
+    /// What the linked contact is to this one.
+    pub kind: LinkKind,
+}
+
+impl fmt::Display for Link {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.name)
+    }
+}
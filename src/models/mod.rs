@@ -1 +1,12 @@
+//! The data the repositories store and return.
+
+/// The change log of a store.
+pub mod change;
+/// A contact as stored and exported.
 pub mod contact;
+/// Relationships between contacts.
+pub mod link;
+/// Pages of contacts and the cursors between them.
+pub mod page;
+/// The orders contacts can be listed in.
+pub mod sort_by;
@@ -0,0 +1,107 @@
+use super::{contact::Contact, sort_by::SortBy};
+
+/// Which side of the cursor position a page is read from.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    /// The page starts right after the position.
+    After,
+    /// The page ends right before the position.
+    Before,
+}
+
+/// Position of a page boundary in a given sort order. Callers pass it around
+/// as the opaque string produced by `encode`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Cursor {
+    /// The order the pages are in.
+    pub sort_by: SortBy,
+    /// Whether that order is reversed.
+    pub descending: bool,
+    /// Which side of the position the page is on.
+    pub direction: Direction,
+    /// The sort key of the contact at the position.
+    pub sort_value: String,
+    /// The name of that contact, which breaks ties between equal sort keys.
+    pub name: String,
+}
+
+/// Contacts in a given order, with the cursors of the pages around them.
+pub struct Page {
+    /// The contacts on the page.
+    pub contacts: Vec<Contact>,
+    /// Where the following page starts, `None` on the last page.
+    pub next_cursor: Option<String>,
+    /// Where the previous page ends, `None` on the first page.
+    pub prev_cursor: Option<String>,
+}
+
+impl Cursor {
+    /// The opaque string `decode` reads back.
+    pub fn encode(&self) -> String {
+        let direction: &str = match self.direction {
+            Direction::After => "a",
+            Direction::Before => "b",
+        };
+        format!(
+            "{}{}{}.{}.{}",
+            direction,
+            self.sort_by.as_str(),
+            if self.descending { "-" } else { "+" },
+            to_hex(&self.sort_value),
+            to_hex(&self.name)
+        )
+    }
+
+    /// Reads a cursor produced by `encode`, failing on any other string.
+    pub fn decode(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid cursor {text}");
+
+        let mut parts = text.split('.');
+        let (header, sort_value, name) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(header), Some(sort_value), Some(name)) if parts.next().is_none() => {
+                (header, sort_value, name)
+            }
+            _ => return Err(invalid()),
+        };
+        if header.len() < 3 || !header.is_ascii() {
+            return Err(invalid());
+        }
+
+        let direction: Direction = match &header[..1] {
+            "a" => Direction::After,
+            "b" => Direction::Before,
+            _ => return Err(invalid()),
+        };
+        let descending: bool = match &header[header.len() - 1..] {
+            "-" => true,
+            "+" => false,
+            _ => return Err(invalid()),
+        };
+        let sort_by: SortBy = header[1..header.len() - 1]
+            .parse::<SortBy>()
+            .map_err(|_| invalid())?;
+
+        Ok(Cursor {
+            sort_by,
+            descending,
+            direction,
+            sort_value: from_hex(sort_value).ok_or_else(invalid)?,
+            name: from_hex(name).ok_or_else(invalid)?,
+        })
+    }
+}
+
+fn to_hex(text: &str) -> String {
+    text.bytes().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Option<String> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect();
+    String::from_utf8(bytes?).ok()
+}
@@ -0,0 +1,43 @@
+use std::str::FromStr;
+
+/// The key contacts are listed by, ties being broken by name.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum SortBy {
+    /// By name.
+    #[default]
+    Name,
+    /// By creation time.
+    Created,
+    /// By last update time.
+    Updated,
+    /// By email address.
+    Email,
+}
+
+impl SortBy {
+    /// The name of the key, as parsed by `from_str`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortBy::Name => "name",
+            SortBy::Created => "created",
+            SortBy::Updated => "updated",
+            SortBy::Email => "email",
+        }
+    }
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortBy::Name),
+            "created" => Ok(SortBy::Created),
+            "updated" => Ok(SortBy::Updated),
+            "email" => Ok(SortBy::Email),
+            _ => Err(format!(
+                "Unknown sort key {s}, expected one of name, created, updated, email"
+            )),
+        }
+    }
+}
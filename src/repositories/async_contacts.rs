@@ -0,0 +1,687 @@
+//! The non-blocking counterpart of `ContactsRepository`, for services that
+//! serve many callers at once, and the adapters between the two: a blocking
+//! repository used from async code, and an async one used from blocking code
+//! such as the REPL.
+
+#[cfg(feature = "json")]
+use crate::atomic_file::WriteOptions;
+#[cfg(feature = "json")]
+use crate::codec::{read_valid_contacts_file, write_contacts_file};
+#[cfg(feature = "json")]
+use crate::crypto::{AsyncDecryptionProvider, DecryptionProvider, Encryption};
+use crate::models::{change::Change, contact::Contact, link::Link, link::LinkKind};
+use crate::models::{page::Page, sort_by::SortBy};
+use crate::repositories::contacts::{with_link, ContactsRepository};
+use crate::repositories::migrations::{MigrationStatus, MigrationStep};
+use async_trait::async_trait;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use tokio::runtime::{Builder, Runtime};
+
+/// Every method of `ContactsRepository`, with the same meaning, as a future.
+#[async_trait]
+pub trait AsyncContactsRepository: Send + Sync {
+    /// See `ContactsRepository::add`.
+    async fn add(
+        &mut self,
+        name: String,
+        phone_no_as_string: String,
+        email: String,
+    ) -> Result<(), String>;
+
+    /// See `ContactsRepository::update_email`.
+    async fn update_email(&mut self, name: &str, new_email: String) -> Result<(), String>;
+
+    /// See `ContactsRepository::update_phone_no`.
+    async fn update_phone_no(
+        &mut self,
+        name: &str,
+        new_phone_no_as_string: String,
+    ) -> Result<(), String>;
+
+    /// See `ContactsRepository::update_links`.
+    async fn update_links(&mut self, name: &str, links: Vec<Link>) -> Result<(), String>;
+
+    /// See `ContactsRepository::delete`.
+    async fn delete(&mut self, name: &str) -> Result<(), String>;
+
+    /// See `ContactsRepository::restore`.
+    async fn restore(&mut self, name: &str) -> Result<(), String>;
+
+    /// See `ContactsRepository::get`.
+    async fn get(&self, name: &str) -> Result<Option<Contact>, String>;
+
+    /// See `ContactsRepository::list`.
+    async fn list(
+        &self,
+        page_no: usize,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Vec<Contact>, String>;
+
+    /// See `ContactsRepository::list_page`.
+    async fn list_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Page, String>;
+
+    /// See `ContactsRepository::list_names`.
+    async fn list_names(&self) -> Result<Vec<String>, String> {
+        Ok(self
+            .list(0, usize::MAX, SortBy::Name, false)
+            .await?
+            .into_iter()
+            .map(|c| c.name)
+            .collect())
+    }
+
+    /// See `ContactsRepository::recent`.
+    async fn recent(&self, limit: usize) -> Result<Vec<Contact>, String> {
+        self.list(0, limit, SortBy::Updated, true).await
+    }
+
+    /// See `ContactsRepository::list_trash`.
+    async fn list_trash(&self) -> Result<Vec<Contact>, String>;
+
+    /// See `ContactsRepository::purge_trash`.
+    async fn purge_trash(&mut self, deleted_before: Option<i64>) -> Result<usize, String>;
+
+    /// Reads the contacts without blocking, the file itself is written in
+    /// place.
+    #[cfg(feature = "json")]
+    async fn export_to_json(
+        &self,
+        file_path: String,
+        include_trash: bool,
+        encryption: Option<&Encryption>,
+        options: WriteOptions,
+    ) -> Result<(), String> {
+        let mut contacts: Vec<Contact> = self.list(0, usize::MAX, SortBy::Name, false).await?;
+        if include_trash {
+            contacts.extend(self.list_trash().await?);
+        }
+        write_contacts_file(&file_path, &contacts, encryption, options)
+    }
+
+    /// Reads and checks the file in place, then imports without blocking.
+    #[cfg(feature = "json")]
+    async fn import_from_json(
+        &mut self,
+        path: String,
+        decryption: &mut AsyncDecryptionProvider,
+    ) -> Result<Vec<String>, String> {
+        let (contacts, rejected) = read_valid_contacts_file(&path, decryption)?;
+        self.import_contacts(contacts).await?;
+        Ok(rejected)
+    }
+
+    /// See `ContactsRepository::import_contacts`.
+    async fn import_contacts(&mut self, contacts: Vec<Contact>) -> Result<(), String>;
+
+    /// See `ContactsRepository::replace_all`.
+    async fn replace_all(&mut self, contacts: Vec<Contact>) -> Result<(), String>;
+
+    /// See `ContactsRepository::replace_books`.
+    async fn replace_books(&mut self, books: BTreeMap<String, Vec<Contact>>) -> Result<(), String>;
+
+    /// See `ContactsRepository::count`.
+    async fn count(&self) -> Result<usize, String>;
+
+    /// See `ContactsRepository::history`.
+    async fn history(&self, name: &str) -> Result<Vec<Change>, String>;
+
+    /// See `ContactsRepository::audit`.
+    async fn audit(&self, since: Option<i64>) -> Result<Vec<Change>, String>;
+
+    /// See `ContactsRepository::current_book`.
+    fn current_book(&self) -> String;
+
+    /// See `ContactsRepository::use_book`.
+    async fn use_book(&mut self, book: &str) -> Result<(), String>;
+
+    /// See `ContactsRepository::create_book`.
+    async fn create_book(&mut self, book: &str) -> Result<(), String>;
+
+    /// See `ContactsRepository::delete_book`.
+    async fn delete_book(&mut self, book: &str) -> Result<(), String>;
+
+    /// See `ContactsRepository::list_books`.
+    async fn list_books(&self) -> Result<Vec<String>, String>;
+
+    /// See `ContactsRepository::migration_status`.
+    async fn migration_status(&self) -> Result<MigrationStatus, String>;
+
+    /// See `ContactsRepository::migrate_up`.
+    async fn migrate_up(&mut self) -> Result<Vec<MigrationStep>, String>;
+
+    /// See `ContactsRepository::copy_to_book`.
+    async fn copy_to_book(&mut self, name: &str, book: &str) -> Result<(), String> {
+        let mut contact: Contact = self
+            .get(name)
+            .await?
+            .ok_or(format!("No contact with name {name}"))?;
+        contact.links.clear();
+        let current_book: String = self.current_book();
+        if book == current_book {
+            return Err(format!("{name} is already in the {book} book"));
+        }
+
+        self.use_book(book).await?;
+        let result: Result<(), String> = match self.get(name).await {
+            Ok(Some(_)) => Err(format!("{name} already exists in the {book} book")),
+            Ok(None) => self.import_contacts(vec![contact]).await,
+            Err(err) => Err(err),
+        };
+        self.use_book(&current_book).await?;
+        result
+    }
+
+    /// See `ContactsRepository::move_to_book`.
+    async fn move_to_book(&mut self, name: &str, book: &str) -> Result<(), String> {
+        self.copy_to_book(name, book).await?;
+        self.delete(name).await
+    }
+
+    /// See `ContactsRepository::link`.
+    async fn link(
+        &mut self,
+        name: &str,
+        other: &str,
+        kind: LinkKind,
+        bidirectional: bool,
+    ) -> Result<(), String> {
+        if name == other {
+            return Err(format!("{name} cannot be linked to itself"));
+        }
+        let contact: Contact = self
+            .get(name)
+            .await?
+            .ok_or(format!("No contact with name {name}"))?;
+        let other_contact: Contact = self
+            .get(other)
+            .await?
+            .ok_or(format!("No contact with name {other}"))?;
+
+        self.update_links(name, with_link(contact.links.clone(), other, kind))
+            .await?;
+        if bidirectional {
+            // Undo the first half rather than leave a one way link behind.
+            if let Err(err) = self
+                .update_links(other, with_link(other_contact.links, name, kind.inverse()))
+                .await
+            {
+                return match self.update_links(name, contact.links).await {
+                    Ok(()) => Err(err),
+                    Err(undo_err) => Err(format!(
+                        "{err}, and the link from {name} to {other} could not be undone: {undo_err}"
+                    )),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// See `ContactsRepository::unlink`.
+    async fn unlink(&mut self, name: &str, other: &str) -> Result<(), String> {
+        let mut found: bool = false;
+        for (from, to) in [(name, other), (other, name)] {
+            let contact: Contact = match self.get(from).await? {
+                Some(x) => x,
+                None => continue,
+            };
+            if contact.links.iter().any(|link| link.name == to) {
+                let links: Vec<Link> = contact
+                    .links
+                    .into_iter()
+                    .filter(|link| link.name != to)
+                    .collect();
+                self.update_links(from, links).await?;
+                found = true;
+            }
+        }
+
+        match found {
+            true => Ok(()),
+            false => Err(format!("{name} and {other} are not linked")),
+        }
+    }
+
+    /// See `ContactsRepository::related`.
+    async fn related(&self, name: &str) -> Result<Vec<(LinkKind, Contact)>, String> {
+        let contact: Contact = self
+            .get(name)
+            .await?
+            .ok_or(format!("No contact with name {name}"))?;
+
+        let mut related: Vec<(LinkKind, Contact)> = Vec::new();
+        for link in contact.links {
+            if let Some(other) = self.get(&link.name).await? {
+                related.push((link.kind, other));
+            }
+        }
+        Ok(related)
+    }
+}
+
+/// Serves a blocking repository, such as the in-memory one, to async callers.
+/// Its calls run in turn and block the task making them, which is fine for
+/// a store that never waits on I/O.
+pub struct AsyncContactsAdapter<R: ContactsRepository + Send> {
+    inner: Mutex<R>,
+}
+
+impl<R: ContactsRepository + Send> AsyncContactsAdapter<R> {
+    /// Wraps `inner`, which the adapter owns from then on.
+    pub fn new(inner: R) -> Self {
+        AsyncContactsAdapter {
+            inner: Mutex::new(inner),
+        }
+    }
+
+    /// Gives the wrapped repository back.
+    pub fn into_inner(self) -> R {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, R> {
+        // A call that panicked leaves the repository as usable as any failed
+        // call does.
+        self.inner.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+#[async_trait]
+impl<R: ContactsRepository + Send> AsyncContactsRepository for AsyncContactsAdapter<R> {
+    async fn add(
+        &mut self,
+        name: String,
+        phone_no_as_string: String,
+        email: String,
+    ) -> Result<(), String> {
+        self.lock().add(name, phone_no_as_string, email)
+    }
+
+    async fn update_email(&mut self, name: &str, new_email: String) -> Result<(), String> {
+        self.lock().update_email(name, new_email)
+    }
+
+    async fn update_phone_no(
+        &mut self,
+        name: &str,
+        new_phone_no_as_string: String,
+    ) -> Result<(), String> {
+        self.lock().update_phone_no(name, new_phone_no_as_string)
+    }
+
+    async fn update_links(&mut self, name: &str, links: Vec<Link>) -> Result<(), String> {
+        self.lock().update_links(name, links)
+    }
+
+    async fn delete(&mut self, name: &str) -> Result<(), String> {
+        self.lock().delete(name)
+    }
+
+    async fn restore(&mut self, name: &str) -> Result<(), String> {
+        self.lock().restore(name)
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<Contact>, String> {
+        self.lock().get(name)
+    }
+
+    async fn list(
+        &self,
+        page_no: usize,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Vec<Contact>, String> {
+        self.lock().list(page_no, page_size, sort_by, descending)
+    }
+
+    async fn list_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Page, String> {
+        self.lock()
+            .list_page(cursor, page_size, sort_by, descending)
+    }
+
+    async fn list_names(&self) -> Result<Vec<String>, String> {
+        self.lock().list_names()
+    }
+
+    async fn list_trash(&self) -> Result<Vec<Contact>, String> {
+        self.lock().list_trash()
+    }
+
+    async fn purge_trash(&mut self, deleted_before: Option<i64>) -> Result<usize, String> {
+        self.lock().purge_trash(deleted_before)
+    }
+
+    async fn import_contacts(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        self.lock().import_contacts(contacts)
+    }
+
+    async fn replace_all(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        self.lock().replace_all(contacts)
+    }
+
+    async fn replace_books(&mut self, books: BTreeMap<String, Vec<Contact>>) -> Result<(), String> {
+        self.lock().replace_books(books)
+    }
+
+    async fn count(&self) -> Result<usize, String> {
+        self.lock().count()
+    }
+
+    async fn history(&self, name: &str) -> Result<Vec<Change>, String> {
+        self.lock().history(name)
+    }
+
+    async fn audit(&self, since: Option<i64>) -> Result<Vec<Change>, String> {
+        self.lock().audit(since)
+    }
+
+    fn current_book(&self) -> String {
+        self.lock().current_book()
+    }
+
+    async fn use_book(&mut self, book: &str) -> Result<(), String> {
+        self.lock().use_book(book)
+    }
+
+    async fn create_book(&mut self, book: &str) -> Result<(), String> {
+        self.lock().create_book(book)
+    }
+
+    async fn delete_book(&mut self, book: &str) -> Result<(), String> {
+        self.lock().delete_book(book)
+    }
+
+    async fn list_books(&self) -> Result<Vec<String>, String> {
+        self.lock().list_books()
+    }
+
+    async fn migration_status(&self) -> Result<MigrationStatus, String> {
+        self.lock().migration_status()
+    }
+
+    async fn migrate_up(&mut self) -> Result<Vec<MigrationStep>, String> {
+        self.lock().migrate_up()
+    }
+}
+
+/// Serves an async repository to blocking callers, running each call to
+/// completion on a runtime of its own. It must not be used from async code,
+/// whose runtime cannot be blocked on.
+pub struct BlockingContactsAdapter<R: AsyncContactsRepository> {
+    inner: R,
+    runtime: Runtime,
+}
+
+impl<R: AsyncContactsRepository> BlockingContactsAdapter<R> {
+    /// Wraps `inner`, failing if the runtime cannot be started.
+    pub fn new(inner: R) -> Result<Self, String> {
+        let runtime: Runtime = Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok(BlockingContactsAdapter { inner, runtime })
+    }
+
+    /// The wrapped repository.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+}
+
+impl<R: AsyncContactsRepository> ContactsRepository for BlockingContactsAdapter<R> {
+    fn add(
+        &mut self,
+        name: String,
+        phone_no_as_string: String,
+        email: String,
+    ) -> Result<(), String> {
+        self.runtime
+            .block_on(self.inner.add(name, phone_no_as_string, email))
+    }
+
+    fn update_email(&mut self, name: &str, new_email: String) -> Result<(), String> {
+        self.runtime
+            .block_on(self.inner.update_email(name, new_email))
+    }
+
+    fn update_phone_no(
+        &mut self,
+        name: &str,
+        new_phone_no_as_string: String,
+    ) -> Result<(), String> {
+        self.runtime
+            .block_on(self.inner.update_phone_no(name, new_phone_no_as_string))
+    }
+
+    fn update_links(&mut self, name: &str, links: Vec<Link>) -> Result<(), String> {
+        self.runtime.block_on(self.inner.update_links(name, links))
+    }
+
+    fn delete(&mut self, name: &str) -> Result<(), String> {
+        self.runtime.block_on(self.inner.delete(name))
+    }
+
+    fn restore(&mut self, name: &str) -> Result<(), String> {
+        self.runtime.block_on(self.inner.restore(name))
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Contact>, String> {
+        self.runtime.block_on(self.inner.get(name))
+    }
+
+    fn list(
+        &self,
+        page_no: usize,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Vec<Contact>, String> {
+        self.runtime
+            .block_on(self.inner.list(page_no, page_size, sort_by, descending))
+    }
+
+    fn list_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Page, String> {
+        self.runtime
+            .block_on(self.inner.list_page(cursor, page_size, sort_by, descending))
+    }
+
+    fn list_names(&self) -> Result<Vec<String>, String> {
+        self.runtime.block_on(self.inner.list_names())
+    }
+
+    fn recent(&self, limit: usize) -> Result<Vec<Contact>, String> {
+        self.runtime.block_on(self.inner.recent(limit))
+    }
+
+    fn list_trash(&self) -> Result<Vec<Contact>, String> {
+        self.runtime.block_on(self.inner.list_trash())
+    }
+
+    fn purge_trash(&mut self, deleted_before: Option<i64>) -> Result<usize, String> {
+        self.runtime
+            .block_on(self.inner.purge_trash(deleted_before))
+    }
+
+    #[cfg(feature = "json")]
+    fn export_to_json(
+        &self,
+        file_path: String,
+        include_trash: bool,
+        encryption: Option<&Encryption>,
+        options: WriteOptions,
+    ) -> Result<(), String> {
+        self.runtime.block_on(self.inner.export_to_json(
+            file_path,
+            include_trash,
+            encryption,
+            options,
+        ))
+    }
+
+    /// Reads the file before blocking on the import, as the decryption prompt
+    /// cannot be sent to the runtime.
+    #[cfg(feature = "json")]
+    fn import_from_json(
+        &mut self,
+        path: String,
+        decryption: &mut DecryptionProvider,
+    ) -> Result<Vec<String>, String> {
+        let (contacts, rejected) = read_valid_contacts_file(&path, decryption)?;
+        self.import_contacts(contacts)?;
+        Ok(rejected)
+    }
+
+    fn import_contacts(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        self.runtime.block_on(self.inner.import_contacts(contacts))
+    }
+
+    fn replace_all(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        self.runtime.block_on(self.inner.replace_all(contacts))
+    }
+
+    fn replace_books(&mut self, books: BTreeMap<String, Vec<Contact>>) -> Result<(), String> {
+        self.runtime.block_on(self.inner.replace_books(books))
+    }
+
+    fn count(&self) -> Result<usize, String> {
+        self.runtime.block_on(self.inner.count())
+    }
+
+    fn history(&self, name: &str) -> Result<Vec<Change>, String> {
+        self.runtime.block_on(self.inner.history(name))
+    }
+
+    fn audit(&self, since: Option<i64>) -> Result<Vec<Change>, String> {
+        self.runtime.block_on(self.inner.audit(since))
+    }
+
+    fn current_book(&self) -> String {
+        self.inner.current_book()
+    }
+
+    fn use_book(&mut self, book: &str) -> Result<(), String> {
+        self.runtime.block_on(self.inner.use_book(book))
+    }
+
+    fn create_book(&mut self, book: &str) -> Result<(), String> {
+        self.runtime.block_on(self.inner.create_book(book))
+    }
+
+    fn delete_book(&mut self, book: &str) -> Result<(), String> {
+        self.runtime.block_on(self.inner.delete_book(book))
+    }
+
+    fn list_books(&self) -> Result<Vec<String>, String> {
+        self.runtime.block_on(self.inner.list_books())
+    }
+
+    fn migration_status(&self) -> Result<MigrationStatus, String> {
+        self.runtime.block_on(self.inner.migration_status())
+    }
+
+    fn migrate_up(&mut self) -> Result<Vec<MigrationStep>, String> {
+        self.runtime.block_on(self.inner.migrate_up())
+    }
+
+    fn copy_to_book(&mut self, name: &str, book: &str) -> Result<(), String> {
+        self.runtime.block_on(self.inner.copy_to_book(name, book))
+    }
+
+    fn move_to_book(&mut self, name: &str, book: &str) -> Result<(), String> {
+        self.runtime.block_on(self.inner.move_to_book(name, book))
+    }
+
+    fn link(
+        &mut self,
+        name: &str,
+        other: &str,
+        kind: LinkKind,
+        bidirectional: bool,
+    ) -> Result<(), String> {
+        self.runtime
+            .block_on(self.inner.link(name, other, kind, bidirectional))
+    }
+
+    fn unlink(&mut self, name: &str, other: &str) -> Result<(), String> {
+        self.runtime.block_on(self.inner.unlink(name, other))
+    }
+
+    fn related(&self, name: &str) -> Result<Vec<(LinkKind, Contact)>, String> {
+        self.runtime.block_on(self.inner.related(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::conformance::check_conformance;
+    use crate::repositories::inmemory_contacts::InMemoryContactsRepository;
+
+    #[test]
+    fn test_adapters_conformance() {
+        let mut contacts_service: BlockingContactsAdapter<
+            AsyncContactsAdapter<InMemoryContactsRepository>,
+        > = BlockingContactsAdapter::new(AsyncContactsAdapter::new(
+            InMemoryContactsRepository::new(),
+        ))
+        .unwrap();
+        check_conformance(&mut contacts_service);
+    }
+
+    #[test]
+    fn test_async_adapter_concurrent_reads() {
+        let runtime: Runtime = Builder::new_current_thread().build().unwrap();
+        let mut contacts_service: AsyncContactsAdapter<InMemoryContactsRepository> =
+            AsyncContactsAdapter::new(InMemoryContactsRepository::new());
+        runtime.block_on(async {
+            for (name, phone_no) in [("Ana", "491234567890"), ("Bogdan", "491234567891")] {
+                contacts_service
+                    .add(
+                        name.to_string(),
+                        phone_no.to_string(),
+                        format!("{}@mail.com", name.to_lowercase()),
+                    )
+                    .await
+                    .unwrap();
+            }
+            contacts_service
+                .link("Ana", "Bogdan", LinkKind::Manager, true)
+                .await
+                .unwrap();
+
+            let (ana, count, related) = tokio::join!(
+                contacts_service.get("Ana"),
+                contacts_service.count(),
+                contacts_service.related("Bogdan"),
+            );
+            assert_eq!(1, ana.unwrap().unwrap().links.len());
+            assert_eq!(2, count.unwrap());
+            assert_eq!(LinkKind::Report, related.unwrap()[0].0);
+        });
+        let contacts_service: InMemoryContactsRepository = contacts_service.into_inner();
+        assert_eq!(2, contacts_service.count().unwrap());
+    }
+}
@@ -0,0 +1,953 @@
+//! The Redis backend. It shares one multiplexed connection between concurrent
+//! calls instead of holding one connection per call, and serves blocking
+//! callers as `DbContactsRepository`.
+
+use crate::{
+    models::{
+        change::{diff_fields, Change, Operation},
+        contact::Contact,
+        link::{Link, LinkKind},
+        page::{Cursor, Direction, Page},
+        sort_by::SortBy,
+    },
+    repositories::async_contacts::AsyncContactsRepository,
+    repositories::contacts::DEFAULT_BOOK,
+    repositories::migrations::{get_status, MigrationStatus, MigrationStep},
+    repositories::redis_connection::{
+        AsyncPersistentConnection, AsyncRedisConnection, RedisOptions, RedisTarget,
+    },
+};
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::contacts::{
+    get_current_timestamp, get_cursor, get_valid_book_name, get_valid_email, get_valid_name,
+    get_valid_phone_no, paginate, remove_links_to, sort_contacts, with_link,
+};
+use super::db_contacts::{
+    add_change, add_replace_all, add_update_links, add_write_contact, get_audit_start,
+    get_delete_book_pipe, get_delete_pipe, get_fill_backlinks_pipe, get_fill_index_pipe,
+    get_range_index_cmd, get_restore_pipe, get_update_cmd, parse_changes, parse_contact,
+    select_purged, with_timestamps, RedisKeys, RedisStore, REDIS_MIGRATIONS, REDIS_SUBKEY_EMAIL,
+    REDIS_SUBKEY_PHONE_NO,
+};
+
+/// Contacts stored in Redis, served to concurrent async callers.
+pub struct AsyncDbContactsRepository {
+    redis_connection: AsyncPersistentConnection,
+    key_prefix: String,
+    hash_tag: bool,
+    book: String,
+    keys: RedisKeys,
+}
+
+impl AsyncDbContactsRepository {
+    /// Opens the server, Sentinel master or cluster configured in the
+    /// environment, see `RedisTarget::from_env` and `RedisOptions::from_env`.
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self::open_target(
+            RedisTarget::from_env()?,
+            RedisOptions::from_env()?,
+        ))
+    }
+
+    /// Opens the repository without connecting yet; the connection is made on
+    /// the first call and shared by the following ones.
+    pub fn open_target(target: RedisTarget, options: RedisOptions) -> Self {
+        let hash_tag: bool = target.is_cluster();
+        let key_prefix: String = options.key_prefix.clone();
+        AsyncDbContactsRepository {
+            redis_connection: AsyncPersistentConnection::new(target, options, &key_prefix),
+            keys: RedisKeys::new(&key_prefix, DEFAULT_BOOK, hash_tag),
+            key_prefix,
+            hash_tag,
+            book: DEFAULT_BOOK.to_string(),
+        }
+    }
+
+    /// The store behind this repository, through a blocking connection of its
+    /// own.
+    pub(super) fn get_store(&self) -> RedisStore {
+        RedisStore::new(
+            self.redis_connection.to_blocking(),
+            &self.key_prefix,
+            self.hash_tag,
+        )
+    }
+
+    async fn get_redis_connection(&self) -> Result<AsyncRedisConnection, String> {
+        self.redis_connection.get().await
+    }
+}
+
+async fn exists(redis_connection: &mut AsyncRedisConnection, key: &str) -> Result<bool, String> {
+    redis::cmd("EXISTS")
+        .arg(key)
+        .query_async::<_, bool>(redis_connection)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+async fn read_contact(
+    redis_connection: &mut AsyncRedisConnection,
+    key: &str,
+    name: &str,
+) -> Result<Option<Contact>, String> {
+    let values: HashMap<String, String> = redis::cmd("HGETALL")
+        .arg(key)
+        .query_async::<_, HashMap<String, String>>(redis_connection)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    parse_contact(key, name, values)
+}
+
+async fn read_keys(
+    redis_connection: &mut AsyncRedisConnection,
+    pattern: &str,
+) -> Result<Vec<String>, String> {
+    redis::cmd("KEYS")
+        .arg(pattern)
+        .query_async::<_, Vec<String>>(redis_connection)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+async fn read_all_contacts(
+    redis_connection: &mut AsyncRedisConnection,
+    key_prefix: &str,
+) -> Result<BTreeMap<String, Contact>, String> {
+    let keys: Vec<String> = read_keys(redis_connection, &format!("{key_prefix}:*")).await?;
+
+    let mut map: BTreeMap<String, Contact> = BTreeMap::new();
+
+    for key in keys {
+        let name: &str = &key[key_prefix.len() + 1..];
+        if let Some(contact) = read_contact(redis_connection, &key, name).await? {
+            map.insert(name.to_string(), contact);
+        }
+    }
+
+    Ok(map)
+}
+
+async fn is_book(
+    redis_connection: &mut AsyncRedisConnection,
+    keys: &RedisKeys,
+    book: &str,
+) -> Result<bool, String> {
+    if book == DEFAULT_BOOK {
+        return Ok(true);
+    }
+    redis::cmd("SISMEMBER")
+        .arg(&keys.books)
+        .arg(book)
+        .query_async::<_, bool>(redis_connection)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+async fn read_changes(
+    redis_connection: &mut AsyncRedisConnection,
+    keys: &RedisKeys,
+    start: &str,
+) -> Result<Vec<Change>, String> {
+    let entries: Vec<redis::Value> = redis::cmd("XRANGE")
+        .arg(&keys.audit)
+        .arg(start)
+        .arg("+")
+        .query_async::<_, Vec<redis::Value>>(redis_connection)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    parse_changes(&entries)
+}
+
+async fn query_pipe(
+    redis_connection: &mut AsyncRedisConnection,
+    pipe: &redis::Pipeline,
+) -> Result<(), String> {
+    pipe.query_async::<_, ()>(redis_connection)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Adds the commands replacing all the contacts of the book `keys` belong to
+/// with `contacts`, and logging the changes.
+async fn add_replace_book(
+    redis_connection: &mut AsyncRedisConnection,
+    pipe: &mut redis::Pipeline,
+    keys: &RedisKeys,
+    contacts: Vec<Contact>,
+) -> Result<(), String> {
+    let mut old: BTreeMap<String, Contact> =
+        read_all_contacts(redis_connection, &keys.contacts).await?;
+    let trash: BTreeMap<String, Contact> = read_all_contacts(redis_connection, &keys.trash).await?;
+    let backlinks: Vec<String> = read_keys(redis_connection, &keys.backlinks_of("*")).await?;
+
+    add_replace_all(pipe, keys, &old, &trash, &backlinks, &contacts);
+    old.extend(trash);
+
+    let new: BTreeMap<String, Contact> = contacts
+        .into_iter()
+        .map(|contact| (contact.name.clone(), contact))
+        .collect();
+    let names: BTreeSet<String> = old.keys().chain(new.keys()).cloned().collect();
+    for name in names {
+        if !diff_fields(old.get(&name), new.get(&name)).is_empty() {
+            add_change(
+                pipe,
+                keys,
+                Operation::RestoreBackup,
+                &name,
+                old.get(&name),
+                new.get(&name),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Fills the sorted set of live contact names from the contacts themselves,
+/// once per book. Every write adds to the index, so for data written before
+/// it was maintained the index can exist and still miss older contacts; the
+/// `_indexed` marker records that it was filled.
+async fn ensure_index(
+    redis_connection: &mut AsyncRedisConnection,
+    keys: &RedisKeys,
+) -> Result<(), String> {
+    if exists(redis_connection, &keys.indexed).await? {
+        return Ok(());
+    }
+
+    let contact_keys: Vec<String> = read_keys(redis_connection, &keys.contact("*")).await?;
+
+    get_fill_index_pipe(keys, &contact_keys)
+        .query_async::<_, ()>(redis_connection)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+async fn range_index(
+    redis_connection: &mut AsyncRedisConnection,
+    keys: &RedisKeys,
+    forward: bool,
+    bound: Option<&str>,
+    limit: usize,
+) -> Result<Vec<String>, String> {
+    get_range_index_cmd(keys, forward, bound, limit)
+        .query_async::<_, Vec<String>>(redis_connection)
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Fills the backlinks of every contact from the links of the others, once
+/// per book, like `ensure_index` fills the index. The backlinks may name
+/// contacts that no longer link to the contact, never miss one that does.
+async fn ensure_backlinks(
+    redis_connection: &mut AsyncRedisConnection,
+    keys: &RedisKeys,
+) -> Result<(), String> {
+    if exists(redis_connection, &keys.backlinked).await? {
+        return Ok(());
+    }
+
+    let contacts: Vec<Contact> = read_all_contacts(redis_connection, &keys.contacts)
+        .await?
+        .into_values()
+        .collect();
+
+    query_pipe(redis_connection, &get_fill_backlinks_pipe(keys, &contacts)).await
+}
+
+/// The live contacts in the backlinks of `name`, other than itself.
+async fn read_backlinked_contacts(
+    redis_connection: &mut AsyncRedisConnection,
+    keys: &RedisKeys,
+    name: &str,
+) -> Result<Vec<Contact>, String> {
+    ensure_backlinks(redis_connection, keys).await?;
+
+    let names: BTreeSet<String> = redis::cmd("SMEMBERS")
+        .arg(keys.backlinks_of(name))
+        .query_async::<_, BTreeSet<String>>(redis_connection)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let mut contacts: Vec<Contact> = Vec::new();
+    for other in names.iter().filter(|other| *other != name) {
+        if let Some(contact) = read_contact(redis_connection, &keys.contact(other), other).await? {
+            contacts.push(contact);
+        }
+    }
+    Ok(contacts)
+}
+
+/// Pages by name straight from the index, reading only the contacts on the page.
+async fn list_page_by_name(
+    redis_connection: &mut AsyncRedisConnection,
+    keys: &RedisKeys,
+    cursor: Option<&Cursor>,
+    page_size: usize,
+    descending: bool,
+) -> Result<Page, String> {
+    ensure_index(redis_connection, keys).await?;
+
+    let direction: Direction = cursor.map(|c| c.direction).unwrap_or(Direction::After);
+    let bound: Option<&str> = cursor.map(|c| c.name.as_str());
+    let forward: bool = (direction == Direction::After) != descending;
+
+    let mut names: Vec<String> =
+        range_index(redis_connection, keys, forward, bound, page_size).await?;
+    if direction == Direction::Before {
+        names.reverse();
+    }
+
+    let mut contacts: Vec<Contact> = Vec::new();
+    for name in &names {
+        if let Some(contact) = read_contact(redis_connection, &keys.contact(name), name).await? {
+            contacts.push(contact);
+        }
+    }
+
+    let next_cursor: Option<String> = match contacts.last() {
+        Some(last)
+            if !range_index(redis_connection, keys, !descending, Some(&last.name), 1)
+                .await?
+                .is_empty() =>
+        {
+            Some(get_cursor(last, SortBy::Name, descending, Direction::After))
+        }
+        _ => None,
+    };
+    let prev_cursor: Option<String> = match contacts.first() {
+        Some(first)
+            if !range_index(redis_connection, keys, descending, Some(&first.name), 1)
+                .await?
+                .is_empty() =>
+        {
+            Some(get_cursor(
+                first,
+                SortBy::Name,
+                descending,
+                Direction::Before,
+            ))
+        }
+        _ => None,
+    };
+
+    Ok(Page {
+        contacts,
+        next_cursor,
+        prev_cursor,
+    })
+}
+
+#[async_trait]
+impl AsyncContactsRepository for AsyncDbContactsRepository {
+    async fn add(
+        &mut self,
+        name: String,
+        phone_no_as_string: String,
+        email: String,
+    ) -> Result<(), String> {
+        let name: String = get_valid_name(&name)?;
+        let email: String = get_valid_email(&email)?;
+        let phone_no: u64 = get_valid_phone_no(&phone_no_as_string)?;
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let key: String = self.keys.contact(&name);
+        let old: Option<Contact> = read_contact(&mut redis_connection, &key, &name).await?;
+        let now: i64 = get_current_timestamp();
+        let new: Contact = Contact {
+            name: name.clone(),
+            phone_no,
+            email,
+            created_at: old.as_ref().map(|x| x.created_at).unwrap_or(now),
+            updated_at: now,
+            deleted_at: None,
+            links: old.as_ref().map(|x| x.links.clone()).unwrap_or_default(),
+        };
+
+        let mut pipe: redis::Pipeline = redis::pipe();
+        pipe.atomic();
+        add_write_contact(&mut pipe, &self.keys, &key, &new);
+        add_change(
+            &mut pipe,
+            &self.keys,
+            Operation::Add,
+            &name,
+            old.as_ref(),
+            Some(&new),
+        )?;
+        query_pipe(&mut redis_connection, &pipe).await
+    }
+
+    async fn update_email(&mut self, name: &str, new_email: String) -> Result<(), String> {
+        let new_email: String = get_valid_email(&new_email)?;
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let key: String = self.keys.contact(name);
+        let old: Contact = match read_contact(&mut redis_connection, &key, name).await? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let now: i64 = get_current_timestamp();
+
+        let mut pipe: redis::Pipeline = redis::pipe();
+        pipe.atomic()
+            .add_command(get_update_cmd(&key, REDIS_SUBKEY_EMAIL, &new_email, now))
+            .ignore();
+        let new: Contact = Contact {
+            email: new_email,
+            updated_at: now,
+            ..old.clone()
+        };
+        add_change(
+            &mut pipe,
+            &self.keys,
+            Operation::UpdateEmail,
+            name,
+            Some(&old),
+            Some(&new),
+        )?;
+        query_pipe(&mut redis_connection, &pipe).await
+    }
+
+    async fn update_phone_no(
+        &mut self,
+        name: &str,
+        new_phone_no_as_string: String,
+    ) -> Result<(), String> {
+        let new_phone_no: u64 = get_valid_phone_no(&new_phone_no_as_string)?;
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let key: String = self.keys.contact(name);
+        let old: Contact = match read_contact(&mut redis_connection, &key, name).await? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let now: i64 = get_current_timestamp();
+
+        let mut pipe: redis::Pipeline = redis::pipe();
+        pipe.atomic()
+            .add_command(get_update_cmd(
+                &key,
+                REDIS_SUBKEY_PHONE_NO,
+                new_phone_no,
+                now,
+            ))
+            .ignore();
+        let new: Contact = Contact {
+            phone_no: new_phone_no,
+            updated_at: now,
+            ..old.clone()
+        };
+        add_change(
+            &mut pipe,
+            &self.keys,
+            Operation::UpdatePhoneNo,
+            name,
+            Some(&old),
+            Some(&new),
+        )?;
+        query_pipe(&mut redis_connection, &pipe).await
+    }
+
+    async fn update_links(&mut self, name: &str, links: Vec<Link>) -> Result<(), String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let key: String = self.keys.contact(name);
+        let old: Contact = read_contact(&mut redis_connection, &key, name)
+            .await?
+            .ok_or(format!("No contact with name {name}"))?;
+        let new: Contact = Contact {
+            links,
+            updated_at: get_current_timestamp(),
+            ..old.clone()
+        };
+
+        let mut pipe: redis::Pipeline = redis::pipe();
+        pipe.atomic();
+        add_update_links(&mut pipe, &self.keys, &old, &new);
+        add_change(
+            &mut pipe,
+            &self.keys,
+            Operation::UpdateLinks,
+            name,
+            Some(&old),
+            Some(&new),
+        )?;
+        query_pipe(&mut redis_connection, &pipe).await
+    }
+
+    /// Writes both directions of the link in one transaction.
+    async fn link(
+        &mut self,
+        name: &str,
+        other: &str,
+        kind: LinkKind,
+        bidirectional: bool,
+    ) -> Result<(), String> {
+        if name == other {
+            return Err(format!("{name} cannot be linked to itself"));
+        }
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let contact: Contact = read_contact(&mut redis_connection, &self.keys.contact(name), name)
+            .await?
+            .ok_or(format!("No contact with name {name}"))?;
+        let other_contact: Contact =
+            read_contact(&mut redis_connection, &self.keys.contact(other), other)
+                .await?
+                .ok_or(format!("No contact with name {other}"))?;
+
+        let mut updates: Vec<(Contact, Vec<Link>)> = Vec::new();
+        let links: Vec<Link> = with_link(contact.links.clone(), other, kind);
+        updates.push((contact, links));
+        if bidirectional {
+            let links: Vec<Link> = with_link(other_contact.links.clone(), name, kind.inverse());
+            updates.push((other_contact, links));
+        }
+
+        let now: i64 = get_current_timestamp();
+        let mut pipe: redis::Pipeline = redis::pipe();
+        pipe.atomic();
+        for (old, links) in updates {
+            let new: Contact = Contact {
+                links,
+                updated_at: now,
+                ..old.clone()
+            };
+            add_update_links(&mut pipe, &self.keys, &old, &new);
+            add_change(
+                &mut pipe,
+                &self.keys,
+                Operation::UpdateLinks,
+                &old.name,
+                Some(&old),
+                Some(&new),
+            )?;
+        }
+        query_pipe(&mut redis_connection, &pipe).await
+    }
+
+    /// Drops the links to the contact from the contacts in its backlinks, in
+    /// the transaction moving it to the trash.
+    async fn delete(&mut self, name: &str) -> Result<(), String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let key: String = self.keys.contact(name);
+
+        let old: Contact = match read_contact(&mut redis_connection, &key, name).await? {
+            Some(x) => x,
+            None => return Err(format!("No contact with name {name}")),
+        };
+        let linking: Vec<Contact> =
+            read_backlinked_contacts(&mut redis_connection, &self.keys, name).await?;
+        let deleted_at: i64 = get_current_timestamp();
+
+        let mut pipe: redis::Pipeline = get_delete_pipe(&self.keys, &old, deleted_at);
+        let new: Contact = Contact {
+            deleted_at: Some(deleted_at),
+            links: Vec::new(),
+            ..old.clone()
+        };
+        add_change(
+            &mut pipe,
+            &self.keys,
+            Operation::Delete,
+            name,
+            Some(&old),
+            Some(&new),
+        )?;
+        for (old, new) in remove_links_to(linking, name) {
+            add_update_links(&mut pipe, &self.keys, &old, &new);
+            add_change(
+                &mut pipe,
+                &self.keys,
+                Operation::UpdateLinks,
+                &new.name,
+                Some(&old),
+                Some(&new),
+            )?;
+        }
+        query_pipe(&mut redis_connection, &pipe).await
+    }
+
+    async fn restore(&mut self, name: &str) -> Result<(), String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let key: String = self.keys.contact(name);
+        let trash_key: String = self.keys.trashed_contact(name);
+
+        if exists(&mut redis_connection, &key).await? {
+            return Err(format!("A contact with name {name} already exists"));
+        }
+        let old: Contact = match read_contact(&mut redis_connection, &trash_key, name).await? {
+            Some(x) => x,
+            None => return Err(format!("No contact with name {name} in the trash")),
+        };
+
+        let mut pipe: redis::Pipeline = get_restore_pipe(&self.keys, name);
+        let new: Contact = Contact {
+            deleted_at: None,
+            ..old.clone()
+        };
+        add_change(
+            &mut pipe,
+            &self.keys,
+            Operation::Restore,
+            name,
+            Some(&old),
+            Some(&new),
+        )?;
+        query_pipe(&mut redis_connection, &pipe).await
+    }
+
+    async fn get(&self, name: &str) -> Result<Option<Contact>, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let key: String = self.keys.contact(name);
+
+        read_contact(&mut redis_connection, &key, name).await
+    }
+
+    async fn list(
+        &self,
+        page_no: usize,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Vec<Contact>, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let mut contacts: Vec<Contact> =
+            read_all_contacts(&mut redis_connection, &self.keys.contacts)
+                .await?
+                .into_values()
+                .collect();
+        sort_contacts(&mut contacts, sort_by, descending);
+
+        Ok(contacts
+            .into_iter()
+            .skip(page_no * page_size)
+            .take(page_size)
+            .collect())
+    }
+
+    async fn list_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Page, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let decoded_cursor: Option<Cursor> = cursor.map(Cursor::decode).transpose()?;
+        let (sort_by, descending) = match &decoded_cursor {
+            Some(c) => (c.sort_by, c.descending),
+            None => (sort_by, descending),
+        };
+
+        if sort_by == SortBy::Name {
+            return list_page_by_name(
+                &mut redis_connection,
+                &self.keys,
+                decoded_cursor.as_ref(),
+                page_size,
+                descending,
+            )
+            .await;
+        }
+
+        let contacts: Vec<Contact> = read_all_contacts(&mut redis_connection, &self.keys.contacts)
+            .await?
+            .into_values()
+            .collect();
+        paginate(contacts, cursor, page_size, sort_by, descending)
+    }
+
+    async fn list_names(&self) -> Result<Vec<String>, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        ensure_index(&mut redis_connection, &self.keys).await?;
+
+        redis::cmd("ZRANGEBYLEX")
+            .arg(&self.keys.index)
+            .arg("-")
+            .arg("+")
+            .query_async::<_, Vec<String>>(&mut redis_connection)
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    async fn list_trash(&self) -> Result<Vec<Contact>, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let map: BTreeMap<String, Contact> =
+            read_all_contacts(&mut redis_connection, &self.keys.trash).await?;
+
+        Ok(map.into_values().collect())
+    }
+
+    async fn purge_trash(&mut self, deleted_before: Option<i64>) -> Result<usize, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let map: BTreeMap<String, Contact> =
+            read_all_contacts(&mut redis_connection, &self.keys.trash).await?;
+
+        let purged: Vec<Contact> = select_purged(map, deleted_before);
+
+        for contact in &purged {
+            let mut pipe: redis::Pipeline = redis::pipe();
+            pipe.atomic()
+                .cmd("DEL")
+                .arg(self.keys.trashed_contact(&contact.name))
+                .ignore();
+            add_change(
+                &mut pipe,
+                &self.keys,
+                Operation::Purge,
+                &contact.name,
+                Some(contact),
+                None,
+            )?;
+            query_pipe(&mut redis_connection, &pipe).await?;
+        }
+
+        Ok(purged.len())
+    }
+
+    async fn import_contacts(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+
+        let now: i64 = get_current_timestamp();
+        for contact in contacts {
+            let contact: Contact = with_timestamps(contact, now);
+            let key: String = self.keys.stored_contact(&contact);
+            let old: Option<Contact> =
+                read_contact(&mut redis_connection, &key, &contact.name).await?;
+
+            let mut pipe: redis::Pipeline = redis::pipe();
+            pipe.atomic();
+            add_write_contact(&mut pipe, &self.keys, &key, &contact);
+            add_change(
+                &mut pipe,
+                &self.keys,
+                Operation::Import,
+                &contact.name,
+                old.as_ref(),
+                Some(&contact),
+            )?;
+            query_pipe(&mut redis_connection, &pipe).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn replace_all(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let mut pipe: redis::Pipeline = redis::pipe();
+        pipe.atomic();
+        add_replace_book(&mut redis_connection, &mut pipe, &self.keys, contacts).await?;
+        query_pipe(&mut redis_connection, &pipe).await
+    }
+
+    /// Reads every book before writing any, then swaps them all in one
+    /// transaction.
+    async fn replace_books(&mut self, books: BTreeMap<String, Vec<Contact>>) -> Result<(), String> {
+        for book in books.keys() {
+            get_valid_book_name(book)?;
+        }
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let mut pipe: redis::Pipeline = redis::pipe();
+        pipe.atomic();
+        for (book, contacts) in books {
+            if book != DEFAULT_BOOK {
+                pipe.cmd("SADD").arg(&self.keys.books).arg(&book).ignore();
+            }
+            let keys: RedisKeys = RedisKeys::new(&self.key_prefix, &book, self.hash_tag);
+            add_replace_book(&mut redis_connection, &mut pipe, &keys, contacts).await?;
+        }
+        query_pipe(&mut redis_connection, &pipe).await
+    }
+
+    /// Counts the index rather than the contact keys, so no key is scanned.
+    async fn count(&self) -> Result<usize, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        ensure_index(&mut redis_connection, &self.keys).await?;
+
+        redis::cmd("ZCARD")
+            .arg(&self.keys.index)
+            .query_async::<_, usize>(&mut redis_connection)
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    async fn history(&self, name: &str) -> Result<Vec<Change>, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let changes: Vec<Change> = read_changes(&mut redis_connection, &self.keys, "-").await?;
+
+        Ok(changes
+            .into_iter()
+            .filter(|change| change.name == name)
+            .collect())
+    }
+
+    async fn audit(&self, since: Option<i64>) -> Result<Vec<Change>, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+
+        read_changes(&mut redis_connection, &self.keys, &get_audit_start(since)).await
+    }
+
+    fn current_book(&self) -> String {
+        self.book.clone()
+    }
+
+    async fn use_book(&mut self, book: &str) -> Result<(), String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        if !is_book(&mut redis_connection, &self.keys, book).await? {
+            return Err(format!("No book with name {book}"));
+        }
+        self.book = book.to_string();
+        self.keys = RedisKeys::new(&self.key_prefix, book, self.hash_tag);
+        Ok(())
+    }
+
+    async fn create_book(&mut self, book: &str) -> Result<(), String> {
+        let book: String = get_valid_book_name(book)?;
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        if is_book(&mut redis_connection, &self.keys, &book).await? {
+            return Err(format!("Book {book} already exists"));
+        }
+
+        redis::cmd("SADD")
+            .arg(&self.keys.books)
+            .arg(&book)
+            .query_async::<_, ()>(&mut redis_connection)
+            .await
+            .map_err(|err| err.to_string())
+    }
+
+    async fn delete_book(&mut self, book: &str) -> Result<(), String> {
+        if book == DEFAULT_BOOK {
+            return Err("The default book cannot be deleted".to_string());
+        }
+        if book == self.book {
+            return Err(format!(
+                "Book {book} is in use, switch to another one first"
+            ));
+        }
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        if !is_book(&mut redis_connection, &self.keys, book).await? {
+            return Err(format!("No book with name {book}"));
+        }
+
+        let keys: RedisKeys = RedisKeys::new(&self.key_prefix, book, self.hash_tag);
+        let mut book_keys: Vec<String> =
+            read_keys(&mut redis_connection, &keys.contact("*")).await?;
+        book_keys.extend(read_keys(&mut redis_connection, &keys.trashed_contact("*")).await?);
+        book_keys.extend(read_keys(&mut redis_connection, &keys.backlinks_of("*")).await?);
+        book_keys.push(keys.index);
+        book_keys.push(keys.indexed);
+        book_keys.push(keys.backlinked);
+        book_keys.push(keys.audit);
+
+        query_pipe(
+            &mut redis_connection,
+            &get_delete_book_pipe(&self.keys, book, book_keys),
+        )
+        .await
+    }
+
+    async fn list_books(&self) -> Result<Vec<String>, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let mut books: BTreeSet<String> = redis::cmd("SMEMBERS")
+            .arg(&self.keys.books)
+            .query_async::<_, BTreeSet<String>>(&mut redis_connection)
+            .await
+            .map_err(|err| err.to_string())?;
+        books.insert(DEFAULT_BOOK.to_string());
+        Ok(books.into_iter().collect())
+    }
+
+    async fn migration_status(&self) -> Result<MigrationStatus, String> {
+        let mut redis_connection: AsyncRedisConnection = self.get_redis_connection().await?;
+        let version: Option<u32> = redis::cmd("GET")
+            .arg(&self.keys.schema)
+            .query_async::<_, Option<u32>>(&mut redis_connection)
+            .await
+            .map_err(|err| err.to_string())?;
+        get_status(REDIS_MIGRATIONS, version.unwrap_or(0))
+    }
+
+    /// Runs the steps on the blocking thread pool, through a connection of
+    /// their own.
+    async fn migrate_up(&mut self) -> Result<Vec<MigrationStep>, String> {
+        let mut store: RedisStore = self.get_store();
+        tokio::task::spawn_blocking(move || store.migrate_up())
+            .await
+            .map_err(|err| err.to_string())?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::fake_redis::FakeRedis;
+    use redis::IntoConnectionInfo;
+    use std::time::Duration;
+    use tokio::runtime::{Builder, Runtime};
+
+    fn get_target(redis_url: &str) -> RedisTarget {
+        RedisTarget::Server(redis_url.into_connection_info().unwrap())
+    }
+
+    #[test]
+    fn test_async_db_contacts_service_connection() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        let options: RedisOptions = RedisOptions {
+            read_timeout: Duration::from_millis(200),
+            backoff: Duration::from_millis(10),
+            ..RedisOptions::default()
+        };
+        let mut contacts_service: AsyncDbContactsRepository =
+            AsyncDbContactsRepository::open_target(get_target(&fake_redis.url()), options);
+        let runtime: Runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            for (name, phone_no) in [("Ana", "491234567890"), ("Bogdan", "491234567891")] {
+                contacts_service
+                    .add(
+                        name.to_string(),
+                        phone_no.to_string(),
+                        format!("{}@mail.com", name.to_lowercase()),
+                    )
+                    .await
+                    .unwrap();
+            }
+
+            // Concurrent calls share the one connection.
+            let (ana, bogdan, count) = tokio::join!(
+                contacts_service.get("Ana"),
+                contacts_service.get("Bogdan"),
+                contacts_service.count(),
+            );
+            assert_eq!("Ana", ana.unwrap().unwrap().name);
+            assert_eq!("Bogdan", bogdan.unwrap().unwrap().name);
+            assert_eq!(2, count.unwrap());
+            assert_eq!(1, fake_redis.connections());
+
+            fake_redis.disconnect_next("HGETALL");
+            assert!(contacts_service
+                .get("Ana")
+                .await
+                .err()
+                .unwrap()
+                .starts_with("Lost the connection to Redis"));
+            assert!(contacts_service.get("Ana").await.unwrap().is_some());
+            assert_eq!(2, fake_redis.connections());
+
+            fake_redis.stall_next("HGETALL", Duration::from_millis(500));
+            assert!(contacts_service
+                .get("Ana")
+                .await
+                .err()
+                .unwrap()
+                .starts_with("Redis did not answer in time"));
+            assert_eq!(
+                vec!["Ana", "Bogdan"],
+                contacts_service.list_names().await.unwrap()
+            );
+            assert_eq!(3, fake_redis.connections());
+        });
+    }
+}
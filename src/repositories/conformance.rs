@@ -0,0 +1,546 @@
+//! Behaviour every `ContactsRepository` has to share. Each backend runs
+//! `check_conformance` from its own tests; every case gets an empty address
+//! book of its own, so a shared store such as Redis only needs to be empty of
+//! those books.
+
+#[cfg(feature = "json")]
+use crate::atomic_file::WriteOptions;
+#[cfg(feature = "json")]
+use crate::crypto::Decryption;
+use crate::models::{change::Operation, contact::Contact, link::LinkKind, sort_by::SortBy};
+use crate::repositories::contacts::{ContactsRepository, DEFAULT_BOOK};
+use std::collections::BTreeMap;
+#[cfg(feature = "json")]
+use std::env;
+#[cfg(feature = "json")]
+use std::fs;
+#[cfg(feature = "json")]
+use std::path::PathBuf;
+
+type Case = fn(&mut dyn ContactsRepository);
+
+const CASES: &[(&str, Case)] = &[
+    ("conformance-add-get", check_add_get),
+    ("conformance-update", check_update),
+    ("conformance-delete-restore", check_delete_restore),
+    ("conformance-list", check_list),
+    ("conformance-trash", check_trash),
+    #[cfg(feature = "json")]
+    ("conformance-import-export", check_import_export),
+    ("conformance-history", check_history),
+    ("conformance-links", check_links),
+    ("conformance-replace-books", check_replace_books),
+];
+
+pub fn check_conformance(contacts_service: &mut dyn ContactsRepository) {
+    check_books(contacts_service);
+
+    for &(book, case) in CASES {
+        // Leftovers of an earlier run that failed halfway.
+        let _ = contacts_service.delete_book(book);
+        contacts_service.create_book(book).unwrap();
+        contacts_service.use_book(book).unwrap();
+        case(contacts_service);
+        contacts_service.use_book(DEFAULT_BOOK).unwrap();
+        contacts_service.delete_book(book).unwrap();
+    }
+}
+
+fn add(contacts_service: &mut dyn ContactsRepository, name: &str, phone_no: &str, email: &str) {
+    contacts_service
+        .add(name.to_string(), phone_no.to_string(), email.to_string())
+        .unwrap();
+}
+
+fn get(contacts_service: &dyn ContactsRepository, name: &str) -> Contact {
+    contacts_service.get(name).unwrap().unwrap()
+}
+
+fn names(contacts: Vec<Contact>) -> Vec<String> {
+    contacts.into_iter().map(|c| c.name).collect()
+}
+
+fn check_books(contacts_service: &mut dyn ContactsRepository) {
+    let book: &str = "conformance-books";
+    let _ = contacts_service.delete_book(book);
+    assert_eq!(DEFAULT_BOOK, contacts_service.current_book());
+    assert!(contacts_service.create_book("Not Valid").is_err());
+    assert!(contacts_service.create_book(DEFAULT_BOOK).is_err());
+    assert!(contacts_service.use_book(book).is_err());
+    assert!(contacts_service.delete_book(book).is_err());
+
+    contacts_service.create_book(book).unwrap();
+    assert!(contacts_service.create_book(book).is_err());
+    let books: Vec<String> = contacts_service.list_books().unwrap();
+    assert!(books.contains(&DEFAULT_BOOK.to_string()) && books.contains(&book.to_string()));
+
+    contacts_service.use_book(book).unwrap();
+    assert_eq!(book, contacts_service.current_book());
+    assert_eq!(0, contacts_service.count().unwrap());
+    assert!(contacts_service.delete_book(book).is_err());
+    assert!(contacts_service.delete_book(DEFAULT_BOOK).is_err());
+
+    contacts_service.use_book(DEFAULT_BOOK).unwrap();
+    contacts_service.delete_book(book).unwrap();
+    assert!(!contacts_service
+        .list_books()
+        .unwrap()
+        .contains(&book.to_string()));
+}
+
+fn check_add_get(contacts_service: &mut dyn ContactsRepository) {
+    assert!(contacts_service.get("Bogdan").unwrap().is_none());
+    assert_eq!(0, contacts_service.count().unwrap());
+
+    add(
+        contacts_service,
+        "Bogdan",
+        "491234567890",
+        "bogdan@mail.com",
+    );
+    let contact: Contact = get(contacts_service, "Bogdan");
+    assert_eq!("Bogdan", contact.name);
+    assert_eq!(491234567890, contact.phone_no);
+    assert_eq!("bogdan@mail.com", contact.email);
+    assert!(contact.created_at > 0);
+    assert_eq!(contact.created_at, contact.updated_at);
+    assert!(contact.deleted_at.is_none());
+    assert!(contact.links.is_empty());
+
+    for (name, phone_no, email) in [
+        ("", "491234567890", "mihai@mail.com"),
+        ("Mihai", "123", "mihai@mail.com"),
+        ("Mihai", "491234567890", "mihai"),
+    ] {
+        assert!(contacts_service
+            .add(name.to_string(), phone_no.to_string(), email.to_string())
+            .is_err());
+    }
+    assert!(contacts_service.get("Mihai").unwrap().is_none());
+    assert_eq!(1, contacts_service.count().unwrap());
+
+    // Adding an existing name overwrites the contact but keeps its creation time.
+    add(
+        contacts_service,
+        "Bogdan",
+        "491111111111",
+        "bogdan@work.com",
+    );
+    let overwritten: Contact = get(contacts_service, "Bogdan");
+    assert_eq!(491111111111, overwritten.phone_no);
+    assert_eq!("bogdan@work.com", overwritten.email);
+    assert_eq!(contact.created_at, overwritten.created_at);
+    assert_eq!(1, contacts_service.count().unwrap());
+}
+
+fn check_update(contacts_service: &mut dyn ContactsRepository) {
+    add(
+        contacts_service,
+        "Bogdan",
+        "491234567890",
+        "bogdan@mail.com",
+    );
+
+    contacts_service
+        .update_email("Bogdan", "bogdan@work.com".to_string())
+        .unwrap();
+    contacts_service
+        .update_phone_no("Bogdan", "491111111111".to_string())
+        .unwrap();
+    let contact: Contact = get(contacts_service, "Bogdan");
+    assert_eq!("bogdan@work.com", contact.email);
+    assert_eq!(491111111111, contact.phone_no);
+    assert!(contact.updated_at >= contact.created_at);
+
+    assert!(contacts_service
+        .update_email("Bogdan", "not an email".to_string())
+        .is_err());
+    assert!(contacts_service
+        .update_phone_no("Bogdan", "123".to_string())
+        .is_err());
+    let unchanged: Contact = get(contacts_service, "Bogdan");
+    assert_eq!("bogdan@work.com", unchanged.email);
+    assert_eq!(491111111111, unchanged.phone_no);
+
+    // Updating a missing contact is a no-op rather than creating it.
+    contacts_service
+        .update_email("Mihai", "mihai@mail.com".to_string())
+        .unwrap();
+    contacts_service
+        .update_phone_no("Mihai", "491234567890".to_string())
+        .unwrap();
+    assert!(contacts_service.get("Mihai").unwrap().is_none());
+    assert_eq!(1, contacts_service.count().unwrap());
+    assert!(contacts_service.history("Mihai").unwrap().is_empty());
+}
+
+fn check_delete_restore(contacts_service: &mut dyn ContactsRepository) {
+    add(
+        contacts_service,
+        "Bogdan",
+        "491234567890",
+        "bogdan@mail.com",
+    );
+
+    assert!(contacts_service.delete("Mihai").is_err());
+    assert!(contacts_service.restore("Bogdan").is_err());
+
+    contacts_service.delete("Bogdan").unwrap();
+    assert!(contacts_service.get("Bogdan").unwrap().is_none());
+    assert_eq!(0, contacts_service.count().unwrap());
+    assert!(contacts_service.delete("Bogdan").is_err());
+    let trash: Vec<Contact> = contacts_service.list_trash().unwrap();
+    assert_eq!(vec!["Bogdan".to_string()], names(trash.clone()));
+    assert!(trash[0].deleted_at.is_some());
+
+    add(
+        contacts_service,
+        "Bogdan",
+        "491111111111",
+        "bogdan@work.com",
+    );
+    assert!(contacts_service.restore("Bogdan").is_err());
+    contacts_service.delete("Bogdan").unwrap();
+    contacts_service.restore("Bogdan").unwrap();
+    let restored: Contact = get(contacts_service, "Bogdan");
+    assert!(restored.deleted_at.is_none());
+    assert!(contacts_service.list_trash().unwrap().is_empty());
+    assert!(contacts_service.restore("Bogdan").is_err());
+}
+
+fn check_list(contacts_service: &mut dyn ContactsRepository) {
+    assert!(contacts_service
+        .list(0, 10, SortBy::Name, false)
+        .unwrap()
+        .is_empty());
+    let empty = contacts_service
+        .list_page(None, 10, SortBy::Name, false)
+        .unwrap();
+    assert!(empty.contacts.is_empty() && empty.next_cursor.is_none());
+
+    for (name, email) in [
+        ("Dan", "z@mail.com"),
+        ("Ana", "y@mail.com"),
+        ("Cip", "x@mail.com"),
+        ("Bob", "w@mail.com"),
+        ("Eva", "v@mail.com"),
+    ] {
+        add(contacts_service, name, "491234567890", email);
+    }
+    assert_eq!(5, contacts_service.count().unwrap());
+    assert_eq!(
+        vec!["Ana", "Bob", "Cip", "Dan", "Eva"],
+        contacts_service.list_names().unwrap()
+    );
+
+    assert_eq!(
+        vec!["Ana", "Bob"],
+        names(contacts_service.list(0, 2, SortBy::Name, false).unwrap())
+    );
+    assert_eq!(
+        vec!["Eva"],
+        names(contacts_service.list(2, 2, SortBy::Name, false).unwrap())
+    );
+    assert!(contacts_service
+        .list(3, 2, SortBy::Name, false)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        vec!["Eva", "Bob", "Cip", "Ana", "Dan"],
+        names(contacts_service.list(0, 10, SortBy::Email, false).unwrap())
+    );
+    assert_eq!(
+        vec!["Eva", "Dan"],
+        names(contacts_service.list(0, 2, SortBy::Name, true).unwrap())
+    );
+
+    for (sort_by, descending) in [
+        (SortBy::Name, false),
+        (SortBy::Name, true),
+        (SortBy::Email, false),
+    ] {
+        let expected: Vec<String> =
+            names(contacts_service.list(0, 10, sort_by, descending).unwrap());
+        let mut seen: Vec<String> = Vec::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let page = contacts_service
+                .list_page(cursor.as_deref(), 2, sort_by, descending)
+                .unwrap();
+            assert!(page.contacts.len() <= 2);
+            seen.extend(names(page.contacts));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(expected, seen);
+    }
+
+    contacts_service.delete("Cip").unwrap();
+    assert_eq!(
+        vec!["Ana", "Bob", "Dan", "Eva"],
+        contacts_service.list_names().unwrap()
+    );
+    assert_eq!(4, contacts_service.count().unwrap());
+}
+
+fn check_trash(contacts_service: &mut dyn ContactsRepository) {
+    add(
+        contacts_service,
+        "Bogdan",
+        "491234567890",
+        "bogdan@mail.com",
+    );
+    add(contacts_service, "Mihai", "491234567890", "mihai@mail.com");
+    contacts_service.delete("Bogdan").unwrap();
+    contacts_service.delete("Mihai").unwrap();
+
+    assert_eq!(0, contacts_service.purge_trash(Some(0)).unwrap());
+    assert_eq!(2, contacts_service.list_trash().unwrap().len());
+    assert_eq!(2, contacts_service.purge_trash(None).unwrap());
+    assert!(contacts_service.list_trash().unwrap().is_empty());
+    assert!(contacts_service.restore("Bogdan").is_err());
+    assert_eq!(0, contacts_service.purge_trash(None).unwrap());
+}
+
+#[cfg(feature = "json")]
+fn get_export_path(name: &str) -> String {
+    let path: PathBuf =
+        env::temp_dir().join(format!("contacts-cli-{name}-{}.json", std::process::id()));
+    path.to_string_lossy().to_string()
+}
+
+#[cfg(feature = "json")]
+fn check_import_export(contacts_service: &mut dyn ContactsRepository) {
+    let mut no_decryption = || -> Result<Decryption, String> { Err("No key".to_string()) };
+    let path: String = get_export_path("conformance");
+    let _ = fs::remove_file(&path);
+
+    add(
+        contacts_service,
+        "Bogdan",
+        "491234567890",
+        "bogdan@mail.com",
+    );
+    add(contacts_service, "Mihai", "491111111111", "mihai@mail.com");
+    contacts_service.delete("Mihai").unwrap();
+    let bogdan: Contact = get(contacts_service, "Bogdan");
+
+    contacts_service
+        .export_to_json(path.clone(), true, None, WriteOptions::default())
+        .unwrap();
+    assert!(contacts_service
+        .export_to_json(path.clone(), true, None, WriteOptions::default())
+        .is_err());
+
+    // Importing over existing contacts overwrites them, trashed ones included.
+    contacts_service
+        .update_email("Bogdan", "bogdan@work.com".to_string())
+        .unwrap();
+    contacts_service.purge_trash(None).unwrap();
+    contacts_service
+        .import_from_json(path.clone(), &mut no_decryption)
+        .unwrap();
+    let imported: Contact = get(contacts_service, "Bogdan");
+    assert_eq!("bogdan@mail.com", imported.email);
+    assert_eq!(bogdan.created_at, imported.created_at);
+    assert_eq!(1, contacts_service.count().unwrap());
+    assert_eq!(
+        vec!["Mihai".to_string()],
+        names(contacts_service.list_trash().unwrap())
+    );
+
+    // Missing timestamps are filled in.
+    fs::write(
+        &path,
+        r#"[{"name":"Ana","phone_no":491234567890,"email":"ana@mail.com"}]"#,
+    )
+    .unwrap();
+    contacts_service
+        .import_from_json(path.clone(), &mut no_decryption)
+        .unwrap();
+    let ana: Contact = get(contacts_service, "Ana");
+    assert!(ana.created_at > 0);
+    assert_eq!(ana.created_at, ana.updated_at);
+
+    // Files that cannot be read are rejected as a whole.
+    for json in ["not json", r#"[{"name":"Dan","phone_no":491234567890}]"#] {
+        fs::write(&path, json).unwrap();
+        assert!(contacts_service
+            .import_from_json(path.clone(), &mut no_decryption)
+            .is_err());
+    }
+    assert!(contacts_service.get("Dan").unwrap().is_none());
+    assert_eq!(2, contacts_service.count().unwrap());
+
+    // Contacts the validators reject are skipped and reported, the others are
+    // imported.
+    fs::write(
+        &path,
+        r#"[{"name":"Dan","phone_no":491234567890,"email":"dan@mail.com"},{"name":"Eva","phone_no":123,"email":"eva@mail.com"},{"name":"","phone_no":491234567890,"email":"dan@mail.com"}]"#,
+    )
+    .unwrap();
+    let rejected: Vec<String> = contacts_service
+        .import_from_json(path.clone(), &mut no_decryption)
+        .unwrap();
+    assert_eq!(2, rejected.len());
+    assert!(rejected[0].starts_with("Eva: "));
+    assert!(contacts_service.get("Dan").unwrap().is_some());
+    assert!(contacts_service.get("Eva").unwrap().is_none());
+    assert_eq!(3, contacts_service.count().unwrap());
+
+    fs::remove_file(&path).unwrap();
+    assert!(contacts_service
+        .import_from_json(path.clone(), &mut no_decryption)
+        .is_err());
+
+    contacts_service
+        .replace_all(vec![Contact {
+            email: "eva@mail.com".to_string(),
+            name: "Eva".to_string(),
+            ..bogdan
+        }])
+        .unwrap();
+    assert_eq!(vec!["Eva"], contacts_service.list_names().unwrap());
+    assert!(contacts_service.list_trash().unwrap().is_empty());
+}
+
+fn check_replace_books(contacts_service: &mut dyn ContactsRepository) {
+    let book: String = contacts_service.current_book();
+    let new_book: &str = "conformance-replace-books-new";
+    let _ = contacts_service.delete_book(new_book);
+    add(
+        contacts_service,
+        "Bogdan",
+        "491234567890",
+        "bogdan@mail.com",
+    );
+    let eva: Contact = Contact {
+        name: "Eva".to_string(),
+        email: "eva@mail.com".to_string(),
+        ..get(contacts_service, "Bogdan")
+    };
+
+    // A book that cannot be created fails the whole restore, even after the
+    // books before it.
+    let books: BTreeMap<String, Vec<Contact>> = BTreeMap::from([
+        (book.clone(), vec![eva.clone()]),
+        ("zz Not Valid".to_string(), Vec::new()),
+    ]);
+    assert!(contacts_service.replace_books(books).is_err());
+    assert_eq!(vec!["Bogdan"], contacts_service.list_names().unwrap());
+
+    let books: BTreeMap<String, Vec<Contact>> = BTreeMap::from([
+        (book.clone(), vec![eva.clone()]),
+        (new_book.to_string(), vec![eva]),
+    ]);
+    contacts_service.replace_books(books).unwrap();
+    assert_eq!(book, contacts_service.current_book());
+    assert_eq!(vec!["Eva"], contacts_service.list_names().unwrap());
+    assert!(contacts_service
+        .list_books()
+        .unwrap()
+        .contains(&new_book.to_string()));
+    contacts_service.use_book(new_book).unwrap();
+    assert_eq!(vec!["Eva"], contacts_service.list_names().unwrap());
+    contacts_service.use_book(&book).unwrap();
+    contacts_service.delete_book(new_book).unwrap();
+}
+
+fn check_history(contacts_service: &mut dyn ContactsRepository) {
+    assert!(contacts_service.audit(None).unwrap().is_empty());
+
+    add(
+        contacts_service,
+        "Bogdan",
+        "491234567890",
+        "bogdan@mail.com",
+    );
+    contacts_service
+        .update_email("Bogdan", "bogdan@work.com".to_string())
+        .unwrap();
+    contacts_service
+        .update_phone_no("Bogdan", "491111111111".to_string())
+        .unwrap();
+    contacts_service.delete("Bogdan").unwrap();
+    contacts_service.restore("Bogdan").unwrap();
+    add(contacts_service, "Mihai", "491234567890", "mihai@mail.com");
+
+    let operations: Vec<Operation> = contacts_service
+        .history("Bogdan")
+        .unwrap()
+        .into_iter()
+        .map(|change| change.operation)
+        .collect();
+    assert_eq!(
+        vec![
+            Operation::Add,
+            Operation::UpdateEmail,
+            Operation::UpdatePhoneNo,
+            Operation::Delete,
+            Operation::Restore
+        ],
+        operations
+    );
+    let update_email = &contacts_service.history("Bogdan").unwrap()[1];
+    assert_eq!(1, update_email.fields.len());
+    assert_eq!("email", update_email.fields[0].field);
+    assert_eq!(
+        Some("bogdan@mail.com".to_string()),
+        update_email.fields[0].old_value
+    );
+
+    assert_eq!(6, contacts_service.audit(None).unwrap().len());
+    assert!(contacts_service
+        .audit(Some(i64::MAX / 1000))
+        .unwrap()
+        .is_empty());
+}
+
+fn check_links(contacts_service: &mut dyn ContactsRepository) {
+    add(
+        contacts_service,
+        "Bogdan",
+        "491234567890",
+        "bogdan@mail.com",
+    );
+    add(contacts_service, "Mihai", "491234567890", "mihai@mail.com");
+    add(contacts_service, "Ana", "491234567890", "ana@mail.com");
+
+    assert!(contacts_service
+        .link("Bogdan", "Dan", LinkKind::Spouse, true)
+        .is_err());
+    contacts_service
+        .link("Bogdan", "Mihai", LinkKind::Manager, true)
+        .unwrap();
+    contacts_service
+        .link("Ana", "Bogdan", LinkKind::Spouse, false)
+        .unwrap();
+    assert_eq!(1, get(contacts_service, "Bogdan").links.len());
+    assert_eq!(
+        LinkKind::Report,
+        get(contacts_service, "Mihai").links[0].kind
+    );
+
+    // Links survive updates and overwrites.
+    add(
+        contacts_service,
+        "Bogdan",
+        "491111111111",
+        "bogdan@mail.com",
+    );
+    contacts_service
+        .update_email("Bogdan", "bogdan@work.com".to_string())
+        .unwrap();
+    assert_eq!(
+        "Mihai",
+        contacts_service.related("Bogdan").unwrap()[0].1.name
+    );
+
+    contacts_service.delete("Bogdan").unwrap();
+    assert!(get(contacts_service, "Mihai").links.is_empty());
+    assert!(get(contacts_service, "Ana").links.is_empty());
+    assert!(contacts_service.list_trash().unwrap()[0].links.is_empty());
+    assert!(contacts_service.unlink("Ana", "Mihai").is_err());
+}
@@ -1,9 +1,35 @@
-use crate::models::contact::Contact;
+//! The `ContactsRepository` trait, the validators every backend shares and the
+//! helpers behind the trait's default methods.
+
+#[cfg(feature = "json")]
+use crate::atomic_file::WriteOptions;
+#[cfg(feature = "json")]
+use crate::codec::{read_valid_contacts_file, write_contacts_file};
+#[cfg(feature = "json")]
+use crate::crypto::{DecryptionProvider, Encryption};
+use crate::models::{
+    change::Change,
+    contact::Contact,
+    link::{Link, LinkKind},
+    page::{Cursor, Direction, Page},
+    sort_by::SortBy,
+};
+use crate::repositories::migrations::{MigrationStatus, MigrationStep};
 use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const EMAIL_REGEX: &str =
-    r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})";
-const DE_PHONE_NO_REGEX: &str = r"49[0-9]{9,10}";
+    r"^([a-z0-9_+]([a-z0-9_+.]*[a-z0-9_+])?)@([a-z0-9]+([\-\.]{1}[a-z0-9]+)*\.[a-z]{2,6})$";
+const DE_PHONE_NO_REGEX: &str = r"^49[0-9]{9,10}$";
+const BOOK_NAME_REGEX: &str = r"^[a-z0-9][a-z0-9_-]{0,31}$";
+const OPERATOR_KEY: &str = "CONTACTS_OPERATOR";
+
+/// The address book that exists in every store and holds the contacts written
+/// before address books were introduced.
+pub const DEFAULT_BOOK: &str = "default";
 
 fn is_valid_regex(text: &str, re: &str) -> Result<bool, regex::Error> {
     match Regex::new(re) {
@@ -12,14 +38,19 @@ fn is_valid_regex(text: &str, re: &str) -> Result<bool, regex::Error> {
     }
 }
 
+/// Rejects empty names and names with control characters.
 pub fn get_valid_name(name: &str) -> Result<String, String> {
-    if name.is_empty() {
+    if name.trim().is_empty() {
         return Err("name canot be empty".to_string());
     }
+    if name.chars().any(char::is_control) {
+        return Err("name cannot contain control characters".to_string());
+    }
 
     Ok(name.to_string())
 }
 
+/// Checks that `email` looks like an email address.
 pub fn get_valid_email(email: &str) -> Result<String, String> {
 
     let is_valid_email: bool = is_valid_regex(email, EMAIL_REGEX)
@@ -32,6 +63,17 @@ pub fn get_valid_email(email: &str) -> Result<String, String> {
     Ok(email.to_string())
 }
 
+/// Checks that `book` can be used as a book name, e.g. in a key prefix.
+pub fn get_valid_book_name(book: &str) -> Result<String, String> {
+    let is_valid_book_name: bool =
+        is_valid_regex(book, BOOK_NAME_REGEX).map_err(|err| err.to_string())?;
+    match is_valid_book_name {
+        true => Ok(book.to_string()),
+        false => Err("Book names have up to 32 lowercase letters, digits, - and _".to_string()),
+    }
+}
+
+/// Parses a German phone number written without the `+`, e.g. `4915112345678`.
 pub fn get_valid_phone_no(phone_no_as_string: &str) -> Result<u64, String> {
     
     let is_valid_phone_no: bool = is_valid_regex(phone_no_as_string, DE_PHONE_NO_REGEX)
@@ -47,7 +89,199 @@ pub fn get_valid_phone_no(phone_no_as_string: &str) -> Result<u64, String> {
     Ok(phone_no)
 }
 
+/// Any text, biased towards what trips validators up: unicode, whitespace
+/// and control characters around otherwise valid values.
+#[cfg(test)]
+pub(crate) fn fuzz_text(valid: &'static str) -> impl proptest::strategy::Strategy<Value = String> {
+    use proptest::prelude::*;
+    prop_oneof![
+        any::<String>(),
+        "\\PC{0,40}",
+        "[ \t\n\r\u{a0}\u{2028}]{0,3}",
+        (
+            "[0-9a-z \t\n\u{a0}\u{200b}]{0,2}",
+            valid,
+            "[ \t\n\u{a0}\u{200b}ß]{0,2}"
+        )
+            .prop_map(|(before, text, after)| format!("{before}{text}{after}")),
+        valid.prop_map(|text| text),
+    ]
+}
+
+/// Seconds since the Unix epoch.
+pub fn get_current_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// The identity recorded in the change log: the configured `CONTACTS_OPERATOR`,
+/// falling back to the OS user.
+pub(crate) fn get_current_operator() -> String {
+    env::var(OPERATOR_KEY)
+        .or_else(|_| env::var("USER"))
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// The value contacts are ordered by for a sort key, padded so that it also
+/// orders correctly as a string.
+pub(crate) fn get_sort_value(contact: &Contact, sort_by: SortBy) -> String {
+    match sort_by {
+        SortBy::Name => contact.name.clone(),
+        SortBy::Created => format!("{:020}", contact.created_at),
+        SortBy::Updated => format!("{:020}", contact.updated_at),
+        SortBy::Email => contact.email.clone(),
+    }
+}
+
+fn compare_position(
+    sort_value: &str,
+    name: &str,
+    other_sort_value: &str,
+    other_name: &str,
+    descending: bool,
+) -> Ordering {
+    let ordering: Ordering = sort_value
+        .cmp(other_sort_value)
+        .then_with(|| name.cmp(other_name));
+
+    if descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+/// Sorts contacts by the given key, falling back to the name to keep the order stable.
+pub(crate) fn sort_contacts(contacts: &mut [Contact], sort_by: SortBy, descending: bool) {
+    contacts.sort_by_cached_key(|c| (get_sort_value(c, sort_by), c.name.clone()));
+    if descending {
+        contacts.reverse();
+    }
+}
+
+pub(crate) fn get_cursor(
+    contact: &Contact,
+    sort_by: SortBy,
+    descending: bool,
+    direction: Direction,
+) -> String {
+    Cursor {
+        sort_by,
+        descending,
+        direction,
+        sort_value: get_sort_value(contact, sort_by),
+        name: contact.name.clone(),
+    }
+    .encode()
+}
+
+/// Cuts one page out of all the contacts, starting from the cursor position if
+/// any. Backends that cannot seek to a cursor natively page through this.
+pub(crate) fn paginate(
+    mut contacts: Vec<Contact>,
+    cursor: Option<&str>,
+    page_size: usize,
+    sort_by: SortBy,
+    descending: bool,
+) -> Result<Page, String> {
+    let cursor: Option<Cursor> = cursor.map(Cursor::decode).transpose()?;
+    let (sort_by, descending) = match &cursor {
+        Some(cursor) => (cursor.sort_by, cursor.descending),
+        None => (sort_by, descending),
+    };
+    sort_contacts(&mut contacts, sort_by, descending);
+
+    let (start, end) = match &cursor {
+        None => (0, page_size.min(contacts.len())),
+        Some(cursor) => {
+            let position = |c: &Contact| {
+                compare_position(
+                    &get_sort_value(c, sort_by),
+                    &c.name,
+                    &cursor.sort_value,
+                    &cursor.name,
+                    descending,
+                )
+            };
+            match cursor.direction {
+                Direction::After => {
+                    let start: usize =
+                        contacts.partition_point(|c| position(c) != Ordering::Greater);
+                    (start, (start + page_size).min(contacts.len()))
+                }
+                Direction::Before => {
+                    let end: usize = contacts.partition_point(|c| position(c) == Ordering::Less);
+                    (end.saturating_sub(page_size), end)
+                }
+            }
+        }
+    };
+
+    let next_cursor: Option<String> = match end < contacts.len() && start < end {
+        true => Some(get_cursor(
+            &contacts[end - 1],
+            sort_by,
+            descending,
+            Direction::After,
+        )),
+        false => None,
+    };
+    let prev_cursor: Option<String> = match start > 0 && start < contacts.len() {
+        true => Some(get_cursor(
+            &contacts[start],
+            sort_by,
+            descending,
+            Direction::Before,
+        )),
+        false => None,
+    };
+
+    Ok(Page {
+        contacts: contacts.drain(start..end).collect(),
+        next_cursor,
+        prev_cursor,
+    })
+}
+
+/// The links of a contact, with the one to `name` set to `kind`.
+pub(crate) fn with_link(links: Vec<Link>, name: &str, kind: LinkKind) -> Vec<Link> {
+    let mut links: Vec<Link> = links.into_iter().filter(|link| link.name != name).collect();
+    links.push(Link {
+        name: name.to_string(),
+        kind,
+    });
+    links
+}
+
+/// Drops the links to `name` from the given contacts, returning the contacts
+/// that had any as their old and new state.
+pub(crate) fn remove_links_to(contacts: Vec<Contact>, name: &str) -> Vec<(Contact, Contact)> {
+    contacts
+        .into_iter()
+        .filter(|contact| contact.links.iter().any(|link| link.name == name))
+        .map(|old| {
+            let new: Contact = Contact {
+                links: old
+                    .links
+                    .iter()
+                    .filter(|link| link.name != name)
+                    .cloned()
+                    .collect(),
+                ..old.clone()
+            };
+            (old, new)
+        })
+        .collect()
+}
+
+/// The operations every storage backend provides, scoped to the active address
+/// book. Errors are messages meant for the user.
 pub trait ContactsRepository {
+    /// Adds a contact, overwriting a live one with the same name while keeping
+    /// its `created_at` and links and bumping its `updated_at`.
     fn add(
         &mut self,
         name: String,
@@ -55,23 +289,317 @@ pub trait ContactsRepository {
         email: String,
     ) -> Result<(), String>;
 
+    /// Changes the email of a live contact.
     fn update_email(&mut self, name: &str, new_email: String) -> Result<(), String>;
 
+    /// Changes the phone number of a live contact.
     fn update_phone_no(
         &mut self,
         name: &str,
         new_phone_no_as_string: String,
     ) -> Result<(), String>;
 
+    /// Replaces the links of a live contact.
+    fn update_links(&mut self, name: &str, links: Vec<Link>) -> Result<(), String>;
+
+    /// Moves the contact to the trash, stamping it with the deletion time, and
+    /// drops the links from and to it.
     fn delete(&mut self, name: &str) -> Result<(), String>;
 
+    /// Moves a trashed contact back among the live ones.
+    fn restore(&mut self, name: &str) -> Result<(), String>;
+
+    /// The live contact with the name, if any.
     fn get(&self, name: &str) -> Result<Option<Contact>, String>;
 
-    fn list(&self, page_no: usize, page_size: usize) -> Result<Vec<Contact>, String>;
+    /// One page of live contacts, counting pages from 0.
+    fn list(
+        &self,
+        page_no: usize,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Vec<Contact>, String>;
+
+    /// A page of contacts starting from an opaque cursor returned by a previous
+    /// page. The cursor carries its own sort order, which takes precedence over
+    /// `sort_by` and `descending`.
+    fn list_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Page, String>;
 
-    fn export_to_json(&self, file_path: String) -> Result<(), String>;
+    /// Names of all the live contacts, in name order.
+    fn list_names(&self) -> Result<Vec<String>, String> {
+        Ok(self
+            .list(0, usize::MAX, SortBy::Name, false)?
+            .into_iter()
+            .map(|c| c.name)
+            .collect())
+    }
+
+    /// The last `limit` modified contacts, most recent first.
+    fn recent(&self, limit: usize) -> Result<Vec<Contact>, String> {
+        self.list(0, limit, SortBy::Updated, true)
+    }
 
-    fn import_from_json(&mut self, path: String) -> Result<(), String>;
+    /// The trashed contacts.
+    fn list_trash(&self) -> Result<Vec<Contact>, String>;
 
+    /// Permanently removes trashed contacts deleted before the given timestamp,
+    /// or the whole trash if none is given. Returns the number of purged contacts.
+    fn purge_trash(&mut self, deleted_before: Option<i64>) -> Result<usize, String>;
+
+    /// Writes the live contacts, and optionally the trashed ones, to a file that
+    /// `import_from_json` reads back.
+    #[cfg(feature = "json")]
+    fn export_to_json(
+        &self,
+        file_path: String,
+        include_trash: bool,
+        encryption: Option<&Encryption>,
+        options: WriteOptions,
+    ) -> Result<(), String> {
+        let mut contacts: Vec<Contact> = self.list(0, usize::MAX, SortBy::Name, false)?;
+        if include_trash {
+            contacts.extend(self.list_trash()?);
+        }
+        write_contacts_file(&file_path, &contacts, encryption, options)
+    }
+
+    /// Imports the valid contacts of a file written by `export_to_json`,
+    /// skipping the ones the validators reject. Returns why each skipped
+    /// contact was rejected.
+    #[cfg(feature = "json")]
+    fn import_from_json(
+        &mut self,
+        path: String,
+        decryption: &mut DecryptionProvider,
+    ) -> Result<Vec<String>, String> {
+        let (contacts, rejected) = read_valid_contacts_file(&path, decryption)?;
+        self.import_contacts(contacts)?;
+        Ok(rejected)
+    }
+
+    /// Adds or overwrites the given contacts, filling in missing timestamps.
+    /// Contacts with `deleted_at` set go to the trash.
+    fn import_contacts(&mut self, contacts: Vec<Contact>) -> Result<(), String>;
+
+    /// Replaces all the contacts, live and trashed, in one step. Contacts with
+    /// `deleted_at` set go to the trash.
+    fn replace_all(&mut self, contacts: Vec<Contact>) -> Result<(), String>;
+
+    /// Replaces all the contacts of each given address book like `replace_all`,
+    /// creating the books that do not exist, all in one step. Other books are
+    /// left as they are.
+    fn replace_books(&mut self, books: BTreeMap<String, Vec<Contact>>) -> Result<(), String>;
+
+    /// The number of live contacts.
     fn count(&self) -> Result<usize, String>;
+
+    /// All the changes recorded for one contact, oldest first.
+    fn history(&self, name: &str) -> Result<Vec<Change>, String>;
+
+    /// All the changes recorded since the given timestamp, oldest first.
+    fn audit(&self, since: Option<i64>) -> Result<Vec<Change>, String>;
+
+    /// The address book every other operation is scoped to.
+    fn current_book(&self) -> String;
+
+    /// Switches to an existing address book.
+    fn use_book(&mut self, book: &str) -> Result<(), String>;
+
+    /// Creates an empty address book without switching to it.
+    fn create_book(&mut self, book: &str) -> Result<(), String>;
+
+    /// Deletes an address book with all its contacts, trash and change log. The
+    /// default and the active books cannot be deleted.
+    fn delete_book(&mut self, book: &str) -> Result<(), String>;
+
+    /// Names of all the address books, in name order.
+    fn list_books(&self) -> Result<Vec<String>, String>;
+
+    /// The schema version the stored data is at and the migration steps it is
+    /// behind by.
+    fn migration_status(&self) -> Result<MigrationStatus, String>;
+
+    /// Runs the pending migration steps in order, recording the version reached
+    /// after each one, and returns the steps run. Running it again once the
+    /// data is up to date does nothing.
+    fn migrate_up(&mut self) -> Result<Vec<MigrationStep>, String>;
+
+    /// Copies a contact of the active book into another one, failing if the
+    /// other book already has a contact with that name.
+    fn copy_to_book(&mut self, name: &str, book: &str) -> Result<(), String> {
+        let mut contact: Contact = self
+            .get(name)?
+            .ok_or(format!("No contact with name {name}"))?;
+        // Links name contacts of the active book, which the other one may not have.
+        contact.links.clear();
+        let current_book: String = self.current_book();
+        if book == current_book {
+            return Err(format!("{name} is already in the {book} book"));
+        }
+
+        self.use_book(book)?;
+        let result: Result<(), String> = match self.get(name) {
+            Ok(Some(_)) => Err(format!("{name} already exists in the {book} book")),
+            Ok(None) => self.import_contacts(vec![contact]),
+            Err(err) => Err(err),
+        };
+        self.use_book(&current_book)?;
+        result
+    }
+
+    /// Copies a contact into another book, then moves it to the trash of the
+    /// active one.
+    fn move_to_book(&mut self, name: &str, book: &str) -> Result<(), String> {
+        self.copy_to_book(name, book)?;
+        self.delete(name)
+    }
+
+    /// Records that `other` is the `kind` of `name`, and when `bidirectional`
+    /// that `name` is the inverse kind of `other`. Linking the same two contacts
+    /// again replaces the previous kind.
+    fn link(
+        &mut self,
+        name: &str,
+        other: &str,
+        kind: LinkKind,
+        bidirectional: bool,
+    ) -> Result<(), String> {
+        if name == other {
+            return Err(format!("{name} cannot be linked to itself"));
+        }
+        let contact: Contact = self
+            .get(name)?
+            .ok_or(format!("No contact with name {name}"))?;
+        let other_contact: Contact = self
+            .get(other)?
+            .ok_or(format!("No contact with name {other}"))?;
+
+        self.update_links(name, with_link(contact.links.clone(), other, kind))?;
+        if bidirectional {
+            // Undo the first half rather than leave a one way link behind.
+            if let Err(err) =
+                self.update_links(other, with_link(other_contact.links, name, kind.inverse()))
+            {
+                return match self.update_links(name, contact.links) {
+                    Ok(()) => Err(err),
+                    Err(undo_err) => Err(format!(
+                        "{err}, and the link from {name} to {other} could not be undone: {undo_err}"
+                    )),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the links between two contacts, in both directions.
+    fn unlink(&mut self, name: &str, other: &str) -> Result<(), String> {
+        let mut found: bool = false;
+        for (from, to) in [(name, other), (other, name)] {
+            let contact: Contact = match self.get(from)? {
+                Some(x) => x,
+                None => continue,
+            };
+            if contact.links.iter().any(|link| link.name == to) {
+                let links: Vec<Link> = contact
+                    .links
+                    .into_iter()
+                    .filter(|link| link.name != to)
+                    .collect();
+                self.update_links(from, links)?;
+                found = true;
+            }
+        }
+
+        match found {
+            true => Ok(()),
+            false => Err(format!("{name} and {other} are not linked")),
+        }
+    }
+
+    /// The contacts `name` links to, with the kind of each link.
+    fn related(&self, name: &str) -> Result<Vec<(LinkKind, Contact)>, String> {
+        let contact: Contact = self
+            .get(name)?
+            .ok_or(format!("No contact with name {name}"))?;
+
+        let mut related: Vec<(LinkKind, Contact)> = Vec::new();
+        for link in contact.links {
+            if let Some(other) = self.get(&link.name)? {
+                related.push((link.kind, other));
+            }
+        }
+        Ok(related)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_valid_phone_no_round_trips(text in fuzz_text("49[0-9]{0,12}")) {
+            if let Ok(phone_no) = get_valid_phone_no(&text) {
+                prop_assert_eq!(phone_no.to_string(), text);
+            }
+        }
+
+        #[test]
+        fn test_valid_phone_no_length(digits in "[0-9]{0,14}") {
+            let text: String = format!("49{digits}");
+            prop_assert_eq!(
+                (9..=10).contains(&digits.len()),
+                get_valid_phone_no(&text).is_ok()
+            );
+        }
+
+        #[test]
+        fn test_valid_email_is_plain_ascii(
+            text in fuzz_text("[a-z0-9_+.]{1,10}@[a-z0-9.-]{1,10}\\.[a-z]{1,8}")
+        ) {
+            if get_valid_email(&text).is_ok() {
+                prop_assert!(text.is_ascii());
+                prop_assert!(!text.chars().any(|c| c.is_whitespace() || c.is_control()));
+                let tld: &str = text.rsplit('.').next().unwrap();
+                prop_assert!((2..=6).contains(&tld.len()));
+            }
+        }
+
+        #[test]
+        fn test_valid_email_tld_length(local in "[a-z]{1,8}", tld in "[a-z]{0,9}") {
+            let text: String = format!("{local}@mail.{tld}");
+            prop_assert_eq!((2..=6).contains(&tld.len()), get_valid_email(&text).is_ok());
+        }
+
+        #[test]
+        fn test_valid_name_is_not_blank(text in fuzz_text("\\PC{1,20}")) {
+            match get_valid_name(&text) {
+                Ok(name) => {
+                    prop_assert!(!name.trim().is_empty());
+                    prop_assert!(!name.chars().any(char::is_control));
+                }
+                Err(_) => prop_assert!(
+                    text.trim().is_empty() || text.chars().any(char::is_control)
+                ),
+            }
+        }
+
+        #[test]
+        fn test_valid_book_name(text in fuzz_text("[a-z0-9][a-z0-9_-]{0,40}")) {
+            if let Ok(book) = get_valid_book_name(&text) {
+                prop_assert!(book.len() <= 32);
+                prop_assert!(book
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_'));
+            }
+        }
+    }
 }
@@ -1,273 +1,1461 @@
-use dotenvy::dotenv;
-use redis::Client as RedisClient;
-use redis::Connection as RedisConnection;
-use std::collections::BTreeMap;
-use std::env;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::Write;
-use crate::{models::contact::Contact, repositories::contacts::ContactsRepository};
-
-use super::contacts::{get_valid_name, get_valid_email, get_valid_phone_no};
-
-const REDIS_URL_KEY: &str = "REDIS_URL";
-const REDIS_SUBKEY_PHONE_NO: &str = "phone_no";
-const REDIS_SUBKEY_EMAIL: &str = "email";
-const REDIS_KEY_PREFFIX: &str = "contacts";
-
-pub struct DbContactsRepository {
-    redis_client: RedisClient,
+//! The layout of the Redis store, shared by the async backend and its blocking
+//! form. Each contact is a hash under the book's key prefix, indexed by a
+//! sorted set.
+
+use crate::{
+    models::{
+        change::{Change, FieldChange, Operation},
+        contact::Contact,
+        link::Link,
+    },
+    repositories::async_contacts::BlockingContactsAdapter,
+    repositories::async_db_contacts::AsyncDbContactsRepository,
+    repositories::contacts::DEFAULT_BOOK,
+    repositories::migrations::{migrate, Migration, MigrationStep},
+    repositories::redis_connection::{
+        get_valid_key_prefix, PersistentConnection, RedisConnection, RedisOptions, RedisTarget,
+    },
+};
+use std::cell::RefMut;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use super::contacts::{get_current_operator, get_current_timestamp};
+
+pub(super) const REDIS_SUBKEY_PHONE_NO: &str = "phone_no";
+pub(super) const REDIS_SUBKEY_EMAIL: &str = "email";
+const REDIS_SUBKEY_CREATED_AT: &str = "created_at";
+const REDIS_SUBKEY_UPDATED_AT: &str = "updated_at";
+const REDIS_SUBKEY_DELETED_AT: &str = "deleted_at";
+pub(super) const REDIS_SUBKEY_LINKS: &str = "links";
+const REDIS_TRASH_KEY_SUFFIX: &str = "_trash";
+const REDIS_INDEX_KEY_SUFFIX: &str = "_index";
+const REDIS_INDEXED_KEY_SUFFIX: &str = "_indexed";
+const REDIS_BACKLINKS_KEY_SUFFIX: &str = "_backlinks";
+const REDIS_BACKLINKED_KEY_SUFFIX: &str = "_backlinked";
+const REDIS_AUDIT_KEY_SUFFIX: &str = "_audit";
+const REDIS_BOOKS_KEY_SUFFIX: &str = "_books";
+const REDIS_SCHEMA_KEY_SUFFIX: &str = "_schema";
+const REDIS_AUDIT_FIELD_TIMESTAMP: &str = "timestamp";
+const REDIS_AUDIT_FIELD_OPERATOR: &str = "operator";
+const REDIS_AUDIT_FIELD_OPERATION: &str = "operation";
+const REDIS_AUDIT_FIELD_NAME: &str = "name";
+const REDIS_AUDIT_FIELD_FIELDS: &str = "fields";
+
+/// Contacts stored in Redis, served to blocking callers by running the async
+/// backend on a runtime of its own.
+pub type DbContactsRepository = BlockingContactsAdapter<AsyncDbContactsRepository>;
+
+/// The whole store, reached through a blocking connection, for the
+/// maintenance tasks that walk every book or key: schema migrations and moving
+/// the key prefix.
+pub(super) struct RedisStore {
+    redis_connection: PersistentConnection,
+    key_prefix: String,
+    hash_tag: bool,
+    keys: RedisKeys,
+}
+
+/// The keys of one address book, all starting with the key prefix of the
+/// store, `contacts` by default. The default book keeps the keys used before
+/// address books existed, the others get an `@<book>` suffix. Next to each
+/// contact a set holds the names of the contacts linking to it, its
+/// backlinks. The list of books and the schema version belong to the whole
+/// store. In a cluster the
+/// prefix is a hash tag, `{contacts}`, so all the keys of the store share one
+/// hash slot and transactions can span them.
+pub(super) struct RedisKeys {
+    pub(super) contacts: String,
+    pub(super) trash: String,
+    pub(super) index: String,
+    pub(super) indexed: String,
+    pub(super) backlinks: String,
+    pub(super) backlinked: String,
+    pub(super) audit: String,
+    pub(super) books: String,
+    pub(super) schema: String,
 }
 
+impl RedisKeys {
+    pub(super) fn new(key_prefix: &str, book: &str, hash_tag: bool) -> Self {
+        let prefix: String = match hash_tag {
+            true => format!("{{{key_prefix}}}"),
+            false => key_prefix.to_string(),
+        };
+        let suffix: String = match book == DEFAULT_BOOK {
+            true => String::new(),
+            false => format!("@{book}"),
+        };
+        RedisKeys {
+            contacts: format!("{prefix}{suffix}"),
+            trash: format!("{prefix}{REDIS_TRASH_KEY_SUFFIX}{suffix}"),
+            index: format!("{prefix}{REDIS_INDEX_KEY_SUFFIX}{suffix}"),
+            indexed: format!("{prefix}{REDIS_INDEXED_KEY_SUFFIX}{suffix}"),
+            backlinks: format!("{prefix}{REDIS_BACKLINKS_KEY_SUFFIX}{suffix}"),
+            backlinked: format!("{prefix}{REDIS_BACKLINKED_KEY_SUFFIX}{suffix}"),
+            audit: format!("{prefix}{REDIS_AUDIT_KEY_SUFFIX}{suffix}"),
+            books: format!("{prefix}{REDIS_BOOKS_KEY_SUFFIX}"),
+            schema: format!("{prefix}{REDIS_SCHEMA_KEY_SUFFIX}"),
+        }
+    }
+
+    pub(super) fn contact(&self, name: &str) -> String {
+        format!("{}:{name}", self.contacts)
+    }
+
+    pub(super) fn trashed_contact(&self, name: &str) -> String {
+        format!("{}:{name}", self.trash)
+    }
+
+    pub(super) fn backlinks_of(&self, name: &str) -> String {
+        format!("{}:{name}", self.backlinks)
+    }
+
+    /// The key of a contact, in the trash if it is deleted.
+    pub(super) fn stored_contact(&self, contact: &Contact) -> String {
+        match contact.deleted_at {
+            Some(_) => self.trashed_contact(&contact.name),
+            None => self.contact(&contact.name),
+        }
+    }
+}
+
+/// The layouts of the store. Versions are recorded under the `_schema` key, a
+/// store without one predates them.
+pub(super) const REDIS_MIGRATIONS: &[Migration<RedisStore>] = &[Migration {
+    version: 1,
+    description: "Index the live contacts of every book",
+    apply: index_books,
+}];
+
 impl Default for DbContactsRepository {
+    /// Opens the configured server, panicking on any error. With the `dotenv`
+    /// feature the settings may also come from a `.env` file.
     fn default() -> Self {
-        Self::new()
+        #[cfg(feature = "dotenv")]
+        dotenvy::dotenv().ok();
+        Self::from_env().unwrap_or_else(|err| panic!("{err}"))
     }
 }
 
 impl DbContactsRepository {
-    pub fn new() -> Self {
-        dotenv().expect("Error loading .env file");
-        let redis_url: String =
-            env::var(REDIS_URL_KEY).unwrap_or_else(|_| panic!("Cannot find key {REDIS_URL_KEY}"));
-        let redis_client: RedisClient = RedisClient::open(redis_url.clone())
-            .unwrap_or_else(|_| panic!("Cannot connect to Redis instance {redis_url}"));
-        DbContactsRepository { redis_client }
-    }
-
-    fn get_redis_connection(&self) -> Result<RedisConnection, String> {
-        let redis_connection: RedisConnection = self
-            .redis_client
-            .get_connection()
-            .map_err(|err| err.to_string())?;
+    /// Opens the server, Sentinel master or cluster configured in the
+    /// environment, see `RedisTarget::from_env` and `RedisOptions::from_env`.
+    pub fn from_env() -> Result<Self, String> {
+        Self::open_target(RedisTarget::from_env()?, RedisOptions::from_env()?)
+    }
 
-        Ok(redis_connection)
+    /// Opens the repository without connecting yet; the connection is made on
+    /// the first call and kept for the following ones. Fails if the runtime
+    /// cannot be started.
+    pub fn open_target(target: RedisTarget, options: RedisOptions) -> Result<Self, String> {
+        Self::new(AsyncDbContactsRepository::open_target(target, options))
+    }
+
+    /// Moves every key of the store under `old_prefix` to `new_prefix`, see
+    /// `RedisStore::migrate_prefix`.
+    pub fn migrate_prefix(
+        &self,
+        old_prefix: &str,
+        new_prefix: &str,
+        batch_size: usize,
+    ) -> Result<usize, String> {
+        self.get_ref()
+            .get_store()
+            .migrate_prefix(old_prefix, new_prefix, batch_size)
     }
 }
 
-impl ContactsRepository for DbContactsRepository {
-    fn add(&mut self, name: String, phone_no_as_string: String, email: String) -> Result<(), String> {
-        let name: String = get_valid_name(&name)?;
-        let email:String = get_valid_email(&email)?;
-        let phone_no: u64 = get_valid_phone_no(&phone_no_as_string)?;
-        let mut redis_connection: RedisConnection = self.get_redis_connection()?;
-        let key: String = format!("{REDIS_KEY_PREFFIX}:{name}");
+impl RedisStore {
+    /// Serves the store behind `redis_connection`.
+    pub(super) fn new(
+        redis_connection: PersistentConnection,
+        key_prefix: &str,
+        hash_tag: bool,
+    ) -> Self {
+        RedisStore {
+            redis_connection,
+            keys: RedisKeys::new(key_prefix, DEFAULT_BOOK, hash_tag),
+            key_prefix: key_prefix.to_string(),
+            hash_tag,
+        }
+    }
 
-        let no_of_subkeys_set: usize = redis::cmd("HSET").arg(&key)
-            .arg(REDIS_SUBKEY_PHONE_NO)
-            .arg(phone_no)
-            .arg(REDIS_SUBKEY_EMAIL)
-            .arg(email)        
-            .query::<usize>(&mut redis_connection)
-            .map_err(|err| err.to_string())?;
+    /// Runs the steps of `REDIS_MIGRATIONS` the store is behind by.
+    pub(super) fn migrate_up(&mut self) -> Result<Vec<MigrationStep>, String> {
+        let version: u32 = read_schema_version(&mut *self.get_redis_connection()?, &self.keys)?;
+        migrate(REDIS_MIGRATIONS, self, version, |store, version| {
+            redis::cmd("SET")
+                .arg(&store.keys.schema)
+                .arg(version)
+                .query::<()>(&mut *store.get_redis_connection()?)
+                .map_err(|err| err.to_string())
+        })
+    }
+
+    /// Moves every key of the store under `old_prefix` to `new_prefix`, up to
+    /// `batch_size` keys per round trip, and returns how many were moved.
+    /// Nothing is moved if a key would land on an existing one, and each key is
+    /// only renamed if its new name is still free, so no data is overwritten.
+    /// A migration that failed halfway resumes when run again.
+    pub(super) fn migrate_prefix(
+        &self,
+        old_prefix: &str,
+        new_prefix: &str,
+        batch_size: usize,
+    ) -> Result<usize, String> {
+        let old_prefix: String = get_valid_key_prefix(old_prefix)?;
+        let new_prefix: String = get_valid_key_prefix(new_prefix)?;
+        if old_prefix == new_prefix {
+            return Err(format!("The keys are already under {new_prefix}"));
+        }
+        if batch_size == 0 {
+            return Err("The batch size must be at least 1".to_string());
+        }
+        if self.hash_tag {
+            return Err(
+                "Keys cannot be moved across the hash slots of a Redis Cluster".to_string(),
+            );
+        }
 
-        if no_of_subkeys_set != 2 {
-            return Err(format!("Unexpected Redis HSET result. Expected 2, actual {no_of_subkeys_set}"));
+        let mut redis_connection: RefMut<RedisConnection> = self.get_redis_connection()?;
+        let patterns: Vec<String> = get_store_patterns(&old_prefix);
+        let mut taken: Vec<String> = Vec::new();
+        scan_keys(
+            &mut redis_connection,
+            &patterns,
+            batch_size,
+            |conn, keys| {
+                let new_keys: Vec<String> = keys
+                    .iter()
+                    .map(|key| get_moved_key(key, &old_prefix, &new_prefix))
+                    .collect();
+                let mut pipe: redis::Pipeline = redis::pipe();
+                for new_key in &new_keys {
+                    pipe.cmd("EXISTS").arg(new_key);
+                }
+                let exists: Vec<bool> = pipe.query(conn).map_err(|err| err.to_string())?;
+                taken.extend(
+                    new_keys
+                        .into_iter()
+                        .zip(exists)
+                        .filter_map(|(new_key, exists)| exists.then_some(new_key)),
+                );
+                Ok(())
+            },
+        )?;
+        if let Some(key) = taken.first() {
+            return Err(format!(
+                "{} key(s) already exist under {new_prefix}, e.g. {key}",
+                taken.len()
+            ));
         }
 
-        Ok(())
+        // Every pass renames what it finds, so keys written meanwhile are
+        // picked up by the next one, until a pass finds nothing to move.
+        let mut moved: usize = 0;
+        loop {
+            let mut seen: BTreeSet<String> = BTreeSet::new();
+            let mut moved_now: usize = 0;
+            let mut skipped: usize = 0;
+            scan_keys(
+                &mut redis_connection,
+                &patterns,
+                batch_size,
+                |conn, keys| {
+                    let keys: Vec<String> = keys
+                        .into_iter()
+                        .filter(|key| seen.insert(key.clone()))
+                        .collect();
+                    if keys.is_empty() {
+                        return Ok(());
+                    }
+                    let mut pipe: redis::Pipeline = redis::pipe();
+                    for key in &keys {
+                        pipe.cmd("RENAMENX").arg(key).arg(get_moved_key(
+                            key,
+                            &old_prefix,
+                            &new_prefix,
+                        ));
+                    }
+                    let renamed: Vec<bool> = pipe.query(conn).map_err(|err| err.to_string())?;
+                    let renamed_now: usize = renamed.iter().filter(|x| **x).count();
+                    moved_now += renamed_now;
+                    skipped += renamed.len() - renamed_now;
+                    Ok(())
+                },
+            )?;
+            moved += moved_now;
+            if moved_now == 0 {
+                return match skipped {
+                    0 => Ok(moved),
+                    _ => Err(format!(
+                        "Moved {moved} key(s), {skipped} were left under {old_prefix} as their new names were taken meanwhile"
+                    )),
+                };
+            }
+        }
     }
 
-    fn update_email(&mut self, name: &str, new_email: String) -> Result<(), String> {
-        let new_email:String = get_valid_email(&new_email)?;
-        let mut redis_connection: RedisConnection = self.get_redis_connection()?;
-        let key: String = format!("{REDIS_KEY_PREFFIX}:{name}");
+    fn get_redis_connection(&self) -> Result<RefMut<'_, RedisConnection>, String> {
+        self.redis_connection.get()
+    }
+}
 
-        let no_of_subkeys_set: usize = redis::cmd("HSET").arg(&key)
-            .arg(REDIS_SUBKEY_EMAIL)
-            .arg(new_email)        
-            .query::<usize>(&mut redis_connection)
+/// Fills the index of every book from its contacts, whether or not it was
+/// filled before.
+fn index_books(store: &mut RedisStore) -> Result<(), String> {
+    let mut redis_connection: RefMut<RedisConnection> = store.get_redis_connection()?;
+    let books: Vec<String> = redis::cmd("SMEMBERS")
+        .arg(&store.keys.books)
+        .query::<Vec<String>>(&mut redis_connection)
+        .map_err(|err| err.to_string())?;
+    for book in std::iter::once(DEFAULT_BOOK.to_string()).chain(books) {
+        let keys: RedisKeys = RedisKeys::new(&store.key_prefix, &book, store.hash_tag);
+        let contact_keys: Vec<String> = redis::cmd("KEYS")
+            .arg(keys.contact("*"))
+            .query::<Vec<String>>(&mut redis_connection)
+            .map_err(|err| err.to_string())?;
+        get_fill_index_pipe(&keys, &contact_keys)
+            .query::<()>(&mut redis_connection)
             .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
 
-        if no_of_subkeys_set != 1 {
-            return Err(format!("Unexpected Redis HSET result. Expected 1, actual {no_of_subkeys_set}"));
-        }
-        
-        Ok(())
+fn read_schema_version(
+    redis_connection: &mut RedisConnection,
+    keys: &RedisKeys,
+) -> Result<u32, String> {
+    redis::cmd("GET")
+        .arg(&keys.schema)
+        .query::<Option<u32>>(redis_connection)
+        .map(|version| version.unwrap_or(0))
+        .map_err(|err| err.to_string())
+}
+
+/// The contact stored in the hash `key`, given all its fields.
+pub(super) fn parse_contact(
+    key: &str,
+    name: &str,
+    values: HashMap<String, String>,
+) -> Result<Option<Contact>, String> {
+    if values.is_empty() {
+        return Ok(None);
     }
 
-    fn update_phone_no(&mut self, name: &str, new_phone_no_as_string: String) -> Result<(), String> {
-        let new_phone_no: u64 = get_valid_phone_no(&new_phone_no_as_string)?;
-        let mut redis_connection: RedisConnection = self.get_redis_connection()?;
-        let key: String = format!("{REDIS_KEY_PREFFIX}:{name}");
+    let phone_no: u64 = values
+        .get(REDIS_SUBKEY_PHONE_NO)
+        .ok_or(format!("Missing {REDIS_SUBKEY_PHONE_NO} for key {key}"))?
+        .parse::<u64>()
+        .map_err(|err| err.to_string())?;
+    let email: String = values
+        .get(REDIS_SUBKEY_EMAIL)
+        .ok_or(format!("Missing {REDIS_SUBKEY_EMAIL} for key {key}"))?
+        .to_string();
+    let links: Vec<Link> = match values.get(REDIS_SUBKEY_LINKS) {
+        Some(x) => serde_json::from_str(x).map_err(|err| err.to_string())?,
+        None => Vec::new(),
+    };
+    let get_timestamp = |subkey: &str| -> Result<Option<i64>, String> {
+        match values.get(subkey) {
+            Some(x) => Ok(Some(x.parse::<i64>().map_err(|err| err.to_string())?)),
+            None => Ok(None),
+        }
+    };
+
+    Ok(Some(Contact {
+        name: name.to_string(),
+        phone_no,
+        email,
+        created_at: get_timestamp(REDIS_SUBKEY_CREATED_AT)?.unwrap_or(0),
+        updated_at: get_timestamp(REDIS_SUBKEY_UPDATED_AT)?.unwrap_or(0),
+        deleted_at: get_timestamp(REDIS_SUBKEY_DELETED_AT)?,
+        links,
+    }))
+}
 
-        let no_of_subkeys_set: usize = redis::cmd("HSET").arg(&key)
-            .arg(REDIS_SUBKEY_PHONE_NO)
-            .arg(new_phone_no)
-            .query::<usize>(&mut redis_connection)
+/// Queues the command appending a change, stamped now, to the audit stream,
+/// in the transaction making the change so that both happen or neither does.
+pub(super) fn add_change(
+    pipe: &mut redis::Pipeline,
+    keys: &RedisKeys,
+    operation: Operation,
+    name: &str,
+    old: Option<&Contact>,
+    new: Option<&Contact>,
+) -> Result<(), String> {
+    let change: Change = Change::new(
+        get_current_timestamp(),
+        get_current_operator(),
+        operation,
+        name,
+        old,
+        new,
+    );
+    let fields_json: String =
+        serde_json::to_string(&change.fields).map_err(|err| err.to_string())?;
+
+    let mut cmd: redis::Cmd = redis::cmd("XADD");
+    cmd.arg(&keys.audit)
+        .arg("*")
+        .arg(REDIS_AUDIT_FIELD_TIMESTAMP)
+        .arg(change.timestamp)
+        .arg(REDIS_AUDIT_FIELD_OPERATOR)
+        .arg(&change.operator)
+        .arg(REDIS_AUDIT_FIELD_OPERATION)
+        .arg(change.operation.to_string())
+        .arg(REDIS_AUDIT_FIELD_NAME)
+        .arg(&change.name)
+        .arg(REDIS_AUDIT_FIELD_FIELDS)
+        .arg(fields_json);
+    pipe.add_command(cmd).ignore();
+    Ok(())
+}
+
+/// The changes read from the audit stream by `XRANGE`.
+pub(super) fn parse_changes(entries: &[redis::Value]) -> Result<Vec<Change>, String> {
+    // Each entry is an `[id, [field, value, ...]]` pair, which has to be decoded
+    // on its own: a vector of tuples is decoded from a flat list.
+    let entries: Vec<(String, HashMap<String, String>)> = entries
+        .iter()
+        .map(redis::from_redis_value)
+        .collect::<Result<Vec<(String, HashMap<String, String>)>, redis::RedisError>>()
+        .map_err(|err| err.to_string())?;
+
+    let mut changes: Vec<Change> = Vec::new();
+
+    for (id, values) in entries {
+        let get_value = |field: &str| -> Result<&String, String> {
+            values
+                .get(field)
+                .ok_or(format!("Missing {field} for audit entry {id}"))
+        };
+
+        let fields: Vec<FieldChange> = serde_json::from_str(get_value(REDIS_AUDIT_FIELD_FIELDS)?)
             .map_err(|err| err.to_string())?;
 
-        if no_of_subkeys_set != 1 {
-            return Err(format!("Unexpected Redis HSET result. Expected 1, actual {no_of_subkeys_set}"));
-        }
+        changes.push(Change {
+            timestamp: get_value(REDIS_AUDIT_FIELD_TIMESTAMP)?
+                .parse::<i64>()
+                .map_err(|err| err.to_string())?,
+            operator: get_value(REDIS_AUDIT_FIELD_OPERATOR)?.to_string(),
+            operation: get_value(REDIS_AUDIT_FIELD_OPERATION)?.parse::<Operation>()?,
+            name: get_value(REDIS_AUDIT_FIELD_NAME)?.to_string(),
+            fields,
+        });
+    }
+
+    Ok(changes)
+}
 
-        Ok(())
+/// Queues the commands storing a contact and, when live, indexing its name.
+pub(super) fn add_write_contact(
+    pipe: &mut redis::Pipeline,
+    keys: &RedisKeys,
+    key: &str,
+    contact: &Contact,
+) {
+    pipe.cmd("HSET")
+        .arg(key)
+        .arg(REDIS_SUBKEY_PHONE_NO)
+        .arg(contact.phone_no)
+        .arg(REDIS_SUBKEY_EMAIL)
+        .arg(&contact.email)
+        .arg(REDIS_SUBKEY_CREATED_AT)
+        .arg(contact.created_at)
+        .arg(REDIS_SUBKEY_UPDATED_AT)
+        .arg(contact.updated_at);
+    if let Some(deleted_at) = contact.deleted_at {
+        pipe.arg(REDIS_SUBKEY_DELETED_AT).arg(deleted_at);
     }
+    pipe.ignore();
+    add_write_links(pipe, keys, key, &contact.name, &contact.links);
 
-    fn delete(&mut self, name: &str) -> Result<(), String> {
-        let mut redis_connection: RedisConnection = self.get_redis_connection()?;
-        let key: String = format!("{REDIS_KEY_PREFFIX}:{name}");
+    if contact.deleted_at.is_none() {
+        pipe.cmd("ZADD")
+            .arg(&keys.index)
+            .arg(0)
+            .arg(&contact.name)
+            .ignore();
+    }
+}
 
-        let no_of_subkeys_set: usize = redis::cmd("DEL").arg(&key)
-            .query::<usize>(&mut redis_connection)
-            .map_err(|err| err.to_string())?;
+/// Queues the commands storing the links of a contact, which are left out of
+/// the hash when there are none, and adding the contact to the backlinks of
+/// every contact it links to.
+pub(super) fn add_write_links(
+    pipe: &mut redis::Pipeline,
+    keys: &RedisKeys,
+    key: &str,
+    name: &str,
+    links: &[Link],
+) {
+    match links.is_empty() {
+        true => pipe.cmd("HDEL").arg(key).arg(REDIS_SUBKEY_LINKS).ignore(),
+        false => pipe
+            .cmd("HSET")
+            .arg(key)
+            .arg(REDIS_SUBKEY_LINKS)
+            .arg(serde_json::to_string(links).unwrap_or_default())
+            .ignore(),
+    };
+    for link in links {
+        pipe.cmd("SADD")
+            .arg(keys.backlinks_of(&link.name))
+            .arg(name)
+            .ignore();
+    }
+}
 
-        if no_of_subkeys_set != 1 {
-            return Err(format!("Unexpected Redis DEL result. Expected 1, actual {no_of_subkeys_set}"));
+/// Queues the commands removing a contact from the backlinks of the contacts
+/// it no longer links to.
+fn add_drop_backlinks(pipe: &mut redis::Pipeline, keys: &RedisKeys, old: &Contact, links: &[Link]) {
+    for link in &old.links {
+        if !links.iter().any(|x| x.name == link.name) {
+            pipe.cmd("SREM")
+                .arg(keys.backlinks_of(&link.name))
+                .arg(&old.name)
+                .ignore();
         }
-
-        Ok(())
     }
+}
 
-    fn get(&self, name: &str) -> Result<Option<Contact>, String> {
-        let mut redis_connection: RedisConnection = self.get_redis_connection()?;
-        let key: String = format!("{REDIS_KEY_PREFFIX}:{name}");
+/// The patterns matching every key of the store under `prefix`, in every book.
+fn get_store_patterns(prefix: &str) -> Vec<String> {
+    let trash: String = format!("{prefix}{REDIS_TRASH_KEY_SUFFIX}");
+    let index: String = format!("{prefix}{REDIS_INDEX_KEY_SUFFIX}");
+    let audit: String = format!("{prefix}{REDIS_AUDIT_KEY_SUFFIX}");
+    vec![
+        format!("{prefix}:*"),
+        format!("{prefix}@*"),
+        format!("{trash}:*"),
+        format!("{trash}@*"),
+        index.clone(),
+        format!("{index}@*"),
+        format!("{prefix}{REDIS_INDEXED_KEY_SUFFIX}"),
+        format!("{prefix}{REDIS_INDEXED_KEY_SUFFIX}@*"),
+        format!("{prefix}{REDIS_BACKLINKS_KEY_SUFFIX}:*"),
+        format!("{prefix}{REDIS_BACKLINKS_KEY_SUFFIX}@*"),
+        format!("{prefix}{REDIS_BACKLINKED_KEY_SUFFIX}"),
+        format!("{prefix}{REDIS_BACKLINKED_KEY_SUFFIX}@*"),
+        audit.clone(),
+        format!("{audit}@*"),
+        format!("{prefix}{REDIS_BOOKS_KEY_SUFFIX}"),
+        format!("{prefix}{REDIS_SCHEMA_KEY_SUFFIX}"),
+    ]
+}
 
-        let values: Vec<String> = redis::cmd("HGETALL").arg(&key)
-            .query::<Vec<String>>(&mut redis_connection)
-            .unwrap();  
+/// `key`, which starts with `old_prefix`, under `new_prefix` instead.
+fn get_moved_key(key: &str, old_prefix: &str, new_prefix: &str) -> String {
+    format!("{new_prefix}{}", &key[old_prefix.len()..])
+}
 
-        if values.len() % 4 != 0 {
-            return Err("Invalid response received from Redis, cannot construct key-value pairs list".to_string());
+/// Walks the keys matching any of `patterns` with `SCAN`, handing them to `f`
+/// about `batch_size` at a time. A key may be handed over more than once.
+fn scan_keys(
+    redis_connection: &mut RedisConnection,
+    patterns: &[String],
+    batch_size: usize,
+    mut f: impl FnMut(&mut RedisConnection, Vec<String>) -> Result<(), String>,
+) -> Result<(), String> {
+    for pattern in patterns {
+        let mut cursor: u64 = 0;
+        loop {
+            let (next_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(pattern)
+                .arg("COUNT")
+                .arg(batch_size)
+                .query(redis_connection)
+                .map_err(|err| err.to_string())?;
+            if !keys.is_empty() {
+                f(redis_connection, keys)?;
+            }
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
         }
-        
-        let phone_no_as_string: &String = &values[1];
-        let phone_no: u64 = phone_no_as_string.parse::<u64>()
-            .map_err(|err| err.to_string())?;
-        let email: &String = &values[3];
+    }
+    Ok(())
+}
 
-        let contact: Contact = Contact { name: name.to_string(), phone_no, email: email.to_string() };
-        Ok(Some(contact))
+/// The transaction indexing the names of the given contact keys and marking
+/// the index as filled.
+pub(super) fn get_fill_index_pipe(keys: &RedisKeys, contact_keys: &[String]) -> redis::Pipeline {
+    let mut pipe: redis::Pipeline = redis::pipe();
+    pipe.atomic();
+    if !contact_keys.is_empty() {
+        let mut cmd: redis::Cmd = redis::cmd("ZADD");
+        cmd.arg(&keys.index);
+        for key in contact_keys {
+            cmd.arg(0).arg(&key[keys.contacts.len() + 1..]);
+        }
+        pipe.add_command(cmd).ignore();
     }
+    pipe.cmd("SET").arg(&keys.indexed).arg(1).ignore();
+    pipe
+}
 
-    fn list(&self, page_no: usize, page_size: usize) -> Result<Vec<Contact>, String> {
-        let mut redis_connection: RedisConnection = self.get_redis_connection()?;
+/// The transaction adding the given contacts to the backlinks of the contacts
+/// they link to and marking the backlinks as filled.
+pub(super) fn get_fill_backlinks_pipe(keys: &RedisKeys, contacts: &[Contact]) -> redis::Pipeline {
+    let mut pipe: redis::Pipeline = redis::pipe();
+    pipe.atomic();
+    for contact in contacts {
+        for link in &contact.links {
+            pipe.cmd("SADD")
+                .arg(keys.backlinks_of(&link.name))
+                .arg(&contact.name)
+                .ignore();
+        }
+    }
+    pipe.cmd("SET").arg(&keys.backlinked).arg(1).ignore();
+    pipe
+}
 
-        let keys: Vec<String> = redis::cmd("KEYS")
-            .arg(format!("{REDIS_KEY_PREFFIX}:*"))
-            .query::<Vec<String>>(&mut redis_connection)
-            .map_err(|err| err.to_string())?;
+/// The command reading up to `limit` names from the index strictly past
+/// `bound`, walking it in lexicographical order if `forward`, in reverse order
+/// otherwise.
+pub(super) fn get_range_index_cmd(
+    keys: &RedisKeys,
+    forward: bool,
+    bound: Option<&str>,
+    limit: usize,
+) -> redis::Cmd {
+    let (command, min, max) = match (forward, bound) {
+        (true, Some(bound)) => ("ZRANGEBYLEX", format!("({bound}"), "+".to_string()),
+        (true, None) => ("ZRANGEBYLEX", "-".to_string(), "+".to_string()),
+        (false, Some(bound)) => ("ZREVRANGEBYLEX", format!("({bound}"), "-".to_string()),
+        (false, None) => ("ZREVRANGEBYLEX", "+".to_string(), "-".to_string()),
+    };
 
-        let mut map: BTreeMap<String, Contact> = BTreeMap::new();
+    let mut cmd: redis::Cmd = redis::cmd(command);
+    cmd.arg(&keys.index)
+        .arg(min)
+        .arg(max)
+        .arg("LIMIT")
+        .arg(0)
+        .arg(limit);
+    cmd
+}
 
-        for mut key in keys {
-            
-            let values: Vec<String> = redis::cmd("HGETALL").arg(&key)
-            .query::<Vec<String>>(&mut redis_connection)
-            .unwrap();  
-    
-            let index = key.find(':').unwrap_or(key.len());
-            let name: String = key.drain(1+index..).collect();
+/// The command setting one field of a contact and its update time.
+pub(super) fn get_update_cmd<T: redis::ToRedisArgs>(
+    key: &str,
+    subkey: &str,
+    value: T,
+    now: i64,
+) -> redis::Cmd {
+    let mut cmd: redis::Cmd = redis::cmd("HSET");
+    cmd.arg(key)
+        .arg(subkey)
+        .arg(value)
+        .arg(REDIS_SUBKEY_UPDATED_AT)
+        .arg(now);
+    cmd
+}
 
-            if values.len() % 4 != 0 {
-                return Err("Invalid response received from Redis, cannot construct key-value pairs list".to_string());
-            }
-            
-            let phone_no_as_string: &String = &values[1];
-            let phone_no: u64 = phone_no_as_string.parse::<u64>()
-                .map_err(|err| err.to_string())?;
-            let email: &String = &values[3];
-    
-            let contact: Contact = Contact { name: name.to_string(), phone_no, email: email.to_string() };
+/// Queues the commands changing the links of the live contact `old` to those
+/// of `new`, along with its update time.
+pub(super) fn add_update_links(
+    pipe: &mut redis::Pipeline,
+    keys: &RedisKeys,
+    old: &Contact,
+    new: &Contact,
+) {
+    let key: String = keys.contact(&old.name);
+    pipe.cmd("HSET")
+        .arg(&key)
+        .arg(REDIS_SUBKEY_UPDATED_AT)
+        .arg(new.updated_at)
+        .ignore();
+    add_drop_backlinks(pipe, keys, old, &new.links);
+    add_write_links(pipe, keys, &key, &old.name, &new.links);
+}
 
-            map.insert(name, contact);
-        }
+/// The transaction moving a contact to the trash, dropping its links and its
+/// backlinks.
+pub(super) fn get_delete_pipe(keys: &RedisKeys, old: &Contact, deleted_at: i64) -> redis::Pipeline {
+    let name: &str = &old.name;
+    let key: String = keys.contact(name);
+    let mut pipe: redis::Pipeline = redis::pipe();
+    pipe.atomic()
+        .cmd("HSET")
+        .arg(&key)
+        .arg(REDIS_SUBKEY_DELETED_AT)
+        .arg(deleted_at)
+        .ignore()
+        .cmd("HDEL")
+        .arg(&key)
+        .arg(REDIS_SUBKEY_LINKS)
+        .ignore()
+        .cmd("RENAME")
+        .arg(&key)
+        .arg(keys.trashed_contact(name))
+        .ignore()
+        .cmd("ZREM")
+        .arg(&keys.index)
+        .arg(name)
+        .ignore()
+        .cmd("DEL")
+        .arg(keys.backlinks_of(name))
+        .ignore();
+    add_drop_backlinks(&mut pipe, keys, old, &[]);
+    pipe
+}
 
-        let contacts: Vec<Contact> = map
-            .values()
-            .skip(page_no * page_size)
-            .take(page_size)
-            .map(|c| (*c).clone())
-            .collect();
+/// The transaction moving a contact back from the trash.
+pub(super) fn get_restore_pipe(keys: &RedisKeys, name: &str) -> redis::Pipeline {
+    let trash_key: String = keys.trashed_contact(name);
+    let mut pipe: redis::Pipeline = redis::pipe();
+    pipe.atomic()
+        .cmd("HDEL")
+        .arg(&trash_key)
+        .arg(REDIS_SUBKEY_DELETED_AT)
+        .ignore()
+        .cmd("RENAME")
+        .arg(&trash_key)
+        .arg(keys.contact(name))
+        .ignore()
+        .cmd("ZADD")
+        .arg(&keys.index)
+        .arg(0)
+        .arg(name)
+        .ignore();
+    pipe
+}
 
-        Ok(contacts)
+/// Adds the commands dropping the `old` live and `trash` contacts and the
+/// `backlinks` keys of a book, and storing `contacts` instead.
+pub(super) fn add_replace_all(
+    pipe: &mut redis::Pipeline,
+    keys: &RedisKeys,
+    old: &BTreeMap<String, Contact>,
+    trash: &BTreeMap<String, Contact>,
+    backlinks: &[String],
+    contacts: &[Contact],
+) {
+    pipe.cmd("DEL").arg(&keys.index).ignore();
+    for key in backlinks {
+        pipe.cmd("DEL").arg(key).ignore();
+    }
+    for name in old.keys() {
+        pipe.cmd("DEL").arg(keys.contact(name)).ignore();
+    }
+    for name in trash.keys() {
+        pipe.cmd("DEL").arg(keys.trashed_contact(name)).ignore();
+    }
+    for contact in contacts {
+        add_write_contact(pipe, keys, &keys.stored_contact(contact), contact);
     }
+    pipe.cmd("SET").arg(&keys.backlinked).arg(1).ignore();
+}
 
-    fn export_to_json(&self, path: String) -> Result<(), String> {
-        let mut redis_connection: RedisConnection = self.get_redis_connection()?;
+/// The transaction deleting the keys of a book and forgetting it.
+pub(super) fn get_delete_book_pipe(
+    keys: &RedisKeys,
+    book: &str,
+    book_keys: Vec<String>,
+) -> redis::Pipeline {
+    let mut pipe: redis::Pipeline = redis::pipe();
+    pipe.atomic()
+        .cmd("DEL")
+        .arg(book_keys)
+        .ignore()
+        .cmd("SREM")
+        .arg(&keys.books)
+        .arg(book)
+        .ignore();
+    pipe
+}
 
-        let keys: Vec<String> = redis::cmd("KEYS")
-            .arg(format!("{REDIS_KEY_PREFFIX}:*"))
-            .query::<Vec<String>>(&mut redis_connection)
-            .map_err(|err| err.to_string())?;
+/// The trashed contacts to purge: those deleted before the given timestamp,
+/// or all of them.
+pub(super) fn select_purged(
+    trash: BTreeMap<String, Contact>,
+    deleted_before: Option<i64>,
+) -> Vec<Contact> {
+    trash
+        .into_values()
+        .filter(|contact| match deleted_before {
+            Some(timestamp) => contact.deleted_at.unwrap_or(0) < timestamp,
+            None => true,
+        })
+        .collect()
+}
 
-        let mut map: BTreeMap<String, Contact> = BTreeMap::new();
+/// An imported contact, with the missing creation and update times filled in.
+pub(super) fn with_timestamps(mut contact: Contact, now: i64) -> Contact {
+    if contact.created_at == 0 {
+        contact.created_at = now;
+    }
+    if contact.updated_at == 0 {
+        contact.updated_at = contact.created_at;
+    }
+    contact
+}
 
-        for mut key in keys {
-            
-            let values: Vec<String> = redis::cmd("HGETALL").arg(&key)
-            .query::<Vec<String>>(&mut redis_connection)
-            .unwrap();  
-    
-            let index = key.find(':').unwrap_or(key.len());
-            let name: String = key.drain(1+index..).collect();
+/// The first audit stream id to read for changes since the given timestamp.
+/// Stream ids are milliseconds and cannot be negative, so anything at or
+/// before the epoch reads from the start.
+pub(super) fn get_audit_start(since: Option<i64>) -> String {
+    match since {
+        Some(timestamp) if timestamp > 0 => timestamp.saturating_mul(1000).to_string(),
+        _ => "-".to_string(),
+    }
+}
 
-            if values.len() % 4 != 0 {
-                return Err("Invalid response received from Redis, cannot construct key-value pairs list".to_string());
-            }
-            
-            let phone_no_as_string: &String = &values[1];
-            let phone_no: u64 = phone_no_as_string.parse::<u64>()
-                .map_err(|err| err.to_string())?;
-            let email: &String = &values[3];
-    
-            let contact: Contact = Contact { name: name.to_string(), phone_no, email: email.to_string() };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::link::LinkKind;
+    use crate::models::page::Page;
+    use crate::models::sort_by::SortBy;
+    use crate::repositories::conformance::check_conformance;
+    use crate::repositories::contacts::ContactsRepository;
+    use crate::repositories::fake_redis::FakeRedis;
+    use crate::repositories::migrations::MigrationStatus;
+    use crate::repositories::model_based::check_against_model_with;
+    use crate::repositories::redis_connection::get_key_slot;
+    use redis::{ConnectionInfo, IntoConnectionInfo};
+    use std::env;
+    use std::net::TcpListener;
+    use std::time::{Duration, Instant};
 
-            map.insert(name, contact);
-        }
+    /// The ignored tests run the suite against a real Redis whose books named
+    /// `conformance-*` may be wiped, e.g. `redis://127.0.0.1/15`, or a
+    /// `rediss://` one verified against `REDIS_CA_FILE`.
+    const TEST_REDIS_URL_KEY: &str = "CONTACTS_TEST_REDIS_URL";
+    /// And against a real cluster, given the comma separated URLs of some of
+    /// its nodes.
+    const TEST_REDIS_CLUSTER_NODES_KEY: &str = "CONTACTS_TEST_REDIS_CLUSTER_NODES";
 
-        let contacts: Vec<Contact> = map
-            .values()
-            .map(|c| (*c).clone())
-            .collect();
+    fn get_info(redis_url: &str) -> ConnectionInfo {
+        redis_url.into_connection_info().unwrap()
+    }
 
-        let json_str: String = serde_json::to_string(&contacts)
-            .map_err(|err| err.to_string())?;
-        let mut file: File = File::create(path)
-            .map_err(|err| err.to_string())?;
-        file.write_all(json_str.as_bytes())
-            .map_err(|err| err.to_string())?;
-        Ok(())
-    
+    fn get_target(redis_url: &str) -> RedisTarget {
+        RedisTarget::Server(get_info(redis_url))
     }
 
-    fn import_from_json(&mut self, path: String) -> Result<(), String> {
-        let inner: File = File::open(path)
-            .map_err(|err| err.to_string())?;
-        let rdr: BufReader<File> = BufReader::new(inner);
-        let contacts: Vec<Contact> = serde_json::from_reader(rdr)
-            .map_err(|err| err.to_string())?;
+    fn open(redis_url: &str) -> DbContactsRepository {
+        DbContactsRepository::open_target(get_target(redis_url), RedisOptions::from_env().unwrap())
+            .unwrap()
+    }
 
-        let mut redis_connection: RedisConnection = self.get_redis_connection()?;
+    #[test]
+    fn test_db_contacts_service_conformance() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        let mut contacts_service: DbContactsRepository = open(&fake_redis.url());
+        check_conformance(&mut contacts_service);
+    }
 
-        for contact in contacts {
-            
-            let key: String = format!("{REDIS_KEY_PREFFIX}:{}", contact.name);
+    #[test]
+    fn test_db_contacts_service_against_model() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        let mut contacts_service: DbContactsRepository = open(&fake_redis.url());
+        check_against_model_with(&mut contacts_service, 32);
+    }
 
-            let no_of_subkeys_set: usize = redis::cmd("HSET").arg(&key)
-                .arg(REDIS_SUBKEY_PHONE_NO)
-                .arg(contact.phone_no)
-                .arg(REDIS_SUBKEY_EMAIL)
-                .arg(contact.email)        
-                .query::<usize>(&mut redis_connection)
-                .map_err(|err| err.to_string())?;
+    #[test]
+    #[ignore = "needs CONTACTS_TEST_REDIS_URL"]
+    fn test_db_contacts_service_conformance_live() {
+        let redis_url: String = env::var(TEST_REDIS_URL_KEY)
+            .unwrap_or_else(|_| panic!("{TEST_REDIS_URL_KEY} is not set"));
+        let mut contacts_service: DbContactsRepository = open(&redis_url);
+        check_conformance(&mut contacts_service);
+    }
 
-            if no_of_subkeys_set != 2 {
-                return Err(format!("Unexpected Redis HSET result. Expected 2, actual {no_of_subkeys_set}"));
-            }
+    #[test]
+    #[ignore = "needs CONTACTS_TEST_REDIS_CLUSTER_NODES"]
+    fn test_db_contacts_service_conformance_live_cluster() {
+        let nodes: String = env::var(TEST_REDIS_CLUSTER_NODES_KEY)
+            .unwrap_or_else(|_| panic!("{TEST_REDIS_CLUSTER_NODES_KEY} is not set"));
+        let target: RedisTarget = RedisTarget::Cluster {
+            nodes: nodes.split(',').map(get_info).collect(),
+        };
+        let mut contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(target, RedisOptions::from_env().unwrap()).unwrap();
+        check_conformance(&mut contacts_service);
+    }
+
+    #[test]
+    fn test_get_audit_start() {
+        assert_eq!("-", get_audit_start(None));
+        assert_eq!("1700000000000", get_audit_start(Some(1_700_000_000)));
+        assert_eq!("-", get_audit_start(Some(0)));
+        assert_eq!("-", get_audit_start(Some(-2_208_988_800)));
+        assert_eq!(i64::MAX.to_string(), get_audit_start(Some(i64::MAX / 10)));
+    }
+
+    #[test]
+    fn test_db_contacts_service_key_prefix() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        let open_with_prefix = |key_prefix: &str| -> DbContactsRepository {
+            let options: RedisOptions = RedisOptions {
+                key_prefix: key_prefix.to_string(),
+                ..RedisOptions::default()
+            };
+            DbContactsRepository::open_target(get_target(&fake_redis.url()), options).unwrap()
+        };
+        let add = |contacts_service: &mut DbContactsRepository, name: &str| {
+            contacts_service
+                .add(
+                    name.to_string(),
+                    "491234567890".to_string(),
+                    format!("{}@mail.com", name.to_lowercase()),
+                )
+                .unwrap();
+        };
+
+        // Stores under different prefixes share the database but no keys.
+        let mut contacts_service: DbContactsRepository = open_with_prefix("contacts");
+        let mut team_service: DbContactsRepository = open_with_prefix("team-a");
+        add(&mut contacts_service, "Bogdan");
+        add(&mut contacts_service, "Mihai");
+        contacts_service.delete("Mihai").unwrap();
+        contacts_service.create_book("work").unwrap();
+        contacts_service.use_book("work").unwrap();
+        add(&mut contacts_service, "Ana");
+        contacts_service.use_book(DEFAULT_BOOK).unwrap();
+        add(&mut team_service, "Bogdan");
+        assert_eq!(1, contacts_service.count().unwrap());
+        assert_eq!(1, team_service.count().unwrap());
+        assert_eq!(vec![DEFAULT_BOOK], team_service.list_books().unwrap());
+
+        assert!(contacts_service
+            .migrate_prefix("contacts", "team_b", 2)
+            .is_err());
+        assert!(contacts_service
+            .migrate_prefix("contacts", "contacts", 2)
+            .is_err());
+        // Both stores have an index and a change log, so nothing is moved.
+        assert!(contacts_service
+            .migrate_prefix("contacts", "team-a", 2)
+            .err()
+            .unwrap()
+            .contains("already exist under team-a"));
+        assert_eq!(1, contacts_service.count().unwrap());
+
+        // The default book: Bogdan, the trashed Mihai, the index, the markers
+        // that it and the backlinks were filled, left by `count` and `delete`,
+        // and the change log; the work book: Ana, the index and the change log;
+        // the books.
+        assert_eq!(
+            10,
+            contacts_service
+                .migrate_prefix("contacts", "team-b", 2)
+                .unwrap()
+        );
+        let mut moved_service: DbContactsRepository = open_with_prefix("team-b");
+        assert_eq!(vec!["Bogdan"], moved_service.list_names().unwrap());
+        assert_eq!(1, moved_service.list_trash().unwrap().len());
+        assert_eq!(2, moved_service.history("Mihai").unwrap().len());
+        moved_service.use_book("work").unwrap();
+        assert_eq!(vec!["Ana"], moved_service.list_names().unwrap());
+        assert_eq!(1, team_service.count().unwrap());
+        assert_eq!(
+            0,
+            contacts_service
+                .migrate_prefix("contacts", "team-b", 2)
+                .unwrap()
+        );
+        assert_eq!(0, contacts_service.count().unwrap());
+    }
+
+    #[test]
+    fn test_db_contacts_service_index_backfill() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        // Contacts written before the index existed, in two books.
+        let client: redis::Client = redis::Client::open(fake_redis.url()).unwrap();
+        let mut connection: redis::Connection = client.get_connection().unwrap();
+        redis::pipe()
+            .cmd("HSET")
+            .arg("contacts:Bogdan")
+            .arg(REDIS_SUBKEY_PHONE_NO)
+            .arg("491234567890")
+            .arg(REDIS_SUBKEY_EMAIL)
+            .arg("bogdan@mail.com")
+            .cmd("HSET")
+            .arg("contacts@work:Ana")
+            .arg(REDIS_SUBKEY_PHONE_NO)
+            .arg("491234567891")
+            .arg(REDIS_SUBKEY_EMAIL)
+            .arg("ana@mail.com")
+            .cmd("SADD")
+            .arg("contacts_books")
+            .arg("work")
+            .query::<()>(&mut connection)
+            .unwrap();
+
+        // The first write creates the index with only the new contact in it.
+        let mut contacts_service: DbContactsRepository = open(&fake_redis.url());
+        contacts_service
+            .add(
+                "Mihai".to_string(),
+                "491234567892".to_string(),
+                "mihai@mail.com".to_string(),
+            )
+            .unwrap();
+        assert_eq!(2, contacts_service.count().unwrap());
+        assert_eq!(
+            vec!["Bogdan", "Mihai"],
+            contacts_service.list_names().unwrap()
+        );
+        let page: Page = contacts_service
+            .list_page(None, 10, SortBy::Name, false)
+            .unwrap();
+        assert_eq!(2, page.contacts.len());
+
+        contacts_service.use_book("work").unwrap();
+        contacts_service
+            .add(
+                "Ioana".to_string(),
+                "491234567893".to_string(),
+                "ioana@mail.com".to_string(),
+            )
+            .unwrap();
+        contacts_service.use_book(DEFAULT_BOOK).unwrap();
+        contacts_service.migrate_up().unwrap();
+        let indexed: Vec<String> = redis::cmd("ZRANGEBYLEX")
+            .arg("contacts_index@work")
+            .arg("-")
+            .arg("+")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(vec!["Ana", "Ioana"], indexed);
+    }
+
+    #[test]
+    fn test_db_contacts_service_backlinks() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        // A link written before backlinks were kept.
+        let client: redis::Client = redis::Client::open(fake_redis.url()).unwrap();
+        let mut connection: redis::Connection = client.get_connection().unwrap();
+        redis::pipe()
+            .cmd("HSET")
+            .arg("contacts:Ana")
+            .arg(REDIS_SUBKEY_PHONE_NO)
+            .arg("491234567890")
+            .arg(REDIS_SUBKEY_EMAIL)
+            .arg("ana@mail.com")
+            .arg(REDIS_SUBKEY_LINKS)
+            .arg(r#"[{"name":"Bogdan","kind":"colleague"}]"#)
+            .cmd("HSET")
+            .arg("contacts:Bogdan")
+            .arg(REDIS_SUBKEY_PHONE_NO)
+            .arg("491234567891")
+            .arg(REDIS_SUBKEY_EMAIL)
+            .arg("bogdan@mail.com")
+            .query::<()>(&mut connection)
+            .unwrap();
+        let get_backlinks = |connection: &mut redis::Connection, name: &str| -> Vec<String> {
+            redis::cmd("SMEMBERS")
+                .arg(format!("contacts_backlinks:{name}"))
+                .query(connection)
+                .unwrap()
+        };
+
+        let mut contacts_service: DbContactsRepository = open(&fake_redis.url());
+        contacts_service.delete("Bogdan").unwrap();
+        assert!(contacts_service
+            .get("Ana")
+            .unwrap()
+            .unwrap()
+            .links
+            .is_empty());
+        assert_eq!(1, contacts_service.history("Ana").unwrap().len());
+
+        // Both directions of a link are written in one transaction.
+        for (name, phone_no) in [("Mihai", "491234567892"), ("Ioana", "491234567893")] {
+            contacts_service
+                .add(
+                    name.to_string(),
+                    phone_no.to_string(),
+                    format!("{}@mail.com", name.to_lowercase()),
+                )
+                .unwrap();
         }
+        fake_redis.fail_next("XADD", "ERR injected");
+        assert!(contacts_service
+            .link("Mihai", "Ioana", LinkKind::Manager, true)
+            .is_err());
+        assert!(contacts_service.related("Mihai").unwrap().is_empty());
+        assert!(contacts_service.related("Ioana").unwrap().is_empty());
+        contacts_service
+            .link("Mihai", "Ioana", LinkKind::Manager, true)
+            .unwrap();
+        assert_eq!(vec!["Mihai"], get_backlinks(&mut connection, "Ioana"));
+        assert_eq!(vec!["Ioana"], get_backlinks(&mut connection, "Mihai"));
 
-        Ok(())
+        // So are a delete and the contacts it unlinks.
+        fake_redis.fail_next("XADD", "ERR injected");
+        assert!(contacts_service.delete("Ioana").is_err());
+        assert_eq!(1, contacts_service.related("Mihai").unwrap().len());
+        contacts_service.delete("Ioana").unwrap();
+        assert!(contacts_service.related("Mihai").unwrap().is_empty());
+        assert!(get_backlinks(&mut connection, "Ioana").is_empty());
+        assert!(get_backlinks(&mut connection, "Mihai").is_empty());
     }
 
-    fn count(&self) -> Result<usize, String> {
-        let mut redis_connection: RedisConnection = self.get_redis_connection()?;
+    #[test]
+    fn test_db_contacts_service_migrations() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        // A store written before the index and the schema version existed.
+        let client: redis::Client = redis::Client::open(fake_redis.url()).unwrap();
+        let mut connection: redis::Connection = client.get_connection().unwrap();
+        redis::pipe()
+            .cmd("HSET")
+            .arg("contacts:Bogdan")
+            .arg(REDIS_SUBKEY_PHONE_NO)
+            .arg("491234567890")
+            .arg(REDIS_SUBKEY_EMAIL)
+            .arg("bogdan@mail.com")
+            .cmd("HSET")
+            .arg("contacts@work:Ana")
+            .arg(REDIS_SUBKEY_PHONE_NO)
+            .arg("491234567891")
+            .arg(REDIS_SUBKEY_EMAIL)
+            .arg("ana@mail.com")
+            .cmd("SADD")
+            .arg("contacts_books")
+            .arg("work")
+            .query::<()>(&mut connection)
+            .unwrap();
 
-        let keys: Vec<String> = redis::cmd("KEYS")
-            .arg(format!("{REDIS_KEY_PREFFIX}:*"))
-            .query::<Vec<String>>(&mut redis_connection)
-            .map_err(|err| err.to_string())?;
+        let mut contacts_service: DbContactsRepository = open(&fake_redis.url());
+        let status: MigrationStatus = contacts_service.migration_status().unwrap();
+        assert_eq!(0, status.version);
+        assert_eq!(1, status.pending.len());
+        assert_eq!(status.pending, contacts_service.migrate_up().unwrap());
+        let indexed: Vec<bool> = redis::pipe()
+            .cmd("EXISTS")
+            .arg("contacts_index")
+            .cmd("EXISTS")
+            .arg("contacts_index@work")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(vec![true, true], indexed);
+        assert_eq!(1, contacts_service.migration_status().unwrap().version);
+        assert!(contacts_service.migrate_up().unwrap().is_empty());
+        contacts_service.use_book("work").unwrap();
+        assert_eq!(vec!["Ana"], contacts_service.list_names().unwrap());
+
+        // A store migrated by a newer release is left alone.
+        redis::cmd("SET")
+            .arg("contacts_schema")
+            .arg(2)
+            .query::<()>(&mut connection)
+            .unwrap();
+        assert!(contacts_service.migration_status().is_err());
+        assert!(contacts_service.migrate_up().is_err());
+    }
+
+    #[test]
+    fn test_db_contacts_service_faults() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        let mut contacts_service: DbContactsRepository = open(&fake_redis.url());
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491234567890".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+
+        fake_redis.fail_next("HGETALL", "ERR injected");
+        assert!(contacts_service
+            .get("Bogdan")
+            .err()
+            .unwrap()
+            .contains("injected"));
+        fake_redis.disconnect_next("HGETALL");
+        assert!(contacts_service.get("Bogdan").is_err());
+        assert!(contacts_service.get("Bogdan").unwrap().is_some());
+
+        // Moving to the trash happens in one transaction, so a failure halfway
+        // leaves the contact where it was.
+        fake_redis.fail_next("RENAME", "ERR injected");
+        assert!(contacts_service.delete("Bogdan").is_err());
+        assert!(contacts_service.get("Bogdan").unwrap().is_some());
+        assert_eq!(vec!["Bogdan"], contacts_service.list_names().unwrap());
+        assert!(contacts_service.list_trash().unwrap().is_empty());
+
+        // A change and its audit entry are written in one transaction: both
+        // happen or neither does.
+        fake_redis.disconnect_next("XADD");
+        assert!(contacts_service
+            .update_email("Bogdan", "bogdan@work.com".to_string())
+            .is_err());
+        fake_redis.fail_next("XADD", "ERR injected");
+        assert!(contacts_service
+            .update_email("Bogdan", "bogdan@work.com".to_string())
+            .is_err());
+        assert_eq!(
+            "bogdan@mail.com",
+            contacts_service.get("Bogdan").unwrap().unwrap().email
+        );
+        assert_eq!(1, contacts_service.history("Bogdan").unwrap().len());
+
+        contacts_service
+            .update_email("Bogdan", "bogdan@work.com".to_string())
+            .unwrap();
+        assert_eq!(
+            "bogdan@work.com",
+            contacts_service.get("Bogdan").unwrap().unwrap().email
+        );
+        assert_eq!(2, contacts_service.history("Bogdan").unwrap().len());
+
+        fake_redis.fail_next("XADD", "ERR injected");
+        assert!(contacts_service.delete("Bogdan").is_err());
+        assert_eq!(1, contacts_service.count().unwrap());
+        contacts_service.delete("Bogdan").unwrap();
+        assert_eq!(0, contacts_service.count().unwrap());
+        assert_eq!(3, contacts_service.history("Bogdan").unwrap().len());
+
+        // Restoring several books is one transaction too: a failure after the
+        // first book was written leaves it as it was and creates no book.
+        let bogdan: Contact = contacts_service.list_trash().unwrap().remove(0);
+        let books: BTreeMap<String, Vec<Contact>> = BTreeMap::from([
+            (
+                DEFAULT_BOOK.to_string(),
+                vec![Contact {
+                    deleted_at: None,
+                    ..bogdan.clone()
+                }],
+            ),
+            ("work".to_string(), vec![bogdan]),
+        ]);
+        fake_redis.fail_next("SADD", "ERR injected");
+        assert!(contacts_service.replace_books(books.clone()).is_err());
+        assert_eq!(0, contacts_service.count().unwrap());
+        assert_eq!(1, contacts_service.list_trash().unwrap().len());
+        assert_eq!(3, contacts_service.history("Bogdan").unwrap().len());
+        assert_eq!(vec![DEFAULT_BOOK], contacts_service.list_books().unwrap());
+
+        contacts_service.replace_books(books).unwrap();
+        assert_eq!(1, contacts_service.count().unwrap());
+        assert_eq!(4, contacts_service.history("Bogdan").unwrap().len());
+        assert_eq!(
+            vec![DEFAULT_BOOK, "work"],
+            contacts_service.list_books().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_db_contacts_service_connection() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        let options: RedisOptions = RedisOptions {
+            read_timeout: Duration::from_millis(200),
+            backoff: Duration::from_millis(10),
+            ..RedisOptions::default()
+        };
+        let mut contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(get_target(&fake_redis.url()), options).unwrap();
+        assert_eq!(0, fake_redis.connections());
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491234567890".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+        assert_eq!(1, contacts_service.count().unwrap());
+        assert!(contacts_service.get("Bogdan").unwrap().is_some());
+        assert_eq!(1, fake_redis.connections());
+
+        // A dropped connection fails the call in flight and the next one
+        // reconnects.
+        fake_redis.disconnect_next("HGETALL");
+        assert!(contacts_service
+            .get("Bogdan")
+            .err()
+            .unwrap()
+            .starts_with("Lost the connection to Redis"));
+        assert!(contacts_service.get("Bogdan").unwrap().is_some());
+        assert_eq!(2, fake_redis.connections());
+
+        // A late reply is never read as the answer to a later command.
+        fake_redis.stall_next("HGETALL", Duration::from_millis(500));
+        assert!(contacts_service
+            .get("Bogdan")
+            .err()
+            .unwrap()
+            .starts_with("Redis did not answer in time"));
+        assert_eq!(vec!["Bogdan"], contacts_service.list_names().unwrap());
+        assert!(contacts_service.get("Bogdan").unwrap().is_some());
+        assert_eq!(3, fake_redis.connections());
+    }
+
+    #[test]
+    fn test_db_contacts_service_connection_refused() {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let redis_url: String = format!("redis://{}/", listener.local_addr().unwrap());
+        drop(listener);
+
+        let options: RedisOptions = RedisOptions {
+            retries: 2,
+            backoff: Duration::from_millis(10),
+            ..RedisOptions::default()
+        };
+        let contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(get_target(&redis_url), options).unwrap();
+        let err: String = contacts_service.count().err().unwrap();
+        assert!(err.starts_with("Cannot connect to Redis at 127.0.0.1:"));
+        assert!(err.contains("after 3 attempts"));
+    }
+
+    #[test]
+    fn test_db_contacts_service_auth() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        fake_redis.require_auth("contacts", "secret");
+        let url: String = fake_redis
+            .url()
+            .replace("redis://", "redis://contacts:wrong@");
+        let options: RedisOptions = RedisOptions {
+            retries: 0,
+            ..RedisOptions::default()
+        };
+        let contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(get_target(&url), options.clone()).unwrap();
+        assert!(contacts_service
+            .count()
+            .err()
+            .unwrap()
+            .starts_with("Cannot connect to Redis"));
+
+        let url: String = fake_redis
+            .url()
+            .replace("redis://", "redis://contacts:secret@");
+        let contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(get_target(&url), options).unwrap();
+        assert_eq!(0, contacts_service.count().unwrap());
+    }
+
+    #[test]
+    fn test_db_contacts_service_tls() {
+        let fake_redis: FakeRedis = FakeRedis::start_tls();
+        fake_redis.require_auth("contacts", "secret");
+        let url: String = fake_redis
+            .url()
+            .replace("rediss://", "rediss://contacts:secret@");
+        let options: RedisOptions = RedisOptions {
+            retries: 0,
+            ca_file: Some(FakeRedis::ca_file()),
+            ..RedisOptions::default()
+        };
+        let mut contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(get_target(&format!("{url}1")), options.clone())
+                .unwrap();
+        check_conformance(&mut contacts_service);
+
+        // Without the CA the certificate of the server is not trusted.
+        let untrusted: RedisOptions = RedisOptions {
+            ca_file: None,
+            ..options.clone()
+        };
+        let contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(get_target(&url), untrusted).unwrap();
+        assert!(contacts_service
+            .count()
+            .err()
+            .unwrap()
+            .contains("TLS handshake failed"));
+
+        // A server that accepts the connection but never answers the
+        // handshake fails it within the connect timeout.
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url: String = format!(
+            "rediss://localhost:{}/",
+            listener.local_addr().unwrap().port()
+        );
+        let silent: RedisOptions = RedisOptions {
+            connect_timeout: Duration::from_millis(200),
+            ..options
+        };
+        let contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(get_target(&url), silent).unwrap();
+        let started: Instant = Instant::now();
+        assert!(contacts_service
+            .count()
+            .err()
+            .unwrap()
+            .contains("no answer within 200 ms"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // So does the blocking connection of the maintenance tasks.
+        let started: Instant = Instant::now();
+        assert!(contacts_service
+            .migrate_prefix("contacts", "people", 100)
+            .err()
+            .unwrap()
+            .contains("no TLS handshake within 200 ms"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_db_contacts_service_sentinel() {
+        let sentinel: FakeRedis = FakeRedis::start();
+        let old_master: FakeRedis = FakeRedis::start();
+        let new_master: FakeRedis = FakeRedis::start();
+        sentinel.set_sentinel_master("contacts", &old_master);
+        let target: RedisTarget = RedisTarget::Sentinel {
+            sentinels: vec![get_info(&sentinel.url())],
+            master: "contacts".to_string(),
+            server: get_info("redis://127.0.0.1/"),
+        };
+        let options: RedisOptions = RedisOptions {
+            retries: 0,
+            ..RedisOptions::default()
+        };
+        let mut contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(target, options).unwrap();
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491234567890".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+        assert_eq!(1, old_master.connections());
+
+        // After a failover the demoted master drops its clients and Sentinel
+        // reports the new one, which the next command connects to.
+        old_master.demote();
+        old_master.disconnect_next("HGETALL");
+        assert!(contacts_service.get("Bogdan").is_err());
+        assert!(contacts_service
+            .count()
+            .err()
+            .unwrap()
+            .contains("is not a master"));
+        sentinel.set_sentinel_master("contacts", &new_master);
+        assert_eq!(0, contacts_service.count().unwrap());
+        assert_eq!(1, new_master.connections());
+    }
 
-        Ok(keys.len())
+    #[test]
+    fn test_db_contacts_service_cluster() {
+        let seed: FakeRedis = FakeRedis::start();
+        let owner: FakeRedis = FakeRedis::start();
+        // Only the slot of the store is served by the owner, so that any key
+        // outside of it is redirected and fails the call.
+        let slot: u16 = get_key_slot(&RedisOptions::default().key_prefix);
+        let slots: Vec<(u16, u16, &FakeRedis)> = vec![
+            (0, slot - 1, &seed),
+            (slot, slot, &owner),
+            (slot + 1, 16383, &seed),
+        ];
+        seed.set_cluster_slots(&slots);
+        owner.set_cluster_slots(&slots);
+        let target: RedisTarget = RedisTarget::Cluster {
+            nodes: vec![get_info(&seed.url())],
+        };
+        let mut contacts_service: DbContactsRepository =
+            DbContactsRepository::open_target(target, RedisOptions::default()).unwrap();
+        check_conformance(&mut contacts_service);
+        assert_eq!(1, owner.connections());
     }
 }
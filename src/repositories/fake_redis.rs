@@ -0,0 +1,893 @@
+//! An in-process Redis speaking RESP on an ephemeral localhost port, so the
+//! Redis repository can be tested without an external server. It implements
+//! the commands the repository sends and can inject faults: error replies,
+//! dropped connections and late replies. It can also require a password, act
+//! as a Sentinel and pose as a node of a cluster, redirecting keys of slots it
+//! does not serve, and serve TLS with a certificate from `testdata`, signed by
+//! the CA in `ca.pem` for `localhost` and `127.0.0.1`.
+//!
+//! Sorted sets ignore scores and order their members by name, as the
+//! repository gives every member of its index the same score. Every logical
+//! database is a store of its own. The `SCAN` cursor is an offset into the
+//! matching keys, so removing keys while scanning may skip others.
+
+use native_tls::{Identity, TlsAcceptor};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::redis_connection::get_key_slot;
+
+enum Value {
+    String(String),
+    Hash(BTreeMap<String, String>),
+    Set(BTreeSet<String>),
+    SortedSet(BTreeSet<String>),
+    Stream(Vec<(StreamId, Vec<String>)>),
+}
+
+type StreamId = (u64, u64);
+
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<String>),
+    Array(Vec<Reply>),
+}
+
+enum Fault {
+    Error(String),
+    Disconnect,
+}
+
+#[derive(Default)]
+struct Store {
+    values: BTreeMap<String, Value>,
+    last_stream_id: StreamId,
+}
+
+#[derive(Default)]
+struct Shared {
+    stores: Mutex<BTreeMap<i64, Store>>,
+    faults: Mutex<VecDeque<(String, Fault)>>,
+    stalls: Mutex<VecDeque<(String, Duration)>>,
+    connections: AtomicUsize,
+    stopped: AtomicBool,
+    credentials: Mutex<Option<(String, String)>>,
+    replica: AtomicBool,
+    sentinel_master: Mutex<Option<(String, SocketAddr)>>,
+    cluster_slots: Mutex<Vec<(u16, u16, SocketAddr)>>,
+}
+
+pub struct FakeRedis {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    tls: bool,
+}
+
+impl FakeRedis {
+    pub fn start() -> Self {
+        FakeRedis::start_with(None)
+    }
+
+    /// Serves TLS only, see `ca_file`.
+    pub fn start_tls() -> Self {
+        let identity: Identity = Identity::from_pkcs8(
+            include_bytes!("testdata/server.pem"),
+            include_bytes!("testdata/server.key"),
+        )
+        .unwrap();
+        FakeRedis::start_with(Some(TlsAcceptor::new(identity).unwrap()))
+    }
+
+    fn start_with(acceptor: Option<TlsAcceptor>) -> Self {
+        let listener: TcpListener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        let shared: Arc<Shared> = Arc::new(Shared::default());
+        let tls: bool = acceptor.is_some();
+
+        let accepting: Arc<Shared> = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accepting.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Ok(stream) = stream {
+                    accepting.connections.fetch_add(1, Ordering::SeqCst);
+                    let serving: Arc<Shared> = accepting.clone();
+                    let acceptor: Option<TlsAcceptor> = acceptor.clone();
+                    thread::spawn(move || match acceptor {
+                        Some(acceptor) => {
+                            if let Ok(stream) = acceptor.accept(stream) {
+                                serve(stream, &serving, addr);
+                            }
+                        }
+                        None => serve(stream, &serving, addr),
+                    });
+                }
+            }
+        });
+
+        FakeRedis { addr, shared, tls }
+    }
+
+    pub fn url(&self) -> String {
+        match self.tls {
+            true => format!("rediss://localhost:{}/", self.addr.port()),
+            false => format!("redis://{}/", self.addr),
+        }
+    }
+
+    /// The CA that signed the certificate of `start_tls` servers.
+    pub fn ca_file() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/repositories/testdata/ca.pem")
+    }
+
+    /// Answers the next `command` with an error instead of running it.
+    pub fn fail_next(&self, command: &str, message: &str) {
+        self.inject(command, Fault::Error(message.to_string()));
+    }
+
+    /// Closes the connection when the next `command` arrives, without a reply.
+    pub fn disconnect_next(&self, command: &str) {
+        self.inject(command, Fault::Disconnect);
+    }
+
+    /// Waits for `delay` when the next `command` arrives, then runs it.
+    pub fn stall_next(&self, command: &str, delay: Duration) {
+        self.shared
+            .stalls
+            .lock()
+            .unwrap()
+            .push_back((command.to_uppercase(), delay));
+    }
+
+    /// How many connections were accepted so far.
+    pub fn connections(&self) -> usize {
+        self.shared.connections.load(Ordering::SeqCst)
+    }
+
+    /// Refuses every command until the client authenticated as `username`.
+    pub fn require_auth(&self, username: &str, password: &str) {
+        *self.shared.credentials.lock().unwrap() =
+            Some((username.to_string(), password.to_string()));
+    }
+
+    /// Reports itself as a replica to `ROLE`.
+    pub fn demote(&self) {
+        self.shared.replica.store(true, Ordering::SeqCst);
+    }
+
+    /// Answers Sentinel queries for the master `name` with `master`.
+    pub fn set_sentinel_master(&self, name: &str, master: &FakeRedis) {
+        *self.shared.sentinel_master.lock().unwrap() = Some((name.to_string(), master.addr));
+    }
+
+    /// Serves `CLUSTER SLOTS` from `slots`, each a range of slots and the
+    /// node serving it, and redirects keys of slots served by other nodes.
+    pub fn set_cluster_slots(&self, slots: &[(u16, u16, &FakeRedis)]) {
+        *self.shared.cluster_slots.lock().unwrap() = slots
+            .iter()
+            .map(|(start, end, node)| (*start, *end, node.addr))
+            .collect();
+    }
+
+    fn inject(&self, command: &str, fault: Fault) {
+        self.shared
+            .faults
+            .lock()
+            .unwrap()
+            .push_back((command.to_uppercase(), fault));
+    }
+}
+
+impl Drop for FakeRedis {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+        // Wakes the accept loop up so that it sees the flag.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve(stream: impl Read + Write, shared: &Shared, addr: SocketAddr) {
+    let mut reader: BufReader<_> = BufReader::new(stream);
+    let mut transaction: Option<Vec<Vec<String>>> = None;
+    let mut aborted: bool = false;
+    let mut authenticated: bool = shared.credentials.lock().unwrap().is_none();
+    let mut db: i64 = 0;
+
+    while let Some(args) = read_command(&mut reader) {
+        let name: String = args.first().cloned().unwrap_or_default().to_uppercase();
+        if let Some(delay) = take_stall(shared, &name) {
+            thread::sleep(delay);
+        }
+        let fault: Option<Fault> = take_fault(shared, &name);
+        let moved: Option<String> = get_moved(shared, addr, &args);
+
+        let reply: Reply = match (fault, name.as_str()) {
+            (Some(Fault::Disconnect), _) => return,
+            (Some(Fault::Error(message)), _) => {
+                aborted |= transaction.is_some();
+                Reply::Error(message)
+            }
+            (None, "AUTH") => {
+                authenticated = is_authenticated(shared, &args[1..]);
+                match authenticated {
+                    true => Reply::Status("OK"),
+                    false => Reply::Error(
+                        "WRONGPASS invalid username-password pair or user is disabled.".to_string(),
+                    ),
+                }
+            }
+            (None, _) if !authenticated => {
+                Reply::Error("NOAUTH Authentication required.".to_string())
+            }
+            (None, "ROLE") => Reply::Array(vec![
+                bulk(match shared.replica.load(Ordering::SeqCst) {
+                    true => "slave",
+                    false => "master",
+                }),
+                Reply::Integer(0),
+                Reply::Array(Vec::new()),
+            ]),
+            (None, "SENTINEL") => get_sentinel_master(shared, &args[1..]),
+            (None, "CLUSTER") => get_cluster_slots(shared, &args[1..]),
+            (None, _) if moved.is_some() => {
+                aborted |= transaction.is_some();
+                Reply::Error(moved.unwrap_or_default())
+            }
+            (None, "SELECT") if transaction.is_none() => {
+                match args.get(1).and_then(|index| index.parse::<i64>().ok()) {
+                    Some(index) if args.len() == 2 && index >= 0 => {
+                        db = index;
+                        Reply::Status("OK")
+                    }
+                    _ => Reply::Error("ERR DB index is out of range".to_string()),
+                }
+            }
+            (None, "MULTI") => {
+                transaction = Some(Vec::new());
+                aborted = false;
+                Reply::Status("OK")
+            }
+            (None, "DISCARD") => {
+                transaction = None;
+                Reply::Status("OK")
+            }
+            (None, "EXEC") => match transaction.take() {
+                None => Reply::Error("ERR EXEC without MULTI".to_string()),
+                Some(_) if aborted => Reply::Error(
+                    "EXECABORT Transaction discarded because of previous errors.".to_string(),
+                ),
+                Some(commands) => {
+                    let mut stores = shared.stores.lock().unwrap();
+                    let store: &mut Store = stores.entry(db).or_default();
+                    Reply::Array(
+                        commands
+                            .iter()
+                            .map(|command| execute(store, command))
+                            .collect(),
+                    )
+                }
+            },
+            (None, _) => match transaction.as_mut() {
+                Some(commands) => {
+                    commands.push(args);
+                    Reply::Status("QUEUED")
+                }
+                None => execute(shared.stores.lock().unwrap().entry(db).or_default(), &args),
+            },
+        };
+
+        let mut bytes: Vec<u8> = Vec::new();
+        encode(&reply, &mut bytes);
+        if reader.get_mut().write_all(&bytes).is_err() {
+            return;
+        }
+    }
+}
+
+fn take_fault(shared: &Shared, name: &str) -> Option<Fault> {
+    let mut faults = shared.faults.lock().unwrap();
+    let index: usize = faults.iter().position(|(command, _)| command == name)?;
+    faults.remove(index).map(|(_, fault)| fault)
+}
+
+fn is_authenticated(shared: &Shared, args: &[String]) -> bool {
+    let credentials = shared.credentials.lock().unwrap();
+    match (credentials.as_ref(), args) {
+        (None, _) => true,
+        (Some((username, password)), [user, pass]) => user == username && pass == password,
+        (Some((username, password)), [pass]) => username == "default" && pass == password,
+        _ => false,
+    }
+}
+
+fn get_sentinel_master(shared: &Shared, args: &[String]) -> Reply {
+    match (shared.sentinel_master.lock().unwrap().as_ref(), args) {
+        (Some((name, addr)), [command, master])
+            if command.eq_ignore_ascii_case("get-master-addr-by-name") && master == name =>
+        {
+            Reply::Array(vec![
+                bulk(&addr.ip().to_string()),
+                bulk(&addr.port().to_string()),
+            ])
+        }
+        (_, [command, _]) if command.eq_ignore_ascii_case("get-master-addr-by-name") => {
+            Reply::Bulk(None)
+        }
+        _ => Reply::Error("ERR unknown SENTINEL subcommand".to_string()),
+    }
+}
+
+fn get_cluster_slots(shared: &Shared, args: &[String]) -> Reply {
+    let slots = shared.cluster_slots.lock().unwrap();
+    match args {
+        [command] if command.eq_ignore_ascii_case("SLOTS") && !slots.is_empty() => Reply::Array(
+            slots
+                .iter()
+                .map(|(start, end, addr)| {
+                    Reply::Array(vec![
+                        Reply::Integer(i64::from(*start)),
+                        Reply::Integer(i64::from(*end)),
+                        Reply::Array(vec![
+                            bulk(&addr.ip().to_string()),
+                            Reply::Integer(i64::from(addr.port())),
+                            bulk("fake"),
+                        ]),
+                    ])
+                })
+                .collect(),
+        ),
+        _ => Reply::Error("ERR This instance has cluster support disabled".to_string()),
+    }
+}
+
+/// The redirection for a command on keys of a slot served by another node of
+/// the cluster, if any.
+fn get_moved(shared: &Shared, addr: SocketAddr, args: &[String]) -> Option<String> {
+    let slots = shared.cluster_slots.lock().unwrap();
+    if slots.is_empty() {
+        return None;
+    }
+    let name: String = args.first()?.to_uppercase();
+    let keys: &[String] = match name.as_str() {
+        "EXISTS" | "DEL" => &args[1..],
+        "RENAME" | "RENAMENX" => args.get(1..3)?,
+        "GET" | "SET" | "HSET" | "HGETALL" | "HDEL" | "SADD" | "SREM" | "SMEMBERS"
+        | "SISMEMBER" | "ZADD" | "ZREM" | "ZCARD" | "ZRANGEBYLEX" | "ZREVRANGEBYLEX" | "XADD"
+        | "XRANGE" => args.get(1..2)?,
+        _ => return None,
+    };
+    keys.iter().find_map(|key| {
+        let slot: u16 = get_key_slot(key);
+        match slots
+            .iter()
+            .find(|(start, end, _)| (*start..=*end).contains(&slot))
+        {
+            Some((_, _, owner)) if *owner == addr => None,
+            Some((_, _, owner)) => Some(format!("MOVED {slot} {owner}")),
+            None => Some(format!("CLUSTERDOWN Hash slot {slot} not served")),
+        }
+    })
+}
+
+fn take_stall(shared: &Shared, name: &str) -> Option<Duration> {
+    let mut stalls = shared.stalls.lock().unwrap();
+    let index: usize = stalls.iter().position(|(command, _)| command == name)?;
+    stalls.remove(index).map(|(_, delay)| delay)
+}
+
+/// Reads one command sent as an array of bulk strings, or `None` once the
+/// client hung up or sent something else.
+fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+    let header: String = read_line(reader)?;
+    let len: usize = header.strip_prefix('*')?.parse().ok()?;
+
+    let mut args: Vec<String> = Vec::with_capacity(len);
+    for _ in 0..len {
+        let bulk_len: usize = read_line(reader)?.strip_prefix('$')?.parse().ok()?;
+        let mut bytes: Vec<u8> = vec![0; bulk_len + 2];
+        reader.read_exact(&mut bytes).ok()?;
+        bytes.truncate(bulk_len);
+        args.push(String::from_utf8_lossy(&bytes).to_string());
+    }
+    Some(args)
+}
+
+fn read_line(reader: &mut impl BufRead) -> Option<String> {
+    let mut line: String = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line.trim_end().to_string()),
+    }
+}
+
+fn encode(reply: &Reply, bytes: &mut Vec<u8>) {
+    match reply {
+        Reply::Status(status) => bytes.extend(format!("+{status}\r\n").as_bytes()),
+        Reply::Error(message) => bytes.extend(format!("-{message}\r\n").as_bytes()),
+        Reply::Integer(x) => bytes.extend(format!(":{x}\r\n").as_bytes()),
+        Reply::Bulk(None) => bytes.extend(b"$-1\r\n"),
+        Reply::Bulk(Some(text)) => {
+            bytes.extend(format!("${}\r\n{text}\r\n", text.len()).as_bytes())
+        }
+        Reply::Array(items) => {
+            bytes.extend(format!("*{}\r\n", items.len()).as_bytes());
+            for item in items {
+                encode(item, bytes);
+            }
+        }
+    }
+}
+
+fn bulk(text: &str) -> Reply {
+    Reply::Bulk(Some(text.to_string()))
+}
+
+fn strings(items: impl IntoIterator<Item = String>) -> Reply {
+    Reply::Array(items.into_iter().map(|x| Reply::Bulk(Some(x))).collect())
+}
+
+fn wrong_type() -> Reply {
+    Reply::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+}
+
+fn wrong_args(name: &str) -> Reply {
+    Reply::Error(format!(
+        "ERR wrong number of arguments for '{}' command",
+        name.to_lowercase()
+    ))
+}
+
+/// The value at `key` as the given variant, created from `default` when
+/// missing. Replies WRONGTYPE when the key holds another kind of value.
+macro_rules! value_mut {
+    ($store:expr, $key:expr, $variant:ident, $default:expr) => {
+        match $store
+            .values
+            .entry($key.to_string())
+            .or_insert_with(|| Value::$variant($default))
+        {
+            Value::$variant(x) => x,
+            _ => return wrong_type(),
+        }
+    };
+}
+
+/// Like `value_mut!`, but replies `empty` when the key is missing.
+macro_rules! value_ref {
+    ($store:expr, $key:expr, $variant:ident, $empty:expr) => {
+        match $store.values.get($key) {
+            Some(Value::$variant(x)) => x,
+            Some(_) => return wrong_type(),
+            None => return $empty,
+        }
+    };
+}
+
+fn execute(store: &mut Store, args: &[String]) -> Reply {
+    let name: String = args.first().cloned().unwrap_or_default().to_uppercase();
+    let args: &[String] = args.get(1..).unwrap_or_default();
+
+    let reply: Reply = match (name.as_str(), args) {
+        ("PING", []) => Reply::Status("PONG"),
+        ("FLUSHDB" | "FLUSHALL", _) => {
+            store.values.clear();
+            Reply::Status("OK")
+        }
+        ("EXISTS", [_, ..]) => Reply::Integer(
+            args.iter()
+                .filter(|key| store.values.contains_key(*key))
+                .count() as i64,
+        ),
+        ("DEL", [_, ..]) => Reply::Integer(
+            args.iter()
+                .filter(|key| store.values.remove(*key).is_some())
+                .count() as i64,
+        ),
+        ("KEYS", [pattern]) => strings(
+            store
+                .values
+                .keys()
+                .filter(|key| matches_glob(pattern, key))
+                .cloned(),
+        ),
+        ("SCAN", [cursor, options @ ..]) => {
+            let Ok(cursor) = cursor.parse::<usize>() else {
+                return Reply::Error("ERR invalid cursor".to_string());
+            };
+            let mut pattern: &str = "*";
+            let mut count: usize = 10;
+            for option in options.chunks(2) {
+                match option {
+                    [name, value] if name.eq_ignore_ascii_case("MATCH") => pattern = value,
+                    [name, value] if name.eq_ignore_ascii_case("COUNT") => {
+                        count = value.parse::<usize>().unwrap_or(count)
+                    }
+                    _ => return wrong_args(&name),
+                }
+            }
+            let keys: Vec<String> = store
+                .values
+                .keys()
+                .filter(|key| matches_glob(pattern, key))
+                .skip(cursor)
+                .take(count + 1)
+                .cloned()
+                .collect();
+            let next_cursor: usize = match keys.len() > count {
+                true => cursor + count,
+                false => 0,
+            };
+            Reply::Array(vec![
+                bulk(&next_cursor.to_string()),
+                strings(keys.into_iter().take(count)),
+            ])
+        }
+        ("RENAME", [from, to]) => match store.values.remove(from) {
+            Some(value) => {
+                store.values.insert(to.to_string(), value);
+                Reply::Status("OK")
+            }
+            None => Reply::Error("ERR no such key".to_string()),
+        },
+        ("RENAMENX", [from, to]) => match store.values.contains_key(to) {
+            _ if !store.values.contains_key(from) => Reply::Error("ERR no such key".to_string()),
+            true => Reply::Integer(0),
+            false => {
+                let value: Value = store.values.remove(from).unwrap();
+                store.values.insert(to.to_string(), value);
+                Reply::Integer(1)
+            }
+        },
+        ("GET", [key]) => Reply::Bulk(Some(
+            value_ref!(store, key, String, Reply::Bulk(None)).clone(),
+        )),
+        ("SET", [key, value]) => {
+            store
+                .values
+                .insert(key.clone(), Value::String(value.clone()));
+            Reply::Status("OK")
+        }
+        ("HSET", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            let hash = value_mut!(store, key, Hash, BTreeMap::new());
+            let added: usize = pairs
+                .chunks(2)
+                .filter(|pair| hash.insert(pair[0].clone(), pair[1].clone()).is_none())
+                .count();
+            Reply::Integer(added as i64)
+        }
+        ("HGETALL", [key]) => {
+            let hash = value_ref!(store, key, Hash, Reply::Array(Vec::new()));
+            strings(hash.iter().flat_map(|(f, v)| [f.clone(), v.clone()]))
+        }
+        ("HDEL", [key, fields @ ..]) if !fields.is_empty() => {
+            let hash = value_ref!(store, key, Hash, Reply::Integer(0));
+            let removed: usize = fields.iter().filter(|f| hash.contains_key(*f)).count();
+            let hash = value_mut!(store, key, Hash, BTreeMap::new());
+            for field in fields {
+                hash.remove(field);
+            }
+            if hash.is_empty() {
+                store.values.remove(key);
+            }
+            Reply::Integer(removed as i64)
+        }
+        ("SADD", [key, members @ ..]) if !members.is_empty() => {
+            let set = value_mut!(store, key, Set, BTreeSet::new());
+            Reply::Integer(members.iter().filter(|m| set.insert(m.to_string())).count() as i64)
+        }
+        ("SREM", [key, members @ ..]) if !members.is_empty() => {
+            value_ref!(store, key, Set, Reply::Integer(0));
+            let set = value_mut!(store, key, Set, BTreeSet::new());
+            let removed: usize = members.iter().filter(|m| set.remove(*m)).count();
+            if set.is_empty() {
+                store.values.remove(key);
+            }
+            Reply::Integer(removed as i64)
+        }
+        ("SMEMBERS", [key]) => {
+            let set = value_ref!(store, key, Set, Reply::Array(Vec::new()));
+            strings(set.iter().cloned())
+        }
+        ("SISMEMBER", [key, member]) => {
+            let set = value_ref!(store, key, Set, Reply::Integer(0));
+            Reply::Integer(set.contains(member) as i64)
+        }
+        ("ZADD", [key, pairs @ ..]) if !pairs.is_empty() && pairs.len() % 2 == 0 => {
+            let zset = value_mut!(store, key, SortedSet, BTreeSet::new());
+            let added: usize = pairs
+                .chunks(2)
+                .filter(|pair| zset.insert(pair[1].clone()))
+                .count();
+            Reply::Integer(added as i64)
+        }
+        ("ZREM", [key, members @ ..]) if !members.is_empty() => {
+            value_ref!(store, key, SortedSet, Reply::Integer(0));
+            let zset = value_mut!(store, key, SortedSet, BTreeSet::new());
+            let removed: usize = members.iter().filter(|m| zset.remove(*m)).count();
+            if zset.is_empty() {
+                store.values.remove(key);
+            }
+            Reply::Integer(removed as i64)
+        }
+        ("ZCARD", [key]) => {
+            let zset = value_ref!(store, key, SortedSet, Reply::Integer(0));
+            Reply::Integer(zset.len() as i64)
+        }
+        ("ZRANGEBYLEX", [key, min, max, limit @ ..]) => {
+            let zset = value_ref!(store, key, SortedSet, Reply::Array(Vec::new()));
+            range_by_lex(zset.iter(), min, max, limit)
+        }
+        ("ZREVRANGEBYLEX", [key, max, min, limit @ ..]) => {
+            let zset = value_ref!(store, key, SortedSet, Reply::Array(Vec::new()));
+            range_by_lex(zset.iter().rev(), min, max, limit)
+        }
+        ("XADD", [key, id, pairs @ ..])
+            if id == "*" && !pairs.is_empty() && pairs.len() % 2 == 0 =>
+        {
+            let now: u64 = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|x| x.as_millis() as u64)
+                .unwrap_or_default();
+            let (last_ms, last_seq) = store.last_stream_id;
+            let id: StreamId = match now > last_ms {
+                true => (now, 0),
+                false => (last_ms, last_seq + 1),
+            };
+            store.last_stream_id = id;
+            let stream = value_mut!(store, key, Stream, Vec::new());
+            stream.push((id, pairs.to_vec()));
+            bulk(&format!("{}-{}", id.0, id.1))
+        }
+        ("XRANGE", [key, start, end]) => {
+            let stream = value_ref!(store, key, Stream, Reply::Array(Vec::new()));
+            let (start, end) = match (parse_stream_id(start, 0), parse_stream_id(end, u64::MAX)) {
+                (Some(start), Some(end)) => (start, end),
+                _ => {
+                    return Reply::Error(
+                        "ERR Invalid stream ID specified as stream command argument".to_string(),
+                    )
+                }
+            };
+            Reply::Array(
+                stream
+                    .iter()
+                    .filter(|(id, _)| start <= *id && *id <= end)
+                    .map(|(id, fields)| {
+                        Reply::Array(vec![
+                            bulk(&format!("{}-{}", id.0, id.1)),
+                            strings(fields.iter().cloned()),
+                        ])
+                    })
+                    .collect(),
+            )
+        }
+        (
+            "PING" | "EXISTS" | "DEL" | "KEYS" | "SCAN" | "RENAME" | "RENAMENX" | "GET" | "SET"
+            | "HSET" | "HGETALL" | "HDEL" | "SADD" | "SREM" | "SMEMBERS" | "SISMEMBER" | "ZADD"
+            | "ZREM" | "ZCARD" | "ZRANGEBYLEX" | "ZREVRANGEBYLEX" | "XADD" | "XRANGE",
+            _,
+        ) => wrong_args(&name),
+        _ => Reply::Error(format!("ERR unknown command '{}'", name.to_lowercase())),
+    };
+    reply
+}
+
+/// Parses `-`, `+`, `<ms>` or `<ms>-<seq>`, where a missing sequence number
+/// defaults to `seq`.
+fn parse_stream_id(text: &str, seq: u64) -> Option<StreamId> {
+    match text {
+        "-" => Some((0, 0)),
+        "+" => Some((u64::MAX, u64::MAX)),
+        _ => match text.split_once('-') {
+            Some((ms, s)) => Some((ms.parse().ok()?, s.parse().ok()?)),
+            None => Some((text.parse().ok()?, seq)),
+        },
+    }
+}
+
+/// Whether `member` is within the lower (`lower` true) or upper `bound` of a
+/// lex range, given as `-`, `+`, `(x` or `[x`.
+fn within_lex(member: &str, bound: &str, lower: bool) -> Option<bool> {
+    match (bound, lower) {
+        ("-", true) | ("+", false) => Some(true),
+        ("+", true) | ("-", false) => Some(false),
+        _ => {
+            let (inclusive, value) = match bound.split_at(1) {
+                ("[", value) => (true, value),
+                ("(", value) => (false, value),
+                _ => return None,
+            };
+            Some(match (lower, inclusive) {
+                (true, true) => member >= value,
+                (true, false) => member > value,
+                (false, true) => member <= value,
+                (false, false) => member < value,
+            })
+        }
+    }
+}
+
+fn range_by_lex<'a>(
+    members: impl Iterator<Item = &'a String>,
+    min: &str,
+    max: &str,
+    limit: &[String],
+) -> Reply {
+    let (offset, count) = match limit {
+        [] => (0, usize::MAX),
+        [keyword, offset, count] if keyword.eq_ignore_ascii_case("LIMIT") => {
+            match (offset.parse::<usize>(), count.parse::<i64>()) {
+                (Ok(offset), Ok(count)) => (offset, usize::try_from(count).unwrap_or(usize::MAX)),
+                _ => {
+                    return Reply::Error("ERR value is not an integer or out of range".to_string())
+                }
+            }
+        }
+        _ => return Reply::Error("ERR syntax error".to_string()),
+    };
+
+    let mut selected: Vec<String> = Vec::new();
+    for member in members {
+        match (
+            within_lex(member, min, true),
+            within_lex(member, max, false),
+        ) {
+            (Some(above), Some(below)) => {
+                if above && below {
+                    selected.push(member.clone());
+                }
+            }
+            _ => return Reply::Error("ERR min or max not valid string range item".to_string()),
+        }
+    }
+    strings(selected.into_iter().skip(offset).take(count))
+}
+
+/// Redis glob patterns with `*`, `?` and `\` escapes.
+fn matches_glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_glob_at(&pattern, &text)
+}
+
+fn matches_glob_at(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|i| matches_glob_at(rest, &text[i..])),
+        Some(('?', rest)) => !text.is_empty() && matches_glob_at(rest, &text[1..]),
+        Some(('\\', [escaped, rest @ ..])) => {
+            text.first() == Some(escaped) && matches_glob_at(rest, &text[1..])
+        }
+        Some((c, rest)) => text.first() == Some(c) && matches_glob_at(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redis::Connection as RedisConnection;
+
+    #[test]
+    fn test_fake_redis_commands_and_faults() {
+        let fake_redis: FakeRedis = FakeRedis::start();
+        let client: redis::Client = redis::Client::open(fake_redis.url()).unwrap();
+        let mut connection: RedisConnection = client.get_connection().unwrap();
+
+        redis::pipe()
+            .atomic()
+            .cmd("ZADD")
+            .arg("index")
+            .arg(0)
+            .arg("b")
+            .arg(0)
+            .arg("a")
+            .arg(0)
+            .arg("c")
+            .ignore()
+            .cmd("HSET")
+            .arg("contacts:a")
+            .arg("email")
+            .arg("a@mail.com")
+            .ignore()
+            .query::<()>(&mut connection)
+            .unwrap();
+        let forward: Vec<String> = redis::cmd("ZRANGEBYLEX")
+            .arg("index")
+            .arg("(a")
+            .arg("+")
+            .arg("LIMIT")
+            .arg(0)
+            .arg(1)
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(vec!["b"], forward);
+        let backward: Vec<String> = redis::cmd("ZREVRANGEBYLEX")
+            .arg("index")
+            .arg("+")
+            .arg("-")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(vec!["c", "b", "a"], backward);
+        let keys: Vec<String> = redis::cmd("KEYS")
+            .arg("contacts:*")
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!(vec!["contacts:a"], keys);
+        assert!(redis::cmd("HGETALL")
+            .arg("index")
+            .query::<Vec<String>>(&mut connection)
+            .is_err());
+
+        // A failed command inside a transaction discards all of it.
+        fake_redis.fail_next("RENAME", "ERR injected");
+        let result: redis::RedisResult<()> = redis::pipe()
+            .atomic()
+            .cmd("DEL")
+            .arg("index")
+            .ignore()
+            .cmd("RENAME")
+            .arg("contacts:a")
+            .arg("trash:a")
+            .ignore()
+            .query(&mut connection);
+        assert!(result.is_err());
+        let exists: bool = redis::cmd("EXISTS")
+            .arg("index")
+            .query(&mut connection)
+            .unwrap();
+        assert!(exists);
+
+        fake_redis.disconnect_next("EXISTS");
+        assert!(redis::cmd("EXISTS")
+            .arg("index")
+            .query::<bool>(&mut connection)
+            .is_err());
+        let mut connection: RedisConnection = client.get_connection().unwrap();
+        let exists: bool = redis::cmd("EXISTS")
+            .arg("index")
+            .query(&mut connection)
+            .unwrap();
+        assert!(exists);
+
+        let moved: bool = redis::cmd("RENAMENX")
+            .arg("contacts:a")
+            .arg("index")
+            .query(&mut connection)
+            .unwrap();
+        assert!(!moved);
+        let (cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(0)
+            .arg("MATCH")
+            .arg("*")
+            .arg("COUNT")
+            .arg(1)
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!((1, vec!["contacts:a".to_string()]), (cursor, keys));
+        let (cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("COUNT")
+            .arg(1)
+            .query(&mut connection)
+            .unwrap();
+        assert_eq!((0, vec!["index".to_string()]), (cursor, keys));
+
+        // Every database has keys of its own.
+        let client: redis::Client = redis::Client::open(format!("{}1", fake_redis.url())).unwrap();
+        let mut connection: RedisConnection = client.get_connection().unwrap();
+        let exists: bool = redis::cmd("EXISTS")
+            .arg("index")
+            .query(&mut connection)
+            .unwrap();
+        assert!(!exists);
+    }
+}
@@ -0,0 +1,349 @@
+//! A backend keeping every book in one local file, encrypted with age.
+
+use crate::{
+    atomic_file::{write_atomically, WriteOptions},
+    crypto::{self, Decryption, Encryption},
+    models::{change::Change, contact::Contact, link::Link, page::Page, sort_by::SortBy},
+    repositories::{
+        contacts::ContactsRepository,
+        inmemory_contacts::InMemoryContactsRepository,
+        migrations::{MigrationStatus, MigrationStep},
+    },
+};
+use age::secrecy::{ExposeSecret, SecretString};
+use age::x25519;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const KEY_FILE_EXTENSION: &str = "key";
+
+/// A local store kept encrypted at rest. The contacts are encrypted to a random
+/// age identity, which is kept next to them encrypted with the passphrase, so
+/// saving after every change does not pay for deriving a key from the passphrase.
+pub struct FileContactsRepository {
+    path: PathBuf,
+    recipient: x25519::Recipient,
+    inner: InMemoryContactsRepository,
+}
+
+fn get_key_path(path: &Path) -> PathBuf {
+    let mut key_path: std::ffi::OsString = path.as_os_str().to_owned();
+    key_path.push(format!(".{KEY_FILE_EXTENSION}"));
+    PathBuf::from(key_path)
+}
+
+impl FileContactsRepository {
+    /// Whether a store was already created at `path`.
+    pub fn exists(path: &Path) -> bool {
+        get_key_path(path).exists()
+    }
+
+    /// Opens the store at `path`, creating it when missing. Fails when the
+    /// passphrase does not match the one the store was created with, or when
+    /// the contacts are there without the key they are encrypted to.
+    pub fn open(path: PathBuf, passphrase: SecretString) -> Result<Self, String> {
+        let key_path: PathBuf = get_key_path(&path);
+
+        if !key_path.exists() {
+            if path.exists() {
+                return Err(format!(
+                    "{}: the key file {} is missing, restore it to open the store",
+                    path.display(),
+                    key_path.display()
+                ));
+            }
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir).map_err(|err| format!("{}: {err}", dir.display()))?;
+            }
+            let (secret_key, recipient) = crypto::generate_identity();
+            let key: Vec<u8> = crypto::encrypt(
+                secret_key.expose_secret().as_bytes(),
+                &Encryption::Passphrase(passphrase),
+            )?;
+            write_atomically(&key_path, &key, WriteOptions::default())?;
+
+            let repository: FileContactsRepository = FileContactsRepository {
+                path,
+                recipient,
+                inner: InMemoryContactsRepository::new(),
+            };
+            repository.save()?;
+            return Ok(repository);
+        }
+
+        let key: Vec<u8> =
+            fs::read(&key_path).map_err(|err| format!("{}: {err}", key_path.display()))?;
+        let secret_key: Vec<u8> = crypto::decrypt(&key, &Decryption::Passphrase(passphrase))
+            .map_err(|_| "Wrong passphrase".to_string())?;
+        let secret_key: SecretString =
+            SecretString::from(String::from_utf8(secret_key).map_err(|err| err.to_string())?);
+        let identity: x25519::Identity = crypto::parse_identity(&secret_key)?;
+
+        let inner: InMemoryContactsRepository = match path.exists() {
+            true => {
+                let bytes: Vec<u8> =
+                    fs::read(&path).map_err(|err| format!("{}: {err}", path.display()))?;
+                let json: Vec<u8> = crypto::decrypt(
+                    &bytes,
+                    &Decryption::Identities(vec![Box::new(identity.clone())]),
+                )?;
+                serde_json::from_slice(&json).map_err(|err| err.to_string())?
+            }
+            false => InMemoryContactsRepository::new(),
+        };
+
+        Ok(FileContactsRepository {
+            path,
+            recipient: identity.to_public(),
+            inner,
+        })
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let json: Vec<u8> = serde_json::to_vec(&self.inner).map_err(|err| err.to_string())?;
+        let bytes: Vec<u8> =
+            crypto::encrypt(&json, &Encryption::Recipients(vec![self.recipient.clone()]))?;
+        write_atomically(&self.path, &bytes, WriteOptions::overwrite())
+    }
+}
+
+impl ContactsRepository for FileContactsRepository {
+    fn add(
+        &mut self,
+        name: String,
+        phone_no_as_string: String,
+        email: String,
+    ) -> Result<(), String> {
+        self.inner.add(name, phone_no_as_string, email)?;
+        self.save()
+    }
+
+    fn update_email(&mut self, name: &str, new_email: String) -> Result<(), String> {
+        self.inner.update_email(name, new_email)?;
+        self.save()
+    }
+
+    fn update_phone_no(
+        &mut self,
+        name: &str,
+        new_phone_no_as_string: String,
+    ) -> Result<(), String> {
+        self.inner.update_phone_no(name, new_phone_no_as_string)?;
+        self.save()
+    }
+
+    fn update_links(&mut self, name: &str, links: Vec<Link>) -> Result<(), String> {
+        self.inner.update_links(name, links)?;
+        self.save()
+    }
+
+    fn delete(&mut self, name: &str) -> Result<(), String> {
+        self.inner.delete(name)?;
+        self.save()
+    }
+
+    fn restore(&mut self, name: &str) -> Result<(), String> {
+        self.inner.restore(name)?;
+        self.save()
+    }
+
+    fn get(&self, name: &str) -> Result<Option<Contact>, String> {
+        self.inner.get(name)
+    }
+
+    fn list(
+        &self,
+        page_no: usize,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Vec<Contact>, String> {
+        self.inner.list(page_no, page_size, sort_by, descending)
+    }
+
+    fn list_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Page, String> {
+        self.inner.list_page(cursor, page_size, sort_by, descending)
+    }
+
+    fn list_trash(&self) -> Result<Vec<Contact>, String> {
+        self.inner.list_trash()
+    }
+
+    fn purge_trash(&mut self, deleted_before: Option<i64>) -> Result<usize, String> {
+        let no_of_contacts: usize = self.inner.purge_trash(deleted_before)?;
+        self.save()?;
+        Ok(no_of_contacts)
+    }
+
+    fn import_contacts(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        self.inner.import_contacts(contacts)?;
+        self.save()
+    }
+
+    fn replace_all(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        self.inner.replace_all(contacts)?;
+        self.save()
+    }
+
+    fn replace_books(&mut self, books: BTreeMap<String, Vec<Contact>>) -> Result<(), String> {
+        self.inner.replace_books(books)?;
+        self.save()
+    }
+
+    fn count(&self) -> Result<usize, String> {
+        self.inner.count()
+    }
+
+    fn history(&self, name: &str) -> Result<Vec<Change>, String> {
+        self.inner.history(name)
+    }
+
+    fn audit(&self, since: Option<i64>) -> Result<Vec<Change>, String> {
+        self.inner.audit(since)
+    }
+
+    fn current_book(&self) -> String {
+        self.inner.current_book()
+    }
+
+    fn use_book(&mut self, book: &str) -> Result<(), String> {
+        self.inner.use_book(book)
+    }
+
+    fn create_book(&mut self, book: &str) -> Result<(), String> {
+        self.inner.create_book(book)?;
+        self.save()
+    }
+
+    fn delete_book(&mut self, book: &str) -> Result<(), String> {
+        self.inner.delete_book(book)?;
+        self.save()
+    }
+
+    fn list_books(&self) -> Result<Vec<String>, String> {
+        self.inner.list_books()
+    }
+
+    fn migration_status(&self) -> Result<MigrationStatus, String> {
+        self.inner.migration_status()
+    }
+
+    fn migrate_up(&mut self) -> Result<Vec<MigrationStep>, String> {
+        let steps: Vec<MigrationStep> = self.inner.migrate_up()?;
+        self.save()?;
+        Ok(steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::conformance::check_conformance;
+    use crate::repositories::model_based::check_against_model_with;
+    use std::env;
+
+    #[test]
+    fn test_file_contacts_service_encrypted_at_rest() {
+        let path: PathBuf =
+            env::temp_dir().join(format!("contacts-cli-store-{}.age", std::process::id()));
+        let passphrase: SecretString = SecretString::from("correct horse battery staple");
+        assert!(!FileContactsRepository::exists(&path));
+
+        let mut contacts_service: FileContactsRepository =
+            FileContactsRepository::open(path.clone(), passphrase.clone()).unwrap();
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491234567890".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+        assert!(FileContactsRepository::exists(&path));
+
+        let bytes: Vec<u8> = fs::read(&path).unwrap();
+        assert!(crypto::is_encrypted(&bytes));
+        assert!(!String::from_utf8_lossy(&bytes).contains("bogdan@mail.com"));
+
+        assert!(FileContactsRepository::open(path.clone(), SecretString::from("wrong")).is_err());
+        let reopened: FileContactsRepository =
+            FileContactsRepository::open(path.clone(), passphrase).unwrap();
+        assert_eq!(
+            "bogdan@mail.com",
+            reopened.get("Bogdan").unwrap().unwrap().email
+        );
+        assert_eq!(1, reopened.history("Bogdan").unwrap().len());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(get_key_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_file_contacts_service_missing_key() {
+        let path: PathBuf =
+            env::temp_dir().join(format!("contacts-cli-no-key-{}.age", std::process::id()));
+        let key_path: PathBuf = get_key_path(&path);
+        let passphrase: SecretString = SecretString::from("correct horse battery staple");
+        let mut contacts_service: FileContactsRepository =
+            FileContactsRepository::open(path.clone(), passphrase.clone()).unwrap();
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491234567890".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+        let bytes: Vec<u8> = fs::read(&path).unwrap();
+        let key: Vec<u8> = fs::read(&key_path).unwrap();
+
+        fs::remove_file(&key_path).unwrap();
+        assert!(
+            FileContactsRepository::open(path.clone(), passphrase.clone())
+                .err()
+                .unwrap()
+                .contains("missing")
+        );
+        assert_eq!(bytes, fs::read(&path).unwrap());
+        assert!(!key_path.exists());
+
+        fs::write(&key_path, key).unwrap();
+        let reopened: FileContactsRepository =
+            FileContactsRepository::open(path.clone(), passphrase).unwrap();
+        assert!(reopened.get("Bogdan").unwrap().is_some());
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&key_path).unwrap();
+    }
+
+    #[test]
+    fn test_file_contacts_service_conformance() {
+        let path: PathBuf = env::temp_dir().join(format!(
+            "contacts-cli-conformance-{}.age",
+            std::process::id()
+        ));
+        let mut contacts_service: FileContactsRepository =
+            FileContactsRepository::open(path.clone(), SecretString::from("passphrase")).unwrap();
+        check_conformance(&mut contacts_service);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(get_key_path(&path)).unwrap();
+    }
+
+    #[test]
+    fn test_file_contacts_service_against_model() {
+        let path: PathBuf =
+            env::temp_dir().join(format!("contacts-cli-model-{}.age", std::process::id()));
+        let mut contacts_service: FileContactsRepository =
+            FileContactsRepository::open(path.clone(), SecretString::from("passphrase")).unwrap();
+        check_against_model_with(&mut contacts_service, 32);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(get_key_path(&path)).unwrap();
+    }
+}
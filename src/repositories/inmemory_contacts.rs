@@ -1,15 +1,62 @@
-use std::{
-    collections::BTreeMap,
-    fs::File,
-    io::{BufReader, Write},
+//! A backend that lives only as long as the process, for tests and scratch
+//! work.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{
+    models::{
+        change::{diff_fields, Change, Operation},
+        contact::Contact,
+        link::Link,
+        page::Page,
+        sort_by::SortBy,
+    },
+    repositories::contacts::ContactsRepository,
+    repositories::migrations::{
+        get_latest_version, get_status, migrate, Migration, MigrationStatus, MigrationStep,
+    },
 };
 
-use crate::{models::contact::Contact, repositories::contacts::ContactsRepository};
+use super::contacts::{
+    get_current_operator, get_current_timestamp, get_valid_book_name, get_valid_email,
+    get_valid_name, get_valid_phone_no, paginate, remove_links_to, sort_contacts, DEFAULT_BOOK,
+};
 
-use super::contacts::{get_valid_name, get_valid_email, get_valid_phone_no};
+/// The contents of an address book other than the active one.
+#[derive(Serialize, Deserialize, Default)]
+struct Book {
+    contacts: BTreeMap<String, Contact>,
+    trash: BTreeMap<String, Contact>,
+    changes: Vec<Change>,
+}
 
+/// Keeps the active address book in its own fields and the others aside, so
+/// switching books swaps their contents in.
+#[derive(Serialize, Deserialize)]
 pub struct InMemoryContactsRepository {
     contacts: BTreeMap<String, Contact>,
+    trash: BTreeMap<String, Contact>,
+    changes: Vec<Change>,
+    #[serde(default = "get_default_book")]
+    book: String,
+    #[serde(default)]
+    other_books: BTreeMap<String, Book>,
+    /// The version of `IN_MEMORY_MIGRATIONS` the serialized store is at, 0 for
+    /// stores saved before it was recorded.
+    #[serde(default)]
+    schema_version: u32,
+}
+
+/// The layouts of the serialized store, which the file backend keeps.
+pub(crate) const IN_MEMORY_MIGRATIONS: &[Migration<InMemoryContactsRepository>] = &[Migration {
+    version: 1,
+    description: "Record the schema version of the store",
+    apply: |_| Ok(()),
+}];
+
+fn get_default_book() -> String {
+    DEFAULT_BOOK.to_string()
 }
 
 impl Default for InMemoryContactsRepository {
@@ -19,11 +66,34 @@ impl Default for InMemoryContactsRepository {
 }
 
 impl InMemoryContactsRepository {
+    /// An empty store on the default book.
     pub fn new() -> Self {
         InMemoryContactsRepository {
             contacts: BTreeMap::new(),
+            trash: BTreeMap::new(),
+            changes: Vec::new(),
+            book: get_default_book(),
+            other_books: BTreeMap::new(),
+            schema_version: get_latest_version(IN_MEMORY_MIGRATIONS),
         }
     }
+
+    fn log_change(
+        &mut self,
+        operation: Operation,
+        name: &str,
+        old: Option<&Contact>,
+        new: Option<&Contact>,
+    ) {
+        self.changes.push(Change::new(
+            get_current_timestamp(),
+            get_current_operator(),
+            operation,
+            name,
+            old,
+            new,
+        ));
+    }
 }
 
 impl ContactsRepository for InMemoryContactsRepository {
@@ -37,14 +107,22 @@ impl ContactsRepository for InMemoryContactsRepository {
         let email:String = get_valid_email(&email)?;
         let phone_no: u64 = get_valid_phone_no(&phone_no_as_string)?;
 
-        self.contacts.insert(
-            name.clone(),
-            Contact {
-                name,
-                phone_no,
-                email,
-            },
-        );
+        let now: i64 = get_current_timestamp();
+        let (created_at, links) = match self.contacts.get(&name) {
+            Some(x) => (x.created_at, x.links.clone()),
+            None => (now, Vec::new()),
+        };
+        let contact: Contact = Contact {
+            name: name.clone(),
+            phone_no,
+            email,
+            created_at,
+            updated_at: now,
+            deleted_at: None,
+            links,
+        };
+        let old: Option<Contact> = self.contacts.insert(name.clone(), contact.clone());
+        self.log_change(Operation::Add, &name, old.as_ref(), Some(&contact));
         Ok(())
     }
 
@@ -56,7 +134,11 @@ impl ContactsRepository for InMemoryContactsRepository {
             None => return Ok(()),
         };
 
+        let old: Contact = contact.clone();
         contact.email = new_email;
+        contact.updated_at = get_current_timestamp();
+        let new: Contact = contact.clone();
+        self.log_change(Operation::UpdateEmail, name, Some(&old), Some(&new));
         Ok(())
     }
 
@@ -72,12 +154,61 @@ impl ContactsRepository for InMemoryContactsRepository {
             None => return Ok(()),
         };
 
+        let old: Contact = contact.clone();
         contact.phone_no = new_phone_no;
+        contact.updated_at = get_current_timestamp();
+        let new: Contact = contact.clone();
+        self.log_change(Operation::UpdatePhoneNo, name, Some(&old), Some(&new));
+        Ok(())
+    }
+
+    fn update_links(&mut self, name: &str, links: Vec<Link>) -> Result<(), String> {
+        let contact: &mut Contact = self
+            .contacts
+            .get_mut(name)
+            .ok_or(format!("No contact with name {name}"))?;
+
+        let old: Contact = contact.clone();
+        contact.links = links;
+        contact.updated_at = get_current_timestamp();
+        let new: Contact = contact.clone();
+        self.log_change(Operation::UpdateLinks, name, Some(&old), Some(&new));
         Ok(())
     }
 
     fn delete(&mut self, name: &str) -> Result<(), String> {
-        self.contacts.remove(name);
+        let mut contact: Contact = match self.contacts.remove(name) {
+            Some(x) => x,
+            None => return Err(format!("No contact with name {name}")),
+        };
+
+        let old: Contact = contact.clone();
+        contact.deleted_at = Some(get_current_timestamp());
+        contact.links.clear();
+        self.log_change(Operation::Delete, name, Some(&old), Some(&contact));
+        self.trash.insert(contact.name.clone(), contact);
+
+        for (old, new) in remove_links_to(self.contacts.values().cloned().collect(), name) {
+            self.log_change(Operation::UpdateLinks, &new.name, Some(&old), Some(&new));
+            self.contacts.insert(new.name.clone(), new);
+        }
+        Ok(())
+    }
+
+    fn restore(&mut self, name: &str) -> Result<(), String> {
+        if self.contacts.contains_key(name) {
+            return Err(format!("A contact with name {name} already exists"));
+        }
+
+        let mut contact: Contact = match self.trash.remove(name) {
+            Some(x) => x,
+            None => return Err(format!("No contact with name {name} in the trash")),
+        };
+
+        let old: Contact = contact.clone();
+        contact.deleted_at = None;
+        self.log_change(Operation::Restore, name, Some(&old), Some(&contact));
+        self.contacts.insert(contact.name.clone(), contact);
         Ok(())
     }
 
@@ -89,48 +220,225 @@ impl ContactsRepository for InMemoryContactsRepository {
         Ok(Some(contact))
     }
 
-    fn list(&self, page_no: usize, page_size: usize) -> Result<Vec<Contact>, String> {
-        let contacts: Vec<Contact> = self.contacts
-            .values()
+    fn list(
+        &self,
+        page_no: usize,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Vec<Contact>, String> {
+        let mut contacts: Vec<Contact> = self.contacts.values().cloned().collect();
+        sort_contacts(&mut contacts, sort_by, descending);
+
+        Ok(contacts
+            .into_iter()
             .skip(page_no * page_size)
             .take(page_size)
-            .map(|c| (*c).clone())
+            .collect())
+    }
+
+    fn list_page(
+        &self,
+        cursor: Option<&str>,
+        page_size: usize,
+        sort_by: SortBy,
+        descending: bool,
+    ) -> Result<Page, String> {
+        let contacts: Vec<Contact> = self.contacts.values().cloned().collect();
+        paginate(contacts, cursor, page_size, sort_by, descending)
+    }
+
+    fn list_names(&self) -> Result<Vec<String>, String> {
+        Ok(self.contacts.keys().cloned().collect())
+    }
+
+    fn list_trash(&self) -> Result<Vec<Contact>, String> {
+        Ok(self.trash.values().cloned().collect())
+    }
+
+    fn purge_trash(&mut self, deleted_before: Option<i64>) -> Result<usize, String> {
+        let purged: Vec<Contact> = self
+            .trash
+            .values()
+            .filter(|contact| match deleted_before {
+                Some(timestamp) => contact.deleted_at.unwrap_or(0) < timestamp,
+                None => true,
+            })
+            .cloned()
             .collect();
 
-        Ok(contacts)
+        for contact in &purged {
+            self.trash.remove(&contact.name);
+            self.log_change(Operation::Purge, &contact.name, Some(contact), None);
+        }
+        Ok(purged.len())
+    }
+
+    fn replace_all(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        let mut old: BTreeMap<String, Contact> = std::mem::take(&mut self.contacts);
+        old.append(&mut self.trash);
+
+        let mut new: BTreeMap<String, Contact> = BTreeMap::new();
+        for contact in contacts {
+            match contact.deleted_at {
+                Some(_) => self.trash.insert(contact.name.clone(), contact.clone()),
+                None => self.contacts.insert(contact.name.clone(), contact.clone()),
+            };
+            new.insert(contact.name.clone(), contact);
+        }
+
+        let names: BTreeSet<String> = old.keys().chain(new.keys()).cloned().collect();
+        for name in names {
+            if !diff_fields(old.get(&name), new.get(&name)).is_empty() {
+                self.log_change(
+                    Operation::RestoreBackup,
+                    &name,
+                    old.get(&name),
+                    new.get(&name),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn replace_books(&mut self, books: BTreeMap<String, Vec<Contact>>) -> Result<(), String> {
+        // Only book names can be rejected, so check them all before changing any
+        for book in books.keys() {
+            get_valid_book_name(book)?;
+        }
+        let active_book: String = self.book.clone();
+        for (book, contacts) in books {
+            if book != self.book && !self.other_books.contains_key(&book) {
+                self.other_books.insert(book.clone(), Book::default());
+            }
+            self.use_book(&book)?;
+            self.replace_all(contacts)?;
+        }
+        self.use_book(&active_book)
     }
 
     fn count(&self) -> Result<usize, String> {
-        return Ok(self.contacts.values().count());
+        Ok(self.contacts.values().count())
     }
 
-    fn export_to_json(&self, path: String) -> Result<(), String> {
-        let list: Vec<&Contact> = self.contacts.values().collect();
-        let json_str: String = serde_json::to_string(&list)
-            .map_err(|err| err.to_string())?;
-        let mut file: File = File::create(path)
-            .map_err(|err| err.to_string())?;
-        file.write_all(json_str.as_bytes())
-            .map_err(|err| err.to_string())?;
+    fn import_contacts(&mut self, contacts: Vec<Contact>) -> Result<(), String> {
+        let now: i64 = get_current_timestamp();
+        for mut contact in contacts {
+            if contact.created_at == 0 {
+                contact.created_at = now;
+            }
+            if contact.updated_at == 0 {
+                contact.updated_at = contact.created_at;
+            }
+            let old: Option<Contact> = if contact.deleted_at.is_some() {
+                self.trash.insert(contact.name.clone(), contact.clone())
+            } else {
+                self.contacts.insert(contact.name.clone(), contact.clone())
+            };
+            self.log_change(
+                Operation::Import,
+                &contact.name,
+                old.as_ref(),
+                Some(&contact),
+            );
+        }
         Ok(())
     }
 
-    fn import_from_json(&mut self, path: String) -> Result<(), String> {
-        let inner: File = File::open(path)
-            .map_err(|err| err.to_string())?;
-        let rdr: BufReader<File> = BufReader::new(inner);
-        let contacts: Vec<Contact> = serde_json::from_reader(rdr)
-            .map_err(|err| err.to_string())?;
-        for contact in contacts {
-            self.contacts.insert(contact.name.clone(), contact);
+    fn history(&self, name: &str) -> Result<Vec<Change>, String> {
+        Ok(self
+            .changes
+            .iter()
+            .filter(|change| change.name == name)
+            .cloned()
+            .collect())
+    }
+
+    fn audit(&self, since: Option<i64>) -> Result<Vec<Change>, String> {
+        Ok(self
+            .changes
+            .iter()
+            .filter(|change| change.timestamp >= since.unwrap_or(i64::MIN))
+            .cloned()
+            .collect())
+    }
+
+    fn current_book(&self) -> String {
+        self.book.clone()
+    }
+
+    fn use_book(&mut self, book: &str) -> Result<(), String> {
+        if book == self.book {
+            return Ok(());
+        }
+        let next: Book = self
+            .other_books
+            .remove(book)
+            .ok_or(format!("No book with name {book}"))?;
+        let current: Book = Book {
+            contacts: std::mem::replace(&mut self.contacts, next.contacts),
+            trash: std::mem::replace(&mut self.trash, next.trash),
+            changes: std::mem::replace(&mut self.changes, next.changes),
+        };
+        self.other_books.insert(self.book.clone(), current);
+        self.book = book.to_string();
+        Ok(())
+    }
+
+    fn create_book(&mut self, book: &str) -> Result<(), String> {
+        let book: String = get_valid_book_name(book)?;
+        if book == self.book || self.other_books.contains_key(&book) {
+            return Err(format!("Book {book} already exists"));
         }
+        self.other_books.insert(book, Book::default());
         Ok(())
     }
+
+    fn delete_book(&mut self, book: &str) -> Result<(), String> {
+        if book == DEFAULT_BOOK {
+            return Err("The default book cannot be deleted".to_string());
+        }
+        if book == self.book {
+            return Err(format!(
+                "Book {book} is in use, switch to another one first"
+            ));
+        }
+        self.other_books
+            .remove(book)
+            .map(|_| ())
+            .ok_or(format!("No book with name {book}"))
+    }
+
+    fn list_books(&self) -> Result<Vec<String>, String> {
+        let mut books: BTreeSet<String> = self.other_books.keys().cloned().collect();
+        books.insert(self.book.clone());
+        books.insert(DEFAULT_BOOK.to_string());
+        Ok(books.into_iter().collect())
+    }
+
+    fn migration_status(&self) -> Result<MigrationStatus, String> {
+        get_status(IN_MEMORY_MIGRATIONS, self.schema_version)
+    }
+
+    fn migrate_up(&mut self) -> Result<Vec<MigrationStep>, String> {
+        let version: u32 = self.schema_version;
+        migrate(IN_MEMORY_MIGRATIONS, self, version, |store, version| {
+            store.schema_version = version;
+            Ok(())
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "json")]
+    use crate::atomic_file::WriteOptions;
+    #[cfg(feature = "json")]
+    use crate::crypto::Decryption;
+    use crate::models::change::FieldChange;
+    use crate::models::link::LinkKind;
+    use crate::repositories::conformance::check_conformance;
 
     #[test]
     fn test_in_memory_contacts_service_add_get() {
@@ -230,6 +538,188 @@ mod tests {
         assert!(res_get.unwrap().is_none());
     }
 
+    #[test]
+    fn test_in_memory_contacts_service_trash() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491234567890".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+        contacts_service.delete("Bogdan").unwrap();
+
+        assert_eq!(0, contacts_service.count().unwrap());
+        assert!(contacts_service
+            .list(0, 10, SortBy::Name, false)
+            .unwrap()
+            .is_empty());
+        let trash: Vec<Contact> = contacts_service.list_trash().unwrap();
+        assert_eq!(1, trash.len());
+        assert!(trash.first().unwrap().deleted_at.is_some());
+
+        contacts_service.restore("Bogdan").unwrap();
+        let actual_contact: Contact = contacts_service.get("Bogdan").unwrap().unwrap();
+        assert!(actual_contact.deleted_at.is_none());
+        assert!(contacts_service.list_trash().unwrap().is_empty());
+        assert!(contacts_service.restore("Bogdan").is_err());
+
+        contacts_service.delete("Bogdan").unwrap();
+        assert_eq!(0, contacts_service.purge_trash(Some(0)).unwrap());
+        assert_eq!(1, contacts_service.purge_trash(None).unwrap());
+        assert!(contacts_service.list_trash().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_in_memory_contacts_service_history_audit() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491234567890".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+        contacts_service
+            .add(
+                "Other".to_string(),
+                "491234567890".to_string(),
+                "other@mail.com".to_string(),
+            )
+            .unwrap();
+        contacts_service
+            .update_phone_no("Bogdan", "490123456789".to_string())
+            .unwrap();
+        contacts_service.delete("Bogdan").unwrap();
+
+        let history: Vec<Change> = contacts_service.history("Bogdan").unwrap();
+        let operations: Vec<Operation> = history.iter().map(|c| c.operation).collect();
+        assert_eq!(
+            vec![Operation::Add, Operation::UpdatePhoneNo, Operation::Delete],
+            operations
+        );
+
+        let update: &Change = history.get(1).unwrap();
+        assert_eq!(1, update.fields.len());
+        let field: &FieldChange = update.fields.first().unwrap();
+        assert_eq!("phone_no", field.field);
+        assert_eq!(Some("491234567890".to_string()), field.old_value);
+        assert_eq!(Some("490123456789".to_string()), field.new_value);
+
+        assert_eq!(4, contacts_service.audit(None).unwrap().len());
+        assert!(contacts_service.audit(Some(i64::MAX)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_in_memory_contacts_service_timestamps_sort() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+
+        for (name, email) in [
+            ("Aaa", "ccc@mail.com"),
+            ("Bbb", "aaa@mail.com"),
+            ("Ccc", "bbb@mail.com"),
+        ] {
+            contacts_service
+                .add(
+                    name.to_string(),
+                    "491234567890".to_string(),
+                    email.to_string(),
+                )
+                .unwrap();
+        }
+
+        let created: Contact = contacts_service.get("Aaa").unwrap().unwrap();
+        assert!(created.created_at > 0);
+        assert_eq!(created.created_at, created.updated_at);
+
+        contacts_service
+            .update_email("Aaa", "ddd@mail.com".to_string())
+            .unwrap();
+        let updated: Contact = contacts_service.get("Aaa").unwrap().unwrap();
+        assert_eq!(created.created_at, updated.created_at);
+        assert!(updated.updated_at >= created.updated_at);
+
+        let by_email: Vec<String> = contacts_service
+            .list(0, 10, SortBy::Email, false)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(vec!["Bbb", "Ccc", "Aaa"], by_email);
+
+        let by_name_desc: Vec<String> = contacts_service
+            .list(0, 2, SortBy::Name, true)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(vec!["Ccc", "Bbb"], by_name_desc);
+
+        assert_eq!(2, contacts_service.recent(2).unwrap().len());
+    }
+
+    #[test]
+    fn test_in_memory_contacts_service_list_page() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        for name in ["Aaa", "Bbb", "Ccc", "Ddd", "Eee"] {
+            contacts_service
+                .add(
+                    name.to_string(),
+                    "491234567890".to_string(),
+                    "mail@mail.com".to_string(),
+                )
+                .unwrap();
+        }
+        let names =
+            |page: &Page| -> Vec<String> { page.contacts.iter().map(|c| c.name.clone()).collect() };
+
+        let page0: Page = contacts_service
+            .list_page(None, 2, SortBy::Name, false)
+            .unwrap();
+        assert_eq!(vec!["Aaa", "Bbb"], names(&page0));
+        assert!(page0.prev_cursor.is_none());
+
+        contacts_service
+            .add(
+                "Abc".to_string(),
+                "491234567890".to_string(),
+                "mail@mail.com".to_string(),
+            )
+            .unwrap();
+
+        let page1: Page = contacts_service
+            .list_page(page0.next_cursor.as_deref(), 2, SortBy::Email, true)
+            .unwrap();
+        assert_eq!(vec!["Ccc", "Ddd"], names(&page1));
+
+        let page2: Page = contacts_service
+            .list_page(page1.next_cursor.as_deref(), 2, SortBy::Name, false)
+            .unwrap();
+        assert_eq!(vec!["Eee"], names(&page2));
+        assert!(page2.next_cursor.is_none());
+
+        let back: Page = contacts_service
+            .list_page(page1.prev_cursor.as_deref(), 2, SortBy::Name, false)
+            .unwrap();
+        assert_eq!(vec!["Abc", "Bbb"], names(&back));
+
+        let desc: Page = contacts_service
+            .list_page(None, 2, SortBy::Name, true)
+            .unwrap();
+        assert_eq!(vec!["Eee", "Ddd"], names(&desc));
+        let desc_next: Page = contacts_service
+            .list_page(desc.next_cursor.as_deref(), 2, SortBy::Name, false)
+            .unwrap();
+        assert_eq!(vec!["Ccc", "Bbb"], names(&desc_next));
+
+        assert!(contacts_service
+            .list_page(Some("not a cursor"), 2, SortBy::Name, false)
+            .is_err());
+    }
+
     #[test]
     fn test_in_memory_contacts_service_list_count() {
         let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
@@ -311,28 +801,202 @@ mod tests {
             )
             .unwrap();
 
-        let page0: Vec<Contact> = contacts_service.list(0, 3).unwrap();
+        let page0: Vec<Contact> = contacts_service.list(0, 3, SortBy::Name, false).unwrap();
         assert_eq!(3, page0.len());
-        assert_eq!("Aaa", page0.get(0).unwrap().name);
+        assert_eq!("Aaa", page0.first().unwrap().name);
         assert_eq!("Aaa2", page0.get(1).unwrap().name);
         assert_eq!("Aaa3", page0.get(2).unwrap().name);
 
-        let page1: Vec<Contact> = contacts_service.list(1, 3).unwrap();
+        let page1: Vec<Contact> = contacts_service.list(1, 3, SortBy::Name, false).unwrap();
         assert_eq!(3, page1.len());
-        assert_eq!("Bbb", page1.get(0).unwrap().name);
+        assert_eq!("Bbb", page1.first().unwrap().name);
         assert_eq!("Ccc", page1.get(1).unwrap().name);
         assert_eq!("Ddd", page1.get(2).unwrap().name);
 
-        let page2: Vec<Contact> = contacts_service.list(2, 3).unwrap();
+        let page2: Vec<Contact> = contacts_service.list(2, 3, SortBy::Name, false).unwrap();
         assert_eq!(3, page2.len());
-        assert_eq!("Eee", page2.get(0).unwrap().name);
+        assert_eq!("Eee", page2.first().unwrap().name);
         assert_eq!("Lll", page2.get(1).unwrap().name);
         assert_eq!("Mmm", page2.get(2).unwrap().name);
 
-        let page3: Vec<Contact> = contacts_service.list(3, 3).unwrap();
+        let page3: Vec<Contact> = contacts_service.list(3, 3, SortBy::Name, false).unwrap();
         assert_eq!(1, page3.len());
-        assert_eq!("Sss", page3.get(0).unwrap().name);
+        assert_eq!("Sss", page3.first().unwrap().name);
 
         assert_eq!(10, contacts_service.count().unwrap());
     }
+
+    #[test]
+    fn test_in_memory_contacts_service_books() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        contacts_service
+            .add(
+                "Bogdan".to_string(),
+                "491234567890".to_string(),
+                "bogdan@mail.com".to_string(),
+            )
+            .unwrap();
+        assert_eq!(DEFAULT_BOOK, contacts_service.current_book());
+
+        assert!(contacts_service.create_book("Work Book").is_err());
+        contacts_service.create_book("work").unwrap();
+        assert!(contacts_service.create_book("work").is_err());
+        assert!(contacts_service.use_book("missing").is_err());
+        assert_eq!(
+            vec!["default".to_string(), "work".to_string()],
+            contacts_service.list_books().unwrap()
+        );
+
+        contacts_service.copy_to_book("Bogdan", "work").unwrap();
+        assert!(contacts_service.copy_to_book("Bogdan", "work").is_err());
+        assert_eq!(DEFAULT_BOOK, contacts_service.current_book());
+        assert_eq!(1, contacts_service.count().unwrap());
+
+        contacts_service
+            .add(
+                "Mihai".to_string(),
+                "491111111111".to_string(),
+                "mihai@mail.com".to_string(),
+            )
+            .unwrap();
+        contacts_service.move_to_book("Mihai", "work").unwrap();
+        assert!(contacts_service.get("Mihai").unwrap().is_none());
+
+        contacts_service.use_book("work").unwrap();
+        assert_eq!(2, contacts_service.count().unwrap());
+        assert_eq!(
+            "bogdan@mail.com",
+            contacts_service.get("Bogdan").unwrap().unwrap().email
+        );
+        assert!(contacts_service.delete_book("work").is_err());
+        assert!(contacts_service.delete_book(DEFAULT_BOOK).is_err());
+
+        contacts_service.use_book(DEFAULT_BOOK).unwrap();
+        assert_eq!(1, contacts_service.count().unwrap());
+        assert_eq!(1, contacts_service.list_trash().unwrap().len());
+        contacts_service.delete_book("work").unwrap();
+        assert_eq!(
+            vec!["default".to_string()],
+            contacts_service.list_books().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_in_memory_contacts_service_links() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        for (name, email) in [
+            ("Bogdan", "bogdan@mail.com"),
+            ("Mihai", "mihai@mail.com"),
+            ("Ana", "ana@mail.com"),
+        ] {
+            contacts_service
+                .add(
+                    name.to_string(),
+                    "491234567890".to_string(),
+                    email.to_string(),
+                )
+                .unwrap();
+        }
+
+        assert!(contacts_service
+            .link("Bogdan", "Bogdan", LinkKind::Spouse, true)
+            .is_err());
+        assert!(contacts_service
+            .link("Bogdan", "Nobody", LinkKind::Spouse, true)
+            .is_err());
+        contacts_service
+            .link("Bogdan", "Mihai", LinkKind::Manager, true)
+            .unwrap();
+        contacts_service
+            .link("Bogdan", "Ana", LinkKind::Colleague, false)
+            .unwrap();
+        contacts_service
+            .link("Bogdan", "Ana", LinkKind::Spouse, false)
+            .unwrap();
+
+        let related: Vec<(LinkKind, String)> = contacts_service
+            .related("Bogdan")
+            .unwrap()
+            .into_iter()
+            .map(|(kind, contact)| (kind, contact.name))
+            .collect();
+        assert_eq!(
+            vec![
+                (LinkKind::Manager, "Mihai".to_string()),
+                (LinkKind::Spouse, "Ana".to_string())
+            ],
+            related
+        );
+        assert_eq!(
+            LinkKind::Report,
+            contacts_service.related("Mihai").unwrap()[0].0
+        );
+        assert!(contacts_service.related("Ana").unwrap().is_empty());
+        assert_eq!(
+            Operation::UpdateLinks,
+            contacts_service.history("Mihai").unwrap()[1].operation
+        );
+
+        #[cfg(feature = "json")]
+        {
+            let path: String = std::env::temp_dir()
+                .join(format!("contacts-cli-links-{}.json", std::process::id()))
+                .to_string_lossy()
+                .to_string();
+            contacts_service
+                .export_to_json(path.clone(), false, None, WriteOptions::overwrite())
+                .unwrap();
+            let mut imported: InMemoryContactsRepository = InMemoryContactsRepository::new();
+            let mut no_decryption = || -> Result<Decryption, String> { Err("No key".to_string()) };
+            imported
+                .import_from_json(path.clone(), &mut no_decryption)
+                .unwrap();
+            assert_eq!(2, imported.related("Bogdan").unwrap().len());
+            std::fs::remove_file(&path).unwrap();
+        }
+
+        contacts_service.unlink("Ana", "Bogdan").unwrap();
+        assert!(contacts_service.unlink("Ana", "Bogdan").is_err());
+        assert_eq!(1, contacts_service.related("Bogdan").unwrap().len());
+
+        contacts_service.delete("Mihai").unwrap();
+        assert!(contacts_service.related("Bogdan").unwrap().is_empty());
+        assert!(contacts_service
+            .get("Bogdan")
+            .unwrap()
+            .unwrap()
+            .links
+            .is_empty());
+        assert!(contacts_service.list_trash().unwrap()[0].links.is_empty());
+    }
+
+    #[test]
+    fn test_in_memory_contacts_service_migrations() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        assert!(contacts_service
+            .migration_status()
+            .unwrap()
+            .pending
+            .is_empty());
+        assert!(contacts_service.migrate_up().unwrap().is_empty());
+
+        let old: &str = r#"{"contacts":{},"trash":{},"changes":[]}"#;
+        let mut contacts_service: InMemoryContactsRepository = serde_json::from_str(old).unwrap();
+        let status: MigrationStatus = contacts_service.migration_status().unwrap();
+        assert_eq!(0, status.version);
+        assert_eq!(1, status.pending.len());
+        assert_eq!(status.pending, contacts_service.migrate_up().unwrap());
+        assert_eq!(1, contacts_service.migration_status().unwrap().version);
+        assert!(contacts_service.migrate_up().unwrap().is_empty());
+
+        let saved: String = serde_json::to_string(&contacts_service).unwrap();
+        let newer: String = saved.replace(r#""schema_version":1"#, r#""schema_version":99"#);
+        let contacts_service: InMemoryContactsRepository = serde_json::from_str(&newer).unwrap();
+        assert!(contacts_service.migration_status().is_err());
+    }
+
+    #[test]
+    fn test_in_memory_contacts_service_conformance() {
+        check_conformance(&mut InMemoryContactsRepository::new());
+    }
 }
@@ -0,0 +1,203 @@
+//! Versioned storage layouts. Every backend, and the export format, keeps an
+//! ordered list of the steps that brought its data from one layout to the
+//! next, and records the version its data is at, so that data written by an
+//! older release can be brought up to date instead of being misread.
+
+/// One step bringing data of type `S` to `version` from the version before.
+/// A step must leave data it already brought up to date unchanged, as a run
+/// interrupted before the version was recorded repeats its last step.
+pub struct Migration<S: ?Sized> {
+    /// The version the data is at after this step, the first step being 1.
+    pub version: u32,
+    /// What the step changes, as shown to the user.
+    pub description: &'static str,
+    /// Rewrites the data.
+    pub apply: fn(&mut S) -> Result<(), String>,
+}
+
+impl<S: ?Sized> Migration<S> {
+    fn step(&self) -> MigrationStep {
+        MigrationStep {
+            version: self.version,
+            description: self.description.to_string(),
+        }
+    }
+}
+
+/// A migration step as reported to the user.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationStep {
+    /// The version the data is at after this step.
+    pub version: u32,
+    /// What the step changes.
+    pub description: String,
+}
+
+/// The version stored data is at and the steps it is behind by.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MigrationStatus {
+    /// The version recorded with the data, 0 if it predates versioning.
+    pub version: u32,
+    /// The version this release writes.
+    pub latest: u32,
+    /// The steps `migrate_up` would run, in order.
+    pub pending: Vec<MigrationStep>,
+}
+
+/// The version data is at after all the given steps.
+pub fn get_latest_version<S: ?Sized>(migrations: &[Migration<S>]) -> u32 {
+    migrations
+        .last()
+        .map(|migration| migration.version)
+        .unwrap_or(0)
+}
+
+/// The steps data at `version` is behind by, failing if it was written by a
+/// newer release.
+pub fn get_status<S: ?Sized>(
+    migrations: &[Migration<S>],
+    version: u32,
+) -> Result<MigrationStatus, String> {
+    let latest: u32 = get_latest_version(migrations);
+    if version > latest {
+        return Err(format!(
+            "The data is at schema version {version}, newer than this release knows of ({latest})"
+        ));
+    }
+
+    Ok(MigrationStatus {
+        version,
+        latest,
+        pending: migrations
+            .iter()
+            .filter(|migration| migration.version > version)
+            .map(Migration::step)
+            .collect(),
+    })
+}
+
+/// Runs the steps `store`, at `version`, is behind by in order, calling
+/// `record` with the version reached after each one. Returns the steps run.
+pub fn migrate<S: ?Sized>(
+    migrations: &[Migration<S>],
+    store: &mut S,
+    version: u32,
+    mut record: impl FnMut(&mut S, u32) -> Result<(), String>,
+) -> Result<Vec<MigrationStep>, String> {
+    let status: MigrationStatus = get_status(migrations, version)?;
+    for migration in migrations
+        .iter()
+        .filter(|migration| migration.version > version)
+    {
+        (migration.apply)(store).map_err(|err| {
+            format!(
+                "Migrating to schema version {} failed: {err}",
+                migration.version
+            )
+        })?;
+        record(store, migration.version)?;
+    }
+    Ok(status.pending)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Numbers, with the version they are at.
+    struct Store {
+        version: u32,
+        numbers: Vec<u32>,
+    }
+
+    const MIGRATIONS: &[Migration<Store>] = &[
+        Migration {
+            version: 1,
+            description: "Sort the numbers",
+            apply: |store| {
+                store.numbers.sort();
+                Ok(())
+            },
+        },
+        Migration {
+            version: 2,
+            description: "Drop duplicate numbers",
+            apply: |store| {
+                store.numbers.dedup();
+                Ok(())
+            },
+        },
+    ];
+
+    fn migrate_store(store: &mut Store) -> Result<Vec<MigrationStep>, String> {
+        let version: u32 = store.version;
+        migrate(MIGRATIONS, store, version, |store, version| {
+            store.version = version;
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut store: Store = Store {
+            version: 0,
+            numbers: vec![3, 1, 3, 2],
+        };
+        let status: MigrationStatus = get_status(MIGRATIONS, store.version).unwrap();
+        assert_eq!(0, status.version);
+        assert_eq!(2, status.latest);
+        assert_eq!(
+            vec![1, 2],
+            status
+                .pending
+                .iter()
+                .map(|step| step.version)
+                .collect::<Vec<u32>>()
+        );
+
+        assert_eq!(status.pending, migrate_store(&mut store).unwrap());
+        assert_eq!(2, store.version);
+        assert_eq!(vec![1, 2, 3], store.numbers);
+
+        assert!(migrate_store(&mut store).unwrap().is_empty());
+        assert_eq!(vec![1, 2, 3], store.numbers);
+        assert!(get_status(MIGRATIONS, 2).unwrap().pending.is_empty());
+
+        let mut store: Store = Store {
+            version: 1,
+            numbers: vec![2, 1, 1],
+        };
+        assert_eq!(
+            "Drop duplicate numbers",
+            migrate_store(&mut store).unwrap()[0].description
+        );
+        assert_eq!(vec![2, 1], store.numbers);
+
+        let mut store: Store = Store {
+            version: 3,
+            numbers: vec![2, 1],
+        };
+        assert!(get_status(MIGRATIONS, 3).is_err());
+        assert!(migrate_store(&mut store).is_err());
+        assert_eq!(3, store.version);
+    }
+
+    /// The steps of every layout are numbered 1, 2, 3 and so on, in order.
+    fn check_numbering<S: ?Sized>(migrations: &[Migration<S>]) {
+        let versions: Vec<u32> = migrations
+            .iter()
+            .map(|migration| migration.version)
+            .collect();
+        assert_eq!((1..=versions.len() as u32).collect::<Vec<u32>>(), versions);
+    }
+
+    #[test]
+    fn test_migrations_numbering() {
+        check_numbering(MIGRATIONS);
+        check_numbering(crate::repositories::inmemory_contacts::IN_MEMORY_MIGRATIONS);
+        #[cfg(feature = "json")]
+        check_numbering(crate::codec::EXPORT_MIGRATIONS);
+        #[cfg(feature = "redis")]
+        check_numbering(crate::repositories::db_contacts::REDIS_MIGRATIONS);
+    }
+}
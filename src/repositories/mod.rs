@@ -1,3 +1,21 @@
+//! The storage interface and its backends.
+
+#[cfg(feature = "async")]
+pub mod async_contacts;
+#[cfg(feature = "redis")]
+pub mod async_db_contacts;
+#[cfg(test)]
+mod conformance;
 pub mod contacts;
+#[cfg(feature = "redis")]
 pub mod db_contacts;
+#[cfg(all(test, feature = "redis"))]
+mod fake_redis;
+#[cfg(feature = "file")]
+pub mod file_contacts;
 pub mod inmemory_contacts;
+pub mod migrations;
+#[cfg(all(test, any(feature = "file", feature = "redis")))]
+mod model_based;
+#[cfg(feature = "redis")]
+pub mod redis_connection;
@@ -0,0 +1,212 @@
+//! Runs random sequences of operations against `InMemoryContactsRepository` as
+//! the reference model and against another backend, comparing every result
+//! and the whole visible state after each step. Failing sequences shrink to a
+//! minimal one.
+
+use crate::models::{contact::Contact, link::LinkKind, sort_by::SortBy};
+use crate::repositories::contacts::{fuzz_text, ContactsRepository};
+use crate::repositories::inmemory_contacts::InMemoryContactsRepository;
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestCaseResult, TestRunner};
+use std::cell::RefCell;
+
+const NAMES: [&str; 4] = ["Ana", "Bob", "Cip", "Dan"];
+
+#[derive(Clone, Debug)]
+enum Op {
+    Add(String, String, String),
+    UpdateEmail(String, String),
+    UpdatePhoneNo(String, String),
+    Delete(String),
+    Restore(String),
+    Purge,
+    Import(Vec<(String, u64, String, bool)>),
+    Link(String, String, LinkKind, bool),
+    Unlink(String, String),
+    List(usize, usize, SortBy, bool),
+    ListPage(usize, SortBy, bool),
+}
+
+/// What a caller can observe of a contact, leaving out the timestamps, which
+/// the backends take at slightly different times.
+type Visible = (String, u64, String, Vec<String>);
+
+fn visible(contacts: Vec<Contact>) -> Vec<Visible> {
+    contacts
+        .into_iter()
+        .map(|c| {
+            let links: Vec<String> = c.links.iter().map(|link| link.to_string()).collect();
+            (c.name, c.phone_no, c.email, links)
+        })
+        .collect()
+}
+
+/// Mostly a few plain names, so that operations hit the same contacts, and
+/// otherwise fuzzed ones.
+fn name() -> impl Strategy<Value = String> {
+    prop_oneof![
+        8 => proptest::sample::select(NAMES.to_vec()).prop_map(String::from),
+        2 => fuzz_text("Ana|Bob|Cip|Dan"),
+    ]
+}
+
+fn phone_no() -> impl Strategy<Value = String> {
+    prop_oneof![
+        5 => "49[0-9]{9,10}",
+        1 => "[0-9]{0,12}",
+    ]
+}
+
+fn email() -> impl Strategy<Value = String> {
+    prop_oneof![
+        5 => "[a-c]{1,3}@mail\\.com",
+        1 => "[a-z@. ]{0,8}",
+    ]
+}
+
+fn sort_by() -> impl Strategy<Value = SortBy> {
+    // Creation and update times differ between backends within the same step.
+    prop_oneof![Just(SortBy::Name), Just(SortBy::Email)]
+}
+
+fn link_kind() -> impl Strategy<Value = LinkKind> {
+    prop_oneof![
+        Just(LinkKind::Manager),
+        Just(LinkKind::Assistant),
+        Just(LinkKind::Spouse),
+    ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (name(), phone_no(), email()).prop_map(|(n, p, e)| Op::Add(n, p, e)),
+        2 => (name(), email()).prop_map(|(n, e)| Op::UpdateEmail(n, e)),
+        2 => (name(), phone_no()).prop_map(|(n, p)| Op::UpdatePhoneNo(n, p)),
+        2 => name().prop_map(Op::Delete),
+        1 => name().prop_map(Op::Restore),
+        1 => Just(Op::Purge),
+        1 => proptest::collection::vec(
+            (
+                proptest::sample::select(NAMES.to_vec()).prop_map(String::from),
+                491000000000..499999999999u64,
+                "[a-c]{1,3}@mail\\.com",
+                any::<bool>(),
+            ),
+            0..3,
+        )
+        .prop_map(Op::Import),
+        2 => (name(), name(), link_kind(), any::<bool>())
+            .prop_map(|(n, o, k, b)| Op::Link(n, o, k, b)),
+        1 => (name(), name()).prop_map(|(n, o)| Op::Unlink(n, o)),
+        1 => (0..3usize, 1..4usize, sort_by(), any::<bool>())
+            .prop_map(|(n, s, b, d)| Op::List(n, s, b, d)),
+        1 => (1..4usize, sort_by(), any::<bool>()).prop_map(|(s, b, d)| Op::ListPage(s, b, d)),
+    ]
+}
+
+/// The outcome of an operation: whether it failed and what it returned.
+fn apply(contacts_service: &mut dyn ContactsRepository, op: &Op) -> Result<Vec<Visible>, ()> {
+    let unit = |result: Result<(), String>| result.map(|_| Vec::new()).map_err(|_| ());
+    match op {
+        Op::Add(name, phone_no, email) => {
+            unit(contacts_service.add(name.clone(), phone_no.clone(), email.clone()))
+        }
+        Op::UpdateEmail(name, email) => unit(contacts_service.update_email(name, email.clone())),
+        Op::UpdatePhoneNo(name, phone_no) => {
+            unit(contacts_service.update_phone_no(name, phone_no.clone()))
+        }
+        Op::Delete(name) => unit(contacts_service.delete(name)),
+        Op::Restore(name) => unit(contacts_service.restore(name)),
+        Op::Purge => unit(contacts_service.purge_trash(None).map(|_| ())),
+        Op::Import(contacts) => unit(
+            contacts_service.import_contacts(
+                contacts
+                    .iter()
+                    .map(|(name, phone_no, email, deleted)| Contact {
+                        name: name.clone(),
+                        phone_no: *phone_no,
+                        email: email.clone(),
+                        created_at: 0,
+                        updated_at: 0,
+                        deleted_at: deleted.then_some(1),
+                        links: Vec::new(),
+                    })
+                    .collect(),
+            ),
+        ),
+        Op::Link(name, other, kind, bidirectional) => {
+            unit(contacts_service.link(name, other, *kind, *bidirectional))
+        }
+        Op::Unlink(name, other) => unit(contacts_service.unlink(name, other)),
+        Op::List(page_no, page_size, sort_by, descending) => contacts_service
+            .list(*page_no, *page_size, *sort_by, *descending)
+            .map(visible)
+            .map_err(|_| ()),
+        Op::ListPage(page_size, sort_by, descending) => {
+            let mut contacts: Vec<Contact> = Vec::new();
+            let mut cursor: Option<String> = None;
+            loop {
+                let page = contacts_service
+                    .list_page(cursor.as_deref(), *page_size, *sort_by, *descending)
+                    .map_err(|_| ())?;
+                contacts.extend(page.contacts);
+                cursor = page.next_cursor;
+                if cursor.is_none() {
+                    return Ok(visible(contacts));
+                }
+            }
+        }
+    }
+}
+
+fn get_state(contacts_service: &dyn ContactsRepository) -> (Vec<Visible>, Vec<Visible>, usize) {
+    (
+        visible(
+            contacts_service
+                .list(0, usize::MAX, SortBy::Name, false)
+                .unwrap(),
+        ),
+        visible(contacts_service.list_trash().unwrap()),
+        contacts_service.count().unwrap(),
+    )
+}
+
+fn check_against_model(
+    contacts_service: &mut dyn ContactsRepository,
+    ops: &[Op],
+) -> TestCaseResult {
+    let mut model: InMemoryContactsRepository = InMemoryContactsRepository::new();
+    contacts_service.replace_all(Vec::new()).unwrap();
+
+    for (step, op) in ops.iter().enumerate() {
+        let expected: Result<Vec<Visible>, ()> = apply(&mut model, op);
+        let actual: Result<Vec<Visible>, ()> = apply(contacts_service, op);
+        prop_assert_eq!(expected, actual, "result of step {} {:?}", step, op);
+        prop_assert_eq!(
+            get_state(&model),
+            get_state(contacts_service),
+            "state after step {} {:?}",
+            step,
+            op
+        );
+    }
+    Ok(())
+}
+
+/// Checks a backend against the model over `cases` random sequences. The
+/// backend is reused, emptied before each sequence, so that it can be slow to
+/// open.
+pub fn check_against_model_with(contacts_service: &mut dyn ContactsRepository, cases: u32) {
+    let contacts_service: RefCell<&mut dyn ContactsRepository> = RefCell::new(contacts_service);
+    let mut runner: TestRunner = TestRunner::new(Config {
+        cases,
+        failure_persistence: None,
+        ..Config::default()
+    });
+    let result = runner.run(&proptest::collection::vec(op(), 1..40), |ops| {
+        check_against_model(*contacts_service.borrow_mut(), &ops)
+    });
+    if let Err(err) = result {
+        panic!("{err}");
+    }
+}
@@ -0,0 +1,1005 @@
+//! A single Redis connection kept open across calls, with connect and read
+//! timeouts. A connection that failed on I/O is dropped and the next call opens
+//! a new one, retrying with exponential backoff.
+//!
+//! The server is a plain URL, the master a set of Sentinels agree on, or the
+//! Redis Cluster node serving the hash slot of the store, so that a failover
+//! or a resharding is followed on reconnect.
+//!
+//! `AsyncPersistentConnection` does the same for async callers, over one
+//! multiplexed connection that concurrent calls share.
+
+use native_tls::{Certificate, HandshakeError, TlsConnector, TlsStream};
+use redis::aio::{self, MultiplexedConnection};
+use redis::{
+    Client as RedisClient, ConnectionAddr, ConnectionInfo, ConnectionLike, ErrorKind,
+    IntoConnectionInfo, Parser, RedisError, RedisFuture, RedisResult, Value,
+};
+use regex::Regex;
+use std::cell::{RefCell, RefMut};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+use tokio::time::error::Elapsed;
+
+const REDIS_URL_KEY: &str = "REDIS_URL";
+const REDIS_DB_KEY: &str = "REDIS_DB";
+const REDIS_KEY_PREFIX_KEY: &str = "REDIS_KEY_PREFIX";
+const REDIS_USERNAME_KEY: &str = "REDIS_USERNAME";
+const REDIS_PASSWORD_KEY: &str = "REDIS_PASSWORD";
+const REDIS_CA_FILE_KEY: &str = "REDIS_CA_FILE";
+const REDIS_SENTINELS_KEY: &str = "REDIS_SENTINELS";
+const REDIS_SENTINEL_MASTER_KEY: &str = "REDIS_SENTINEL_MASTER";
+const REDIS_CLUSTER_NODES_KEY: &str = "REDIS_CLUSTER_NODES";
+const REDIS_CONNECT_TIMEOUT_KEY: &str = "REDIS_CONNECT_TIMEOUT_MS";
+const REDIS_READ_TIMEOUT_KEY: &str = "REDIS_READ_TIMEOUT_MS";
+const REDIS_RETRIES_KEY: &str = "REDIS_RETRIES";
+const CLUSTER_SLOTS: u16 = 16384;
+const DEFAULT_KEY_PREFIX: &str = "contacts";
+/// No `_`, `:`, `@` or braces, which separate the prefix from the rest of a
+/// key, so that the keys of one prefix never match the patterns of another.
+const KEY_PREFIX_REGEX: &str = r"^[A-Za-z0-9][A-Za-z0-9.-]{0,63}$";
+
+/// How long to wait and how often to retry when talking to Redis, and which
+/// keys the store lives under.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RedisOptions {
+    /// The longest a single connection attempt may take.
+    pub connect_timeout: Duration,
+    /// The longest to wait for the answer to a command.
+    pub read_timeout: Duration,
+    /// How many more times to try connecting after the first attempt failed.
+    pub retries: u32,
+    /// The wait before the first retry, doubled for every following one.
+    pub backoff: Duration,
+    /// The CA certificates to verify `rediss://` servers against, besides
+    /// the system ones.
+    pub ca_file: Option<PathBuf>,
+    /// What every key of the store starts with, so that several stores can
+    /// share one database.
+    pub key_prefix: String,
+}
+
+impl Default for RedisOptions {
+    fn default() -> Self {
+        RedisOptions {
+            connect_timeout: Duration::from_secs(2),
+            read_timeout: Duration::from_secs(5),
+            retries: 3,
+            backoff: Duration::from_millis(100),
+            ca_file: None,
+            key_prefix: DEFAULT_KEY_PREFIX.to_string(),
+        }
+    }
+}
+
+impl RedisOptions {
+    /// The defaults, overridden by `REDIS_CONNECT_TIMEOUT_MS`,
+    /// `REDIS_READ_TIMEOUT_MS`, `REDIS_RETRIES`, `REDIS_CA_FILE` and
+    /// `REDIS_KEY_PREFIX`.
+    pub fn from_env() -> Result<Self, String> {
+        let mut options: RedisOptions = RedisOptions::default();
+        if let Some(millis) = get_env_number(REDIS_CONNECT_TIMEOUT_KEY)? {
+            options.connect_timeout = Duration::from_millis(millis);
+        }
+        if let Some(millis) = get_env_number(REDIS_READ_TIMEOUT_KEY)? {
+            options.read_timeout = Duration::from_millis(millis);
+        }
+        if let Some(retries) = get_env_number(REDIS_RETRIES_KEY)? {
+            options.retries = u32::try_from(retries).map_err(|err| err.to_string())?;
+        }
+        if let Ok(ca_file) = env::var(REDIS_CA_FILE_KEY) {
+            let ca_file: PathBuf = PathBuf::from(ca_file);
+            if !ca_file.is_file() {
+                return Err(format!(
+                    "{REDIS_CA_FILE_KEY} {} is not a file",
+                    ca_file.display()
+                ));
+            }
+            options.ca_file = Some(ca_file);
+        }
+        if let Ok(key_prefix) = env::var(REDIS_KEY_PREFIX_KEY) {
+            options.key_prefix = get_valid_key_prefix(&key_prefix)
+                .map_err(|err| format!("{REDIS_KEY_PREFIX_KEY}: {err}"))?;
+        }
+        Ok(options)
+    }
+}
+
+/// Checks that `prefix` can start the keys of a store.
+pub fn get_valid_key_prefix(prefix: &str) -> Result<String, String> {
+    let regex: Regex = Regex::new(KEY_PREFIX_REGEX).map_err(|err| err.to_string())?;
+    match regex.is_match(prefix) {
+        true => Ok(prefix.to_string()),
+        false => Err(format!(
+            "Invalid key prefix {prefix}, key prefixes have up to 64 letters, digits, - and ."
+        )),
+    }
+}
+
+fn get_env_number(key: &str) -> Result<Option<u64>, String> {
+    match env::var(key) {
+        Ok(value) => value
+            .parse::<u64>()
+            .map(Some)
+            .map_err(|_| format!("{key} must be a whole number, got {value}")),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Where to connect to. Every variant carries the credentials, TLS and
+/// database of the connections it makes.
+#[derive(Clone, Debug)]
+pub enum RedisTarget {
+    /// A single server.
+    Server(ConnectionInfo),
+    /// The master named `master` as reported by the first Sentinel that
+    /// answers, connected to with the settings of `server` but its address.
+    Sentinel {
+        /// The Sentinels to ask, in order.
+        sentinels: Vec<ConnectionInfo>,
+        /// The name the Sentinels know the master by.
+        master: String,
+        /// The settings of the connections to the master.
+        server: ConnectionInfo,
+    },
+    /// The node owning the hash slot of the store, found through the first
+    /// of `nodes` that answers.
+    Cluster {
+        /// The cluster nodes to ask, in order.
+        nodes: Vec<ConnectionInfo>,
+    },
+}
+
+impl RedisTarget {
+    /// Reads `REDIS_CLUSTER_NODES` or `REDIS_SENTINELS` with
+    /// `REDIS_SENTINEL_MASTER`, or else `REDIS_URL`, all URLs being comma
+    /// separated. `REDIS_USERNAME` and `REDIS_PASSWORD` override the
+    /// credentials of the URLs, except for those of the Sentinels, and
+    /// `REDIS_DB` the database of `REDIS_URL`.
+    pub fn from_env() -> Result<Self, String> {
+        let credentials: (Option<String>, Option<String>) = (
+            env::var(REDIS_USERNAME_KEY).ok(),
+            env::var(REDIS_PASSWORD_KEY).ok(),
+        );
+        let database: Option<i64> = get_env_number(REDIS_DB_KEY)?
+            .map(|database| i64::try_from(database).map_err(|err| err.to_string()))
+            .transpose()?;
+
+        if let Ok(nodes) = env::var(REDIS_CLUSTER_NODES_KEY) {
+            if database.is_some_and(|database| database != 0) {
+                return Err(format!(
+                    "A Redis Cluster only has database 0, unset {REDIS_DB_KEY}"
+                ));
+            }
+            let nodes: Vec<ConnectionInfo> = get_connection_infos(&nodes)?
+                .into_iter()
+                .map(|node| with_credentials(node, &credentials))
+                .collect();
+            return Ok(RedisTarget::Cluster { nodes });
+        }
+
+        let redis_url: String =
+            env::var(REDIS_URL_KEY).map_err(|_| format!("Cannot find key {REDIS_URL_KEY}"))?;
+        let mut server: ConnectionInfo =
+            with_credentials(get_connection_info(&redis_url)?, &credentials);
+        if let Some(database) = database {
+            server.redis.db = database;
+        }
+        match env::var(REDIS_SENTINELS_KEY) {
+            Ok(sentinels) => Ok(RedisTarget::Sentinel {
+                sentinels: get_connection_infos(&sentinels)?,
+                master: env::var(REDIS_SENTINEL_MASTER_KEY).map_err(|_| {
+                    format!("Set {REDIS_SENTINEL_MASTER_KEY} to the name of the master")
+                })?,
+                server,
+            }),
+            Err(_) => Ok(RedisTarget::Server(server)),
+        }
+    }
+
+    /// Whether the keys need a hash tag to share one slot.
+    pub fn is_cluster(&self) -> bool {
+        matches!(self, RedisTarget::Cluster { .. })
+    }
+}
+
+impl fmt::Display for RedisTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedisTarget::Server(server) => write!(f, "{}", server.addr),
+            RedisTarget::Sentinel { master, .. } => write!(f, "master {master} via Sentinel"),
+            RedisTarget::Cluster { nodes } => {
+                let nodes: Vec<String> = nodes.iter().map(|node| node.addr.to_string()).collect();
+                write!(f, "cluster {}", nodes.join(", "))
+            }
+        }
+    }
+}
+
+fn get_connection_info(redis_url: &str) -> Result<ConnectionInfo, String> {
+    redis_url
+        .trim()
+        .into_connection_info()
+        .map_err(|err| format!("Invalid Redis URL {redis_url}: {err}"))
+}
+
+fn get_connection_infos(redis_urls: &str) -> Result<Vec<ConnectionInfo>, String> {
+    let infos: Vec<ConnectionInfo> = redis_urls
+        .split(',')
+        .filter(|redis_url| !redis_url.trim().is_empty())
+        .map(get_connection_info)
+        .collect::<Result<Vec<ConnectionInfo>, String>>()?;
+    match infos.is_empty() {
+        true => Err("Expected at least one Redis URL".to_string()),
+        false => Ok(infos),
+    }
+}
+
+fn with_credentials(
+    mut info: ConnectionInfo,
+    (username, password): &(Option<String>, Option<String>),
+) -> ConnectionInfo {
+    if username.is_some() {
+        info.redis.username = username.clone();
+    }
+    if password.is_some() {
+        info.redis.password = password.clone();
+    }
+    info
+}
+
+/// `info` moved to another host, keeping TLS, credentials and database.
+fn with_addr(info: &ConnectionInfo, host: String, port: u16) -> ConnectionInfo {
+    let addr: ConnectionAddr = match info.addr {
+        ConnectionAddr::TcpTls { insecure, .. } => ConnectionAddr::TcpTls {
+            host,
+            port,
+            insecure,
+        },
+        _ => ConnectionAddr::Tcp(host, port),
+    };
+    ConnectionInfo {
+        addr,
+        redis: info.redis.clone(),
+    }
+}
+
+/// The cluster hash slot of `key`: only the part between the first `{` and
+/// the following `}` counts, if not empty, so keys sharing it share a slot.
+pub fn get_key_slot(key: &str) -> u16 {
+    let hashed: &str = match key.split_once('{') {
+        Some((_, rest)) => match rest.split_once('}') {
+            Some((tag, _)) if !tag.is_empty() => tag,
+            _ => key,
+        },
+        None => key,
+    };
+    crc16(hashed.as_bytes()) % CLUSTER_SLOTS
+}
+
+/// CRC-16/XMODEM, as used by Redis Cluster.
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = match crc & 0x8000 {
+                0 => crc << 1,
+                _ => (crc << 1) ^ 0x1021,
+            };
+        }
+    }
+    crc
+}
+
+/// The error to report for a failed command and whether the connection it
+/// failed on must be dropped: after an I/O error the stream may be out of step
+/// with the server, after a redirection the server no longer holds the store.
+fn check_error(err: RedisError, read_timeout: Duration) -> (RedisError, bool) {
+    if err.is_io_error() {
+        let err: RedisError = match err.is_timeout() {
+            true => RedisError::from((
+                ErrorKind::IoError,
+                "Redis did not answer in time",
+                format!("no reply within {} ms", read_timeout.as_millis()),
+            )),
+            false => RedisError::from((
+                ErrorKind::IoError,
+                "Lost the connection to Redis",
+                err.to_string(),
+            )),
+        };
+        return (err, true);
+    }
+    match err.kind() {
+        ErrorKind::Moved
+        | ErrorKind::Ask
+        | ErrorKind::TryAgain
+        | ErrorKind::ClusterDown
+        | ErrorKind::MasterDown
+        | ErrorKind::ReadOnly => {
+            let err: RedisError = RedisError::from((
+                err.kind(),
+                "Redis moved the contacts elsewhere, the next command reconnects",
+                err.to_string(),
+            ));
+            (err, true)
+        }
+        _ => (err, false),
+    }
+}
+
+/// Sentinel may still point to a master that was just demoted, so the role
+/// of the server it reported is checked once connected.
+fn check_role(role: &[Value], info: &ConnectionInfo) -> Result<(), String> {
+    match role.first() == Some(&Value::Data(b"master".to_vec())) {
+        true => Ok(()),
+        false => Err(format!("{} is not a master", info.addr)),
+    }
+}
+
+/// A blocking connection over TLS. The one `redis` makes can neither trust
+/// a CA of our choosing nor bound its handshake by a timeout.
+struct TlsConnection {
+    stream: TlsStream<TcpStream>,
+    parser: Parser,
+    db: i64,
+    open: bool,
+}
+
+impl TlsConnection {
+    fn send(&mut self, cmd: &[u8]) -> RedisResult<()> {
+        let result: io::Result<()> = self.stream.write_all(cmd).and_then(|_| self.stream.flush());
+        self.open &= result.is_ok();
+        Ok(result?)
+    }
+
+    fn receive(&mut self) -> RedisResult<Value> {
+        let result: RedisResult<Value> = self.parser.parse_value(&mut self.stream);
+        if let Err(err) = &result {
+            self.open &= !err.is_io_error();
+        }
+        result
+    }
+}
+
+impl ConnectionLike for TlsConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        self.send(cmd)?;
+        self.receive()
+    }
+
+    /// Reads every reply even after an error one, as replies to the commands
+    /// of a transaction may be errors, and reports the first error.
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        self.send(cmd)?;
+        let mut values: Vec<Value> = Vec::with_capacity(count);
+        let mut first_err: Option<RedisError> = None;
+        for index in 0..offset + count {
+            match self.receive() {
+                Ok(value) if index >= offset => values.push(value),
+                Ok(_) => {}
+                Err(err) if err.is_io_error() => return Err(err),
+                Err(err) => {
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+        match first_err {
+            Some(err) => Err(err),
+            None => Ok(values),
+        }
+    }
+
+    fn get_db(&self) -> i64 {
+        self.db
+    }
+
+    fn check_connection(&mut self) -> bool {
+        redis::cmd("PING").query::<String>(self).is_ok()
+    }
+
+    fn is_open(&self) -> bool {
+        self.open
+    }
+}
+
+/// Wraps a Redis connection to notice the errors after which it must not be
+/// reused, see `check_error`.
+pub struct RedisConnection {
+    connection: Box<dyn ConnectionLike + Send>,
+    read_timeout: Duration,
+    broken: bool,
+}
+
+impl RedisConnection {
+    fn check<T>(&mut self, result: RedisResult<T>) -> RedisResult<T> {
+        result.map_err(|err| {
+            let (err, broken) = check_error(err, self.read_timeout);
+            self.broken |= broken;
+            err
+        })
+    }
+}
+
+impl ConnectionLike for RedisConnection {
+    fn req_packed_command(&mut self, cmd: &[u8]) -> RedisResult<Value> {
+        let result: RedisResult<Value> = self.connection.req_packed_command(cmd);
+        self.check(result)
+    }
+
+    fn req_packed_commands(
+        &mut self,
+        cmd: &[u8],
+        offset: usize,
+        count: usize,
+    ) -> RedisResult<Vec<Value>> {
+        let result: RedisResult<Vec<Value>> =
+            self.connection.req_packed_commands(cmd, offset, count);
+        self.check(result)
+    }
+
+    fn get_db(&self) -> i64 {
+        self.connection.get_db()
+    }
+
+    fn check_connection(&mut self) -> bool {
+        self.connection.check_connection()
+    }
+
+    fn is_open(&self) -> bool {
+        !self.broken && self.connection.is_open()
+    }
+}
+
+/// A multiplexed connection, cheap to clone and shared by concurrent calls,
+/// with the read timeout applied to every command. A failure that breaks it
+/// breaks all its clones.
+#[derive(Clone)]
+pub struct AsyncRedisConnection {
+    connection: MultiplexedConnection,
+    read_timeout: Duration,
+    broken: Arc<AtomicBool>,
+}
+
+impl AsyncRedisConnection {
+    fn is_open(&self) -> bool {
+        !self.broken.load(Ordering::Relaxed)
+    }
+
+    fn check<T>(&self, result: Result<RedisResult<T>, Elapsed>) -> RedisResult<T> {
+        let result: RedisResult<T> = result
+            .unwrap_or_else(|_| Err(RedisError::from(io::Error::from(io::ErrorKind::TimedOut))));
+        result.map_err(|err| {
+            let (err, broken) = check_error(err, self.read_timeout);
+            if broken {
+                self.broken.store(true, Ordering::Relaxed);
+            }
+            err
+        })
+    }
+}
+
+impl aio::ConnectionLike for AsyncRedisConnection {
+    fn req_packed_command<'a>(&'a mut self, cmd: &'a redis::Cmd) -> RedisFuture<'a, Value> {
+        Box::pin(async move {
+            let result: Result<RedisResult<Value>, Elapsed> =
+                tokio::time::timeout(self.read_timeout, self.connection.req_packed_command(cmd))
+                    .await;
+            self.check(result)
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a redis::Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> RedisFuture<'a, Vec<Value>> {
+        Box::pin(async move {
+            let result: Result<RedisResult<Vec<Value>>, Elapsed> = tokio::time::timeout(
+                self.read_timeout,
+                self.connection.req_packed_commands(cmd, offset, count),
+            )
+            .await;
+            self.check(result)
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.connection.get_db()
+    }
+}
+
+/// What the blocking and the async connections share: where to connect, how,
+/// and in a cluster which hash slot the store lives in.
+#[derive(Clone, Debug)]
+struct RedisEndpoint {
+    target: RedisTarget,
+    options: RedisOptions,
+    hash_slot: u16,
+}
+
+impl RedisEndpoint {
+    fn new(target: RedisTarget, options: RedisOptions, hash_tag: &str) -> Self {
+        RedisEndpoint {
+            target,
+            options,
+            hash_slot: get_key_slot(hash_tag),
+        }
+    }
+
+    fn get_connect_error(&self, attempts: u32, err: String) -> String {
+        format!(
+            "Cannot connect to Redis at {} after {attempts} attempt{}: {err}",
+            self.target,
+            if attempts == 1 { "" } else { "s" },
+        )
+    }
+
+    /// The server holding the store, looked up through the Sentinels or the
+    /// cluster nodes if need be.
+    fn resolve(&self) -> Result<ConnectionInfo, String> {
+        match &self.target {
+            RedisTarget::Server(server) => Ok(server.clone()),
+            RedisTarget::Sentinel {
+                sentinels,
+                master,
+                server,
+            } => self.find_master(sentinels, master, server),
+            RedisTarget::Cluster { nodes } => self.find_slot_owner(nodes),
+        }
+    }
+
+    /// What `rediss://` servers are verified with: the CA file if there is
+    /// one, besides the system certificates, and nothing at all if the URL
+    /// asks for an insecure connection.
+    fn get_tls_connector(&self, insecure: bool) -> Result<TlsConnector, String> {
+        let mut builder = TlsConnector::builder();
+        if insecure {
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true)
+                .use_sni(false);
+        }
+        if let Some(ca_file) = &self.options.ca_file {
+            let pem: Vec<u8> = fs::read(ca_file)
+                .map_err(|err| format!("{REDIS_CA_FILE_KEY} {}: {err}", ca_file.display()))?;
+            let certificates: Vec<Certificate> = Certificate::stack_from_pem(&pem)
+                .map_err(|err| format!("{REDIS_CA_FILE_KEY} {}: {err}", ca_file.display()))?;
+            for certificate in certificates {
+                builder.add_root_certificate(certificate);
+            }
+        }
+        builder.build().map_err(|err| err.to_string())
+    }
+
+    fn get_handshake_timeout_error(&self) -> String {
+        format!(
+            "no TLS handshake within {} ms",
+            self.options.connect_timeout.as_millis()
+        )
+    }
+
+    fn open(&self, info: &ConnectionInfo) -> Result<Box<dyn ConnectionLike + Send>, String> {
+        let connect = || -> Result<Box<dyn ConnectionLike + Send>, String> {
+            match &info.addr {
+                ConnectionAddr::TcpTls {
+                    host,
+                    port,
+                    insecure,
+                } => Ok(Box::new(self.open_tls(info, host, *port, *insecure)?)),
+                _ => {
+                    let redis_client: RedisClient =
+                        RedisClient::open(info.clone()).map_err(|err| err.to_string())?;
+                    let connection: redis::Connection = redis_client
+                        .get_connection_with_timeout(self.options.connect_timeout)
+                        .map_err(|err| err.to_string())?;
+                    connection
+                        .set_read_timeout(Some(self.options.read_timeout))
+                        .and_then(|_| connection.set_write_timeout(Some(self.options.read_timeout)))
+                        .map_err(|err| err.to_string())?;
+                    Ok(Box::new(connection))
+                }
+            }
+        };
+        connect().map_err(|err| format!("{}: {err}", info.addr))
+    }
+
+    /// Both reaching the server and the handshake are bounded by the connect
+    /// timeout, then the connection logs in and selects the database as
+    /// `redis` would.
+    fn open_tls(
+        &self,
+        info: &ConnectionInfo,
+        host: &str,
+        port: u16,
+        insecure: bool,
+    ) -> Result<TlsConnection, String> {
+        let connector: TlsConnector = self.get_tls_connector(insecure)?;
+        let stream: TcpStream =
+            reach(host, port, self.options.connect_timeout).map_err(|err| err.to_string())?;
+        stream
+            .set_read_timeout(Some(self.options.connect_timeout))
+            .and_then(|_| stream.set_write_timeout(Some(self.options.connect_timeout)))
+            .map_err(|err| err.to_string())?;
+        let stream: TlsStream<TcpStream> = match connector.connect(host, stream) {
+            Ok(x) => x,
+            Err(HandshakeError::WouldBlock(_)) => return Err(self.get_handshake_timeout_error()),
+            Err(HandshakeError::Failure(err)) => {
+                return Err(format!("TLS handshake failed: {err}"));
+            }
+        };
+        stream
+            .get_ref()
+            .set_read_timeout(Some(self.options.read_timeout))
+            .and_then(|_| {
+                stream
+                    .get_ref()
+                    .set_write_timeout(Some(self.options.read_timeout))
+            })
+            .map_err(|err| err.to_string())?;
+
+        let mut connection: TlsConnection = TlsConnection {
+            stream,
+            parser: Parser::new(),
+            db: info.redis.db,
+            open: true,
+        };
+        if let Some(password) = &info.redis.password {
+            let mut auth: redis::Cmd = redis::cmd("AUTH");
+            if let Some(username) = &info.redis.username {
+                auth.arg(username);
+            }
+            auth.arg(password)
+                .query::<()>(&mut connection)
+                .map_err(|err| err.to_string())?;
+        }
+        if info.redis.db != 0 {
+            redis::cmd("SELECT")
+                .arg(info.redis.db)
+                .query::<()>(&mut connection)
+                .map_err(|err| err.to_string())?;
+        }
+        Ok(connection)
+    }
+
+    async fn open_async(&self, info: &ConnectionInfo) -> Result<MultiplexedConnection, String> {
+        let connect = async {
+            match &info.addr {
+                ConnectionAddr::TcpTls {
+                    host,
+                    port,
+                    insecure,
+                } => {
+                    let connector: tokio_native_tls::TlsConnector =
+                        self.get_tls_connector(*insecure)?.into();
+                    let stream: tokio::net::TcpStream =
+                        tokio::net::TcpStream::connect((host.as_str(), *port))
+                            .await
+                            .map_err(|err| err.to_string())?;
+                    let stream: tokio_native_tls::TlsStream<tokio::net::TcpStream> = connector
+                        .connect(host, stream)
+                        .await
+                        .map_err(|err| format!("TLS handshake failed: {err}"))?;
+                    let (connection, driver) = MultiplexedConnection::new(&info.redis, stream)
+                        .await
+                        .map_err(|err| err.to_string())?;
+                    tokio::spawn(driver);
+                    Ok(connection)
+                }
+                _ => RedisClient::open(info.clone())
+                    .map_err(|err| err.to_string())?
+                    .get_multiplexed_tokio_connection()
+                    .await
+                    .map_err(|err| err.to_string()),
+            }
+        };
+        tokio::time::timeout(self.options.connect_timeout, connect)
+            .await
+            .unwrap_or_else(|_| {
+                Err(format!(
+                    "no answer within {} ms",
+                    self.options.connect_timeout.as_millis()
+                ))
+            })
+            .map_err(|err| format!("{}: {err}", info.addr))
+    }
+
+    fn find_master(
+        &self,
+        sentinels: &[ConnectionInfo],
+        master: &str,
+        server: &ConnectionInfo,
+    ) -> Result<ConnectionInfo, String> {
+        let mut errors: Vec<String> = Vec::new();
+        for sentinel in sentinels {
+            let address: Result<Option<(String, u16)>, String> =
+                self.open(sentinel).and_then(|mut connection| {
+                    redis::cmd("SENTINEL")
+                        .arg("get-master-addr-by-name")
+                        .arg(master)
+                        .query::<Option<(String, u16)>>(&mut *connection)
+                        .map_err(|err| format!("{}: {err}", sentinel.addr))
+                });
+            match address {
+                Ok(Some((host, port))) => return Ok(with_addr(server, host, port)),
+                Ok(None) => errors.push(format!("{}: unknown master", sentinel.addr)),
+                Err(err) => errors.push(err),
+            }
+        }
+        Err(errors.join(", "))
+    }
+
+    fn find_slot_owner(&self, nodes: &[ConnectionInfo]) -> Result<ConnectionInfo, String> {
+        let mut errors: Vec<String> = Vec::new();
+        for node in nodes {
+            let owner: Result<Option<(String, u16)>, String> =
+                self.open(node).and_then(|mut connection| {
+                    redis::cmd("CLUSTER")
+                        .arg("SLOTS")
+                        .query::<Vec<Value>>(&mut *connection)
+                        .map_err(|err| err.to_string())
+                        .and_then(|ranges| get_slot_owner(&ranges, self.hash_slot))
+                        .map_err(|err| format!("{}: {err}", node.addr))
+                });
+            match owner {
+                Ok(Some((host, port))) => return Ok(with_addr(node, host, port)),
+                Ok(None) => errors.push(format!(
+                    "{}: no node serves slot {}",
+                    node.addr, self.hash_slot
+                )),
+                Err(err) => errors.push(err),
+            }
+        }
+        Err(errors.join(", "))
+    }
+}
+
+/// A blocking connection to a target, opened on first use and reopened
+/// after an I/O error.
+pub struct PersistentConnection {
+    endpoint: RedisEndpoint,
+    connection: RefCell<Option<RedisConnection>>,
+}
+
+impl PersistentConnection {
+    /// In a cluster, connects to the node serving the slot of `hash_tag`,
+    /// which every key of the store must share.
+    pub fn new(target: RedisTarget, options: RedisOptions, hash_tag: &str) -> Self {
+        PersistentConnection {
+            endpoint: RedisEndpoint::new(target, options, hash_tag),
+            connection: RefCell::new(None),
+        }
+    }
+
+    /// The open connection, or a new one if there is none or the last one
+    /// failed.
+    pub fn get(&self) -> Result<RefMut<'_, RedisConnection>, String> {
+        let mut connection: RefMut<Option<RedisConnection>> = self.connection.borrow_mut();
+        if !connection.as_ref().is_some_and(|x| x.is_open()) {
+            *connection = None;
+            *connection = Some(self.connect()?);
+        }
+        Ok(RefMut::map(connection, |x| x.as_mut().expect("connected")))
+    }
+
+    fn connect(&self) -> Result<RedisConnection, String> {
+        let options: &RedisOptions = &self.endpoint.options;
+        let mut backoff: Duration = options.backoff;
+        let mut attempt: u32 = 0;
+        loop {
+            match self.try_connect() {
+                Ok(connection) => return Ok(connection),
+                Err(err) if attempt == options.retries => {
+                    return Err(self.endpoint.get_connect_error(attempt + 1, err));
+                }
+                Err(_) => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn try_connect(&self) -> Result<RedisConnection, String> {
+        let server: ConnectionInfo = self.endpoint.resolve()?;
+        let mut connection: Box<dyn ConnectionLike + Send> = self.endpoint.open(&server)?;
+        if let RedisTarget::Sentinel { .. } = self.endpoint.target {
+            let role: Vec<Value> = redis::cmd("ROLE")
+                .query::<Vec<Value>>(&mut *connection)
+                .map_err(|err| err.to_string())?;
+            check_role(&role, &server)?;
+        }
+        Ok(RedisConnection {
+            connection,
+            read_timeout: self.endpoint.options.read_timeout,
+            broken: false,
+        })
+    }
+}
+
+/// The async counterpart of `PersistentConnection`, which any number of calls
+/// can use at once. Looking up the server through Sentinel or the cluster
+/// nodes, once per connection, runs on the blocking thread pool.
+pub struct AsyncPersistentConnection {
+    endpoint: RedisEndpoint,
+    connection: Mutex<Option<AsyncRedisConnection>>,
+}
+
+impl AsyncPersistentConnection {
+    /// See `PersistentConnection::new`.
+    pub fn new(target: RedisTarget, options: RedisOptions, hash_tag: &str) -> Self {
+        AsyncPersistentConnection {
+            endpoint: RedisEndpoint::new(target, options, hash_tag),
+            connection: Mutex::new(None),
+        }
+    }
+
+    /// The open connection, or a new one if there is none or the last one
+    /// failed. Calls racing to replace a failed connection may each open one,
+    /// the last one is kept.
+    pub async fn get(&self) -> Result<AsyncRedisConnection, String> {
+        if let Some(connection) = self.lock()?.as_ref().filter(|x| x.is_open()) {
+            return Ok(connection.clone());
+        }
+        let connection: AsyncRedisConnection = self.connect().await?;
+        *self.lock()? = Some(connection.clone());
+        Ok(connection)
+    }
+
+    /// A blocking connection to the same target, for work only written for
+    /// one. It connects on its first use, to be made off the async runtime.
+    pub fn to_blocking(&self) -> PersistentConnection {
+        PersistentConnection {
+            endpoint: self.endpoint.clone(),
+            connection: RefCell::new(None),
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Option<AsyncRedisConnection>>, String> {
+        self.connection.lock().map_err(|err| err.to_string())
+    }
+
+    async fn connect(&self) -> Result<AsyncRedisConnection, String> {
+        let options: &RedisOptions = &self.endpoint.options;
+        let mut backoff: Duration = options.backoff;
+        let mut attempt: u32 = 0;
+        loop {
+            match self.try_connect().await {
+                Ok(connection) => return Ok(connection),
+                Err(err) if attempt == options.retries => {
+                    return Err(self.endpoint.get_connect_error(attempt + 1, err));
+                }
+                Err(_) => {
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn try_connect(&self) -> Result<AsyncRedisConnection, String> {
+        let server: ConnectionInfo = match &self.endpoint.target {
+            RedisTarget::Server(server) => server.clone(),
+            _ => {
+                let endpoint: RedisEndpoint = self.endpoint.clone();
+                tokio::task::spawn_blocking(move || endpoint.resolve())
+                    .await
+                    .map_err(|err| err.to_string())??
+            }
+        };
+        let mut connection: AsyncRedisConnection = AsyncRedisConnection {
+            connection: self.endpoint.open_async(&server).await?,
+            read_timeout: self.endpoint.options.read_timeout,
+            broken: Arc::new(AtomicBool::new(false)),
+        };
+        if let RedisTarget::Sentinel { .. } = self.endpoint.target {
+            let role: Vec<Value> = redis::cmd("ROLE")
+                .query_async::<_, Vec<Value>>(&mut connection)
+                .await
+                .map_err(|err| err.to_string())?;
+            check_role(&role, &server)?;
+        }
+        Ok(connection)
+    }
+}
+
+fn reach(host: &str, port: u16, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last_err: io::Error = io::Error::new(io::ErrorKind::NotFound, "no address");
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// The master serving `slot` in a `CLUSTER SLOTS` reply, whose entries are
+/// `[start, end, [host, port, ..], replicas..]`.
+fn get_slot_owner(ranges: &[Value], slot: u16) -> Result<Option<(String, u16)>, String> {
+    for range in ranges {
+        let range: Vec<Value> = redis::from_redis_value(range).map_err(|err| err.to_string())?;
+        let (start, end, master): (u16, u16, &Value) = match range.as_slice() {
+            [start, end, master, ..] => (
+                redis::from_redis_value(start).map_err(|err| err.to_string())?,
+                redis::from_redis_value(end).map_err(|err| err.to_string())?,
+                master,
+            ),
+            _ => return Err("Unexpected CLUSTER SLOTS reply".to_string()),
+        };
+        if (start..=end).contains(&slot) {
+            let master: Vec<Value> =
+                redis::from_redis_value(master).map_err(|err| err.to_string())?;
+            return match master.as_slice() {
+                [host, port, ..] => Ok(Some((
+                    redis::from_redis_value(host).map_err(|err| err.to_string())?,
+                    redis::from_redis_value(port).map_err(|err| err.to_string())?,
+                ))),
+                _ => Err("Unexpected CLUSTER SLOTS reply".to_string()),
+            };
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_key_slot() {
+        assert_eq!(0x31C3, crc16(b"123456789"));
+        assert_eq!(12182, get_key_slot("foo"));
+        assert_eq!(
+            get_key_slot("user1000"),
+            get_key_slot("{user1000}.following")
+        );
+        assert_eq!(get_key_slot("bar"), get_key_slot("foo{bar}{zap}"));
+        assert_eq!(
+            get_key_slot("foo{}{bar}"),
+            crc16(b"foo{}{bar}") % CLUSTER_SLOTS
+        );
+        assert_eq!(get_key_slot("{bar"), get_key_slot("foo{{bar}}zap"));
+        assert_eq!(
+            get_key_slot("contacts"),
+            get_key_slot("{contacts}_trash@work:Bogdan")
+        );
+    }
+
+    #[test]
+    fn test_get_valid_key_prefix() {
+        for prefix in ["contacts", "team-a", "Team.B", "1", &"a".repeat(64)] {
+            assert_eq!(Ok(prefix.to_string()), get_valid_key_prefix(prefix));
+        }
+        for prefix in [
+            "",
+            "-contacts",
+            "team_a",
+            "team:a",
+            "team@a",
+            "{team}",
+            "team*",
+            "team a",
+            &"a".repeat(65),
+        ] {
+            assert!(get_valid_key_prefix(prefix).is_err());
+        }
+    }
+}
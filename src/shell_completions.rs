@@ -0,0 +1,199 @@
+use crate::completion::get_subcommands_with_name;
+use clap::Command;
+use clap_complete::Shell;
+use std::io::Write;
+
+/// Hidden subcommand printing one contact name per line, called back by the
+/// completion scripts.
+pub const COMPLETE_NAMES_COMMAND: &str = "__complete-names";
+
+/// Writes the clap generated completion script for the shell, extended so that
+/// contact names are completed dynamically.
+pub fn write_completions(
+    shell: Shell,
+    cmd: &mut Command,
+    names_command: &str,
+    out: &mut dyn Write,
+) -> Result<(), String> {
+    let bin_name: String = cmd.get_name().to_string();
+    let subcommands: Vec<String> = get_subcommands_with_name(cmd);
+
+    let mut buf: Vec<u8> = Vec::new();
+    clap_complete::generate(shell, cmd, bin_name.clone(), &mut buf);
+    let script: String = String::from_utf8(buf).map_err(|e| e.to_string())?;
+
+    let script: String = match shell {
+        Shell::Bash => extend_bash(script, &bin_name, names_command, &subcommands),
+        Shell::Zsh => extend_zsh(script, &bin_name, names_command),
+        Shell::Fish => extend_fish(script, &bin_name, names_command, &subcommands),
+        Shell::PowerShell => extend_powershell(script, &bin_name, names_command, &subcommands),
+        Shell::Elvish => extend_elvish(script, &bin_name, names_command, &subcommands),
+        _ => script,
+    };
+
+    out.write_all(script.as_bytes()).map_err(|e| e.to_string())
+}
+
+fn extend_bash(
+    script: String,
+    bin_name: &str,
+    names_command: &str,
+    subcommands: &[String],
+) -> String {
+    let generated_function: String = match script
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("complete -F "))
+        .and_then(|rest| rest.split_whitespace().next())
+    {
+        Some(function) => function.to_string(),
+        None => return script,
+    };
+    let function: String = format!("_{}_with_names", bin_name.replace('-', "_"));
+
+    format!(
+        r#"{script}
+{function}() {{
+    if [[ ${{COMP_CWORD}} -eq 2 && ${{COMP_WORDS[2]}} != -* ]]; then
+        case "${{COMP_WORDS[1]}}" in
+            {cases})
+                local IFS=$'\n'
+                COMPREPLY=( $(compgen -W "$({bin_name} {names_command} 2>/dev/null)" -- "${{COMP_WORDS[2]}}") )
+                return 0
+                ;;
+        esac
+    fi
+    {generated_function} "$@"
+}}
+
+complete -F {function} -o bashdefault -o default {bin_name}
+"#,
+        cases = subcommands.join("|"),
+    )
+}
+
+fn extend_zsh(script: String, bin_name: &str, names_command: &str) -> String {
+    let function: String = format!("_{}_names", bin_name.replace('-', "_"));
+    let script: String = script.replace(
+        ":NAME -- The name of the contact:_default",
+        &format!(":NAME -- The name of the contact:{function}"),
+    );
+    let (first_line, rest) = script.split_once('\n').unwrap_or((&script, ""));
+
+    format!(
+        r#"{first_line}
+
+{function}() {{
+    local -a names
+    names=("${{(@f)$({bin_name} {names_command} 2>/dev/null)}}")
+    compadd -a names
+}}
+{rest}"#
+    )
+}
+
+fn extend_fish(
+    script: String,
+    bin_name: &str,
+    names_command: &str,
+    subcommands: &[String],
+) -> String {
+    format!(
+        r#"{script}complete -c {bin_name} -n "__fish_seen_subcommand_from {}" -f -a "({bin_name} {names_command})"
+"#,
+        subcommands.join(" ")
+    )
+}
+
+/// Adds the contact names to the branch of each subcommand taking a NAME. A
+/// name with spaces or quotes is completed in single quotes, so the final
+/// filter also matches on the unquoted name.
+fn extend_powershell(
+    script: String,
+    bin_name: &str,
+    names_command: &str,
+    subcommands: &[String],
+) -> String {
+    let names: String = format!(
+        r#"            & '{bin_name}' {names_command} 2>$null | ForEach-Object {{
+                $text = if ($_ -match '[\s''`"$;,(){{}}@&|<>#]') {{ "'" + ($_ -replace "'", "''") + "'" }} else {{ $_ }}
+                [CompletionResult]::new($text, $_, [CompletionResultType]::ParameterValue, $_)
+            }}
+"#
+    );
+    let script: String = script.replace(
+        "$_.CompletionText -like \"$wordToComplete*\"",
+        "$_.CompletionText -like \"$wordToComplete*\" -or $_.ListItemText -like \"$wordToComplete*\"",
+    );
+
+    subcommands.iter().fold(script, |script, subcommand| {
+        let branch: String = format!("        '{bin_name};{subcommand}' {{\n");
+        script.replace(&branch, &format!("{branch}{names}"))
+    })
+}
+
+/// Adds the contact names to the branch of each subcommand taking a NAME,
+/// ignoring a failing store so that the other candidates are still offered.
+fn extend_elvish(
+    script: String,
+    bin_name: &str,
+    names_command: &str,
+    subcommands: &[String],
+) -> String {
+    let names: String = format!(
+        r#"            try {{
+                {bin_name} {names_command} 2>/dev/null | from-lines | each {{|name| edit:complex-candidate $name }}
+            }} catch e {{ }}
+"#
+    );
+
+    subcommands.iter().fold(script, |script, subcommand| {
+        let branch: String = format!("        &'{bin_name};{subcommand}'= {{\n");
+        script.replace(&branch, &format!("{branch}{names}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(shell: Shell) -> String {
+        let mut cmd: Command = Command::new("contacts-cli")
+            .subcommand(Command::new("view").arg(clap::arg!(<NAME> "The name of the contact")))
+            .subcommand(Command::new("quit"));
+        let mut buf: Vec<u8> = Vec::new();
+        write_completions(shell, &mut cmd, COMPLETE_NAMES_COMMAND, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_completions_call_back_for_contact_names() {
+        let bash: String = generate(Shell::Bash);
+        assert!(bash.contains("            view)\n"));
+        assert!(bash.contains("contacts-cli __complete-names"));
+        assert!(bash.ends_with(
+            "complete -F _contacts_cli_with_names -o bashdefault -o default contacts-cli\n"
+        ));
+
+        let zsh: String = generate(Shell::Zsh);
+        assert!(zsh.starts_with("#compdef contacts-cli\n"));
+        assert!(zsh.contains(":NAME -- The name of the contact:_contacts_cli_names"));
+
+        let fish: String = generate(Shell::Fish);
+        assert!(fish.contains(
+            "-n \"__fish_seen_subcommand_from view\" -f -a \"(contacts-cli __complete-names)\""
+        ));
+
+        let powershell: String = generate(Shell::PowerShell);
+        assert!(powershell.contains(
+            "        'contacts-cli;view' {\n            & 'contacts-cli' __complete-names 2>$null | ForEach-Object {\n"
+        ));
+        assert!(!powershell.contains("'contacts-cli;quit' {\n            & "));
+        assert!(powershell.contains("-or $_.ListItemText -like \"$wordToComplete*\""));
+
+        let elvish: String = generate(Shell::Elvish);
+        assert!(elvish.contains(
+            "        &'contacts-cli;view'= {\n            try {\n                contacts-cli __complete-names 2>/dev/null | from-lines"
+        ));
+        assert!(!elvish.contains("&'contacts-cli;quit'= {\n            try"));
+    }
+}
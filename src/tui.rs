@@ -0,0 +1,615 @@
+use contacts_app::models::{contact::Contact, page::Page, sort_by::SortBy};
+use contacts_app::repositories::contacts::{
+    get_valid_email, get_valid_name, get_valid_phone_no, ContactsRepository,
+};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+const PAGE_JUMP: usize = 10;
+/// Contacts are read this many at a time, and kept loaded this far below the
+/// selection.
+const PAGE_SIZE: usize = 100;
+const FIELD_NAME: usize = 0;
+const FIELD_PHONE_NO: usize = 1;
+const FIELD_EMAIL: usize = 2;
+
+struct FormField {
+    label: &'static str,
+    value: String,
+    error: Option<String>,
+}
+
+/// Inline form used both to edit the selected contact and to add a new one.
+struct Form {
+    original: Option<Contact>,
+    fields: [FormField; 3],
+    focused: usize,
+}
+
+enum Mode {
+    Browse,
+    Search,
+    Edit(Box<Form>),
+}
+
+struct App {
+    /// The contacts read so far, in name order.
+    all_contacts: Vec<Contact>,
+    /// Where the next unread page starts, `None` once every page is read.
+    next_cursor: Option<String>,
+    contacts: Vec<Contact>,
+    filter: String,
+    list_state: ListState,
+    mode: Mode,
+    status: Option<String>,
+    quit: bool,
+}
+
+/// Runs the full-screen terminal UI on top of the repository until the user quits.
+pub fn run(contacts_service: &mut dyn ContactsRepository) -> Result<(), String> {
+    let mut terminal: DefaultTerminal = ratatui::try_init().map_err(|e| e.to_string())?;
+    let result: Result<(), String> = run_app(&mut terminal, contacts_service);
+    ratatui::try_restore().map_err(|e| e.to_string())?;
+    result
+}
+
+fn run_app(
+    terminal: &mut DefaultTerminal,
+    contacts_service: &mut dyn ContactsRepository,
+) -> Result<(), String> {
+    let mut app: App = App::new();
+    app.reload(contacts_service)?;
+
+    while !app.quit {
+        terminal
+            .draw(|frame| draw(frame, &mut app))
+            .map_err(|e| e.to_string())?;
+
+        if let Event::Key(key) = event::read().map_err(|e| e.to_string())? {
+            if key.kind == KeyEventKind::Press {
+                app.handle_key(key, contacts_service)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+impl Form {
+    fn new(original: Option<Contact>) -> Self {
+        let (name, phone_no, email) = match &original {
+            Some(contact) => (
+                contact.name.clone(),
+                contact.phone_no.to_string(),
+                contact.email.clone(),
+            ),
+            None => (String::new(), String::new(), String::new()),
+        };
+        let field = |label: &'static str, value: String| FormField {
+            label,
+            value,
+            error: None,
+        };
+
+        Form {
+            // The name is the key of a contact, so it can only be typed in for new ones.
+            focused: if original.is_some() {
+                FIELD_PHONE_NO
+            } else {
+                FIELD_NAME
+            },
+            original,
+            fields: [
+                field("name", name),
+                field("phone_no", phone_no),
+                field("email", email),
+            ],
+        }
+    }
+
+    fn is_editable(&self, index: usize) -> bool {
+        index != FIELD_NAME || self.original.is_none()
+    }
+
+    fn focus_next(&mut self, step: isize) {
+        let len: isize = self.fields.len() as isize;
+        let mut index: isize = self.focused as isize;
+        loop {
+            index = (index + step).rem_euclid(len);
+            if self.is_editable(index as usize) {
+                break;
+            }
+        }
+        self.focused = index as usize;
+    }
+
+    /// Runs the repository validators on every field, keeping their messages
+    /// next to the fields, and for a new contact checks that its name is not
+    /// taken, since adding would overwrite that contact. Returns whether the
+    /// form is valid.
+    fn validate(&mut self, contacts_service: &dyn ContactsRepository) -> bool {
+        let name: &str = &self.fields[FIELD_NAME].value;
+        self.fields[FIELD_NAME].error = match (get_valid_name(name), &self.original) {
+            (Err(err), _) => Some(err),
+            (Ok(_), Some(_)) => None,
+            (Ok(_), None) => match contacts_service.get(name) {
+                Ok(Some(_)) => Some(format!("a contact with name {name} already exists")),
+                Ok(None) => None,
+                Err(err) => Some(err),
+            },
+        };
+        self.fields[FIELD_PHONE_NO].error =
+            get_valid_phone_no(&self.fields[FIELD_PHONE_NO].value).err();
+        self.fields[FIELD_EMAIL].error = get_valid_email(&self.fields[FIELD_EMAIL].value).err();
+
+        self.fields.iter().all(|field| field.error.is_none())
+    }
+
+    fn save(&self, contacts_service: &mut dyn ContactsRepository) -> Result<(), String> {
+        let name: String = self.fields[FIELD_NAME].value.clone();
+        let phone_no: String = self.fields[FIELD_PHONE_NO].value.clone();
+        let email: String = self.fields[FIELD_EMAIL].value.clone();
+
+        match &self.original {
+            None => contacts_service.add(name, phone_no, email),
+            Some(original) => {
+                if original.phone_no.to_string() != phone_no {
+                    contacts_service.update_phone_no(&name, phone_no)?;
+                }
+                if original.email != email {
+                    contacts_service.update_email(&name, email)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl App {
+    fn new() -> Self {
+        App {
+            all_contacts: Vec::new(),
+            next_cursor: None,
+            contacts: Vec::new(),
+            filter: String::new(),
+            list_state: ListState::default(),
+            mode: Mode::Browse,
+            status: None,
+            quit: false,
+        }
+    }
+
+    /// Reads the contacts again from the first page, as far as the selection needs.
+    fn reload(&mut self, contacts_service: &dyn ContactsRepository) -> Result<(), String> {
+        let page: Page = contacts_service.list_page(None, PAGE_SIZE, SortBy::Name, false)?;
+        self.all_contacts = page.contacts;
+        self.next_cursor = page.next_cursor;
+        self.apply_filter();
+        self.load_ahead(contacts_service)
+    }
+
+    /// Reads pages until a page of matching contacts is loaded below the
+    /// selection, then keeps the selection within the matching contacts.
+    fn load_ahead(&mut self, contacts_service: &dyn ContactsRepository) -> Result<(), String> {
+        let needed: usize = self.list_state.selected().unwrap_or(0) + PAGE_SIZE;
+        self.load_until(contacts_service, needed)
+    }
+
+    fn load_until(
+        &mut self,
+        contacts_service: &dyn ContactsRepository,
+        needed: usize,
+    ) -> Result<(), String> {
+        while self.contacts.len() < needed {
+            let cursor: String = match self.next_cursor.take() {
+                Some(cursor) => cursor,
+                None => break,
+            };
+            let page: Page =
+                contacts_service.list_page(Some(&cursor), PAGE_SIZE, SortBy::Name, false)?;
+            self.all_contacts.extend(page.contacts);
+            self.next_cursor = page.next_cursor;
+            self.apply_filter();
+        }
+
+        let selected: Option<usize> = match self.list_state.selected() {
+            _ if self.contacts.is_empty() => None,
+            Some(index) => Some(index.min(self.contacts.len() - 1)),
+            None => Some(0),
+        };
+        self.list_state.select(selected);
+        Ok(())
+    }
+
+    fn apply_filter(&mut self) {
+        let filter: String = self.filter.to_lowercase();
+        self.contacts = self
+            .all_contacts
+            .iter()
+            .filter(|contact| {
+                filter.is_empty()
+                    || contact.name.to_lowercase().contains(&filter)
+                    || contact.email.to_lowercase().contains(&filter)
+                    || contact.phone_no.to_string().contains(&filter)
+            })
+            .cloned()
+            .collect();
+    }
+
+    fn selected_contact(&self) -> Option<&Contact> {
+        self.list_state
+            .selected()
+            .and_then(|index| self.contacts.get(index))
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        if self.contacts.is_empty() {
+            return;
+        }
+        let last: isize = self.contacts.len() as isize - 1;
+        let current: isize = self.list_state.selected().unwrap_or(0) as isize;
+        self.list_state
+            .select(Some((current + delta).clamp(0, last) as usize));
+    }
+
+    fn handle_key(
+        &mut self,
+        key: KeyEvent,
+        contacts_service: &mut dyn ContactsRepository,
+    ) -> Result<(), String> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return Ok(());
+        }
+
+        match &mut self.mode {
+            Mode::Browse => self.handle_browse_key(key, contacts_service)?,
+            Mode::Search => match key.code {
+                KeyCode::Esc => {
+                    self.filter.clear();
+                    self.apply_filter();
+                    self.mode = Mode::Browse;
+                }
+                KeyCode::Enter | KeyCode::Down | KeyCode::Up => self.mode = Mode::Browse,
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                    self.apply_filter();
+                }
+                KeyCode::Char(c) => {
+                    self.filter.push(c);
+                    self.apply_filter();
+                }
+                _ => {}
+            },
+            Mode::Edit(form) => match key.code {
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Tab | KeyCode::Down => form.focus_next(1),
+                KeyCode::BackTab | KeyCode::Up => form.focus_next(-1),
+                KeyCode::Backspace => {
+                    form.fields[form.focused].value.pop();
+                }
+                KeyCode::Char(c) => form.fields[form.focused].value.push(c),
+                KeyCode::Enter => {
+                    if !form.validate(contacts_service) {
+                        return Ok(());
+                    }
+                    match form.save(contacts_service) {
+                        Ok(()) => {
+                            self.status = Some("Contact saved succesfully".to_string());
+                            self.mode = Mode::Browse;
+                            self.reload(contacts_service)?;
+                        }
+                        Err(err) => self.status = Some(format!("Err: {err}")),
+                    }
+                }
+                _ => {}
+            },
+        }
+
+        self.load_ahead(contacts_service)
+    }
+
+    fn handle_browse_key(
+        &mut self,
+        key: KeyEvent,
+        contacts_service: &mut dyn ContactsRepository,
+    ) -> Result<(), String> {
+        self.status = None;
+        match key.code {
+            KeyCode::Char('q') => self.quit = true,
+            KeyCode::Down | KeyCode::Char('j') => self.move_selection(1),
+            KeyCode::Up | KeyCode::Char('k') => self.move_selection(-1),
+            KeyCode::PageDown => self.move_selection(PAGE_JUMP as isize),
+            KeyCode::PageUp => self.move_selection(-(PAGE_JUMP as isize)),
+            KeyCode::Home | KeyCode::Char('g') => self.move_selection(isize::MIN / 2),
+            KeyCode::End | KeyCode::Char('G') => {
+                self.load_until(contacts_service, usize::MAX)?;
+                self.move_selection(isize::MAX / 2);
+            }
+            KeyCode::Char('/') => self.mode = Mode::Search,
+            KeyCode::Char('a') => self.mode = Mode::Edit(Box::new(Form::new(None))),
+            KeyCode::Char('e') | KeyCode::Enter => {
+                if let Some(contact) = self.selected_contact() {
+                    self.mode = Mode::Edit(Box::new(Form::new(Some(contact.clone()))));
+                }
+            }
+            KeyCode::Char('d') => {
+                if let Some(name) = self.selected_contact().map(|c| c.name.clone()) {
+                    self.status = Some(match contacts_service.delete(&name) {
+                        Ok(()) => format!("{name} moved to the trash"),
+                        Err(err) => format!("Err: {err}"),
+                    });
+                    self.reload(contacts_service)?;
+                }
+            }
+            KeyCode::Char('r') => self.reload(contacts_service)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [search_area, main_area, status_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(0),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [list_area, detail_area] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .areas(main_area);
+
+    let searching: bool = matches!(app.mode, Mode::Search);
+    let search: Paragraph = Paragraph::new(app.filter.as_str()).block(
+        Block::bordered()
+            .title(" Search (/) ")
+            .border_style(focus_style(searching)),
+    );
+    frame.render_widget(search, search_area);
+    if searching {
+        frame.set_cursor_position((
+            search_area.x + 1 + app.filter.chars().count() as u16,
+            search_area.y + 1,
+        ));
+    }
+
+    let items: Vec<ListItem> = app
+        .contacts
+        .iter()
+        .map(|contact| ListItem::new(contact.name.as_str()))
+        .collect();
+    let list: List = List::new(items)
+        .block(
+            Block::bordered()
+                .title(format!(
+                    " Contacts ({}{}) ",
+                    app.contacts.len(),
+                    if app.next_cursor.is_some() { "+" } else { "" }
+                ))
+                .border_style(focus_style(matches!(app.mode, Mode::Browse))),
+        )
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, list_area, &mut app.list_state);
+
+    match &app.mode {
+        Mode::Edit(form) => draw_form(frame, form, detail_area),
+        _ => draw_detail(frame, app.selected_contact(), detail_area),
+    }
+
+    let help: String = match &app.status {
+        Some(status) => status.clone(),
+        None => match app.mode {
+            Mode::Browse => "↑/↓ move  / search  e edit  a add  d delete  r reload  q quit",
+            Mode::Search => "type to filter  enter done  esc clear",
+            Mode::Edit(_) => "tab next field  enter save  esc cancel",
+        }
+        .to_string(),
+    };
+    frame.render_widget(Paragraph::new(help), status_area);
+}
+
+fn draw_detail(frame: &mut Frame, contact: Option<&Contact>, area: Rect) {
+    let lines: Vec<Line> = match contact {
+        Some(contact) => vec![
+            detail_line("name", contact.name.clone()),
+            detail_line("phone_no", contact.phone_no.to_string()),
+            detail_line("email", contact.email.clone()),
+        ],
+        None => vec![Line::from("No contact selected")],
+    };
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title(" Details ")),
+        area,
+    );
+}
+
+fn draw_form(frame: &mut Frame, form: &Form, area: Rect) {
+    let title: &str = match form.original {
+        Some(_) => " Edit contact ",
+        None => " New contact ",
+    };
+    let block: Block = Block::bordered()
+        .title(title)
+        .border_style(focus_style(true));
+    let inner: Rect = block.inner(area);
+    frame.render_widget(block, area);
+
+    let rows = Layout::vertical(form.fields.iter().map(|_| Constraint::Length(2))).split(inner);
+    for (index, (field, row)) in form.fields.iter().zip(rows.iter()).enumerate() {
+        let mut spans: Vec<Span> = vec![
+            Span::styled(
+                format!("{:>9}: ", field.label),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::styled(
+                field.value.as_str(),
+                match form.is_editable(index) {
+                    true => Style::default(),
+                    false => Style::default().fg(Color::DarkGray),
+                },
+            ),
+        ];
+        if let Some(error) = &field.error {
+            spans.push(Span::styled(
+                format!("  {error}"),
+                Style::default().fg(Color::Red),
+            ));
+        }
+        frame.render_widget(Paragraph::new(Line::from(spans)), *row);
+
+        if index == form.focused {
+            frame.set_cursor_position((row.x + 11 + field.value.chars().count() as u16, row.y));
+        }
+    }
+}
+
+fn detail_line(label: &'static str, value: String) -> Line<'static> {
+    Line::from(vec![
+        Span::styled(
+            format!("{label:>9}: "),
+            Style::default().add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(value),
+    ])
+}
+
+fn focus_style(focused: bool) -> Style {
+    match focused {
+        true => Style::default().fg(Color::Cyan),
+        false => Style::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use contacts_app::repositories::inmemory_contacts::InMemoryContactsRepository;
+
+    fn press(app: &mut App, code: KeyCode, contacts_service: &mut dyn ContactsRepository) {
+        app.handle_key(KeyEvent::from(code), contacts_service)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_tui_search_and_edit() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        for name in ["Bogdan", "Maria", "Mihai"] {
+            contacts_service
+                .add(
+                    name.to_string(),
+                    "491234567890".to_string(),
+                    format!("{}@mail.com", name.to_lowercase()),
+                )
+                .unwrap();
+        }
+        let mut app: App = App::new();
+        app.reload(&contacts_service).unwrap();
+        assert_eq!(3, app.contacts.len());
+
+        for code in [KeyCode::Char('/'), KeyCode::Char('m'), KeyCode::Char('i')] {
+            press(&mut app, code, &mut contacts_service);
+        }
+        assert_eq!(
+            vec!["Mihai"],
+            app.contacts
+                .iter()
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>()
+        );
+
+        press(&mut app, KeyCode::Enter, &mut contacts_service);
+        press(&mut app, KeyCode::Char('e'), &mut contacts_service);
+        press(&mut app, KeyCode::Tab, &mut contacts_service);
+        for _ in 0.."mihai@mail.com".len() {
+            press(&mut app, KeyCode::Backspace, &mut contacts_service);
+        }
+        press(&mut app, KeyCode::Enter, &mut contacts_service);
+        match &app.mode {
+            Mode::Edit(form) => assert!(form.fields[FIELD_EMAIL].error.is_some()),
+            _ => panic!("the form should stay open on validation errors"),
+        }
+
+        for c in "new@mail.com".chars() {
+            press(&mut app, KeyCode::Char(c), &mut contacts_service);
+        }
+        press(&mut app, KeyCode::Enter, &mut contacts_service);
+        assert!(matches!(app.mode, Mode::Browse));
+        assert_eq!(
+            "new@mail.com",
+            contacts_service.get("Mihai").unwrap().unwrap().email
+        );
+
+        // Adding a contact with a name already in use would overwrite it.
+        press(&mut app, KeyCode::Char('a'), &mut contacts_service);
+        for c in "Bogdan\t491111111111\tbogdan@work.com".chars() {
+            let code: KeyCode = match c {
+                '\t' => KeyCode::Tab,
+                c => KeyCode::Char(c),
+            };
+            press(&mut app, code, &mut contacts_service);
+        }
+        press(&mut app, KeyCode::Enter, &mut contacts_service);
+        match &app.mode {
+            Mode::Edit(form) => assert_eq!(
+                Some("a contact with name Bogdan already exists"),
+                form.fields[FIELD_NAME].error.as_deref()
+            ),
+            _ => panic!("the form should stay open for a name in use"),
+        }
+        assert_eq!(
+            "bogdan@mail.com",
+            contacts_service.get("Bogdan").unwrap().unwrap().email
+        );
+    }
+
+    #[test]
+    fn test_tui_pages_lazily() {
+        let mut contacts_service: InMemoryContactsRepository = InMemoryContactsRepository::new();
+        for i in 0..250 {
+            contacts_service
+                .add(
+                    format!("Contact{i:03}"),
+                    "491234567890".to_string(),
+                    format!("contact{i}@mail.com"),
+                )
+                .unwrap();
+        }
+        let mut app: App = App::new();
+        app.reload(&contacts_service).unwrap();
+        assert_eq!(PAGE_SIZE, app.all_contacts.len());
+        assert!(app.next_cursor.is_some());
+
+        press(&mut app, KeyCode::Down, &mut contacts_service);
+        assert_eq!(2 * PAGE_SIZE, app.all_contacts.len());
+
+        for code in [KeyCode::Char('/'), KeyCode::Char('2'), KeyCode::Char('4')] {
+            press(&mut app, code, &mut contacts_service);
+        }
+        assert_eq!(250, app.all_contacts.len());
+        assert_eq!(
+            vec!["Contact024", "Contact124", "Contact224", "Contact240"],
+            app.contacts
+                .iter()
+                .take(4)
+                .map(|c| c.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        press(&mut app, KeyCode::Esc, &mut contacts_service);
+
+        let mut app: App = App::new();
+        app.reload(&contacts_service).unwrap();
+        press(&mut app, KeyCode::End, &mut contacts_service);
+        assert!(app.next_cursor.is_none());
+        assert_eq!("Contact249", app.selected_contact().unwrap().name);
+
+        press(&mut app, KeyCode::Esc, &mut contacts_service);
+        assert!(!app.quit);
+        press(&mut app, KeyCode::Char('q'), &mut contacts_service);
+        assert!(app.quit);
+    }
+}